@@ -0,0 +1,28 @@
+#![no_std]
+#![no_main]
+
+use ram::io::Audio;
+use ram::{println, tm::halt};
+
+const FREQ: u32 = 8000;
+/// A above middle C
+const PITCH: u32 = 440;
+const SECONDS: u32 = 2;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    Audio::init(FREQ, 1, 16);
+    println!("playing {} Hz for {} seconds", PITCH, SECONDS);
+    // a square wave, a period at a time
+    let period = (FREQ / PITCH) as usize;
+    let mut samples = [0_u8; 2 * 64];
+    for (i, sample) in samples.chunks_exact_mut(2).take(period).enumerate() {
+        let level: i16 = if i < period / 2 { 8000 } else { -8000 };
+        sample.copy_from_slice(&level.to_le_bytes());
+    }
+    for _ in 0..FREQ * SECONDS / period as u32 {
+        Audio::play(&samples[..2 * period]);
+    }
+    while Audio::queued() > 0 {}
+    halt(0);
+}
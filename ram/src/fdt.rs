@@ -0,0 +1,264 @@
+//! ------------------ FDT: Flattened Device Tree --------------------
+//! - Fdt::from_ptr  the tree the machine passes in a1 at boot
+//! - find           node by path, such as `/soc/serial`
+//! - compatible     first node compatible with a string
+//! - memory         RAM regions
+//! - Node: name, properties and `reg` of a node
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+/// deepest nesting of nodes followed
+const MAX_DEPTH: usize = 16;
+
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let word = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+}
+
+fn align(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// the NUL terminated string at `offset`
+fn cstr(bytes: &[u8], offset: usize) -> Option<&str> {
+    let bytes = bytes.get(offset..)?;
+    let end = bytes.iter().position(|byte| *byte == 0)?;
+    core::str::from_utf8(&bytes[..end]).ok()
+}
+
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    structure: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> Fdt<'a> {
+    /// Check the header of `data` and find its blocks.
+    pub fn new(data: &'a [u8]) -> Option<Fdt<'a>> {
+        if be32(data, 0)? != FDT_MAGIC || be32(data, 24)? < 16 {
+            return None;
+        }
+        let total = be32(data, 4)? as usize;
+        let data = data.get(..total)?;
+        let structure = be32(data, 8)? as usize;
+        let strings = be32(data, 12)? as usize;
+        let strings_size = be32(data, 32)? as usize;
+        let structure_size = be32(data, 36)? as usize;
+        Some(Fdt {
+            structure: data.get(structure..structure + structure_size)?,
+            strings: data.get(strings..strings + strings_size)?,
+        })
+    }
+
+    /// The tree at `addr`, none if there is none.
+    ///
+    /// # Safety
+    /// `addr` must be 0 or readable for the size in the header it points to.
+    pub unsafe fn from_ptr(addr: usize) -> Option<Fdt<'static>> {
+        if addr == 0 || !addr.is_multiple_of(4) {
+            return None;
+        }
+        let header = core::slice::from_raw_parts(addr as *const u8, 8);
+        if be32(header, 0)? != FDT_MAGIC {
+            return None;
+        }
+        let total = be32(header, 4)? as usize;
+        Fdt::new(core::slice::from_raw_parts(addr as *const u8, total))
+    }
+
+    /// All nodes, depth first from the root.
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes {
+            fdt: *self,
+            offset: 0,
+            depth: 0,
+            cells: [(2, 1); MAX_DEPTH],
+        }
+    }
+
+    /// The node at `path`, unit addresses being optional.
+    pub fn find(&self, path: &str) -> Option<Node<'a>> {
+        let mut names = path.split('/').filter(|name| !name.is_empty());
+        let mut name = names.next();
+        // depth of the last node of the path found
+        let mut found = 0;
+        for node in self.nodes() {
+            if node.depth == 0 {
+                if name.is_none() {
+                    return Some(node);
+                }
+                continue;
+            }
+            if node.depth <= found {
+                return None;
+            }
+            if node.depth == found + 1 && name.is_some_and(|name| node.is(name)) {
+                found += 1;
+                name = names.next();
+                if name.is_none() {
+                    return Some(node);
+                }
+            }
+        }
+        None
+    }
+
+    /// The first node compatible with `compatible`.
+    pub fn compatible(&self, compatible: &str) -> Option<Node<'a>> {
+        self.nodes().find(|node| node.is_compatible(compatible))
+    }
+
+    /// Base and size of the RAM regions.
+    pub fn memory(&self) -> impl Iterator<Item = (usize, usize)> + 'a {
+        self.nodes()
+            .filter(|node| node.string("device_type") == Some("memory"))
+            .flat_map(|node| node.reg())
+    }
+}
+
+pub struct Nodes<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+    depth: usize,
+    /// #address-cells and #size-cells of the nodes being walked
+    cells: [(u32, u32); MAX_DEPTH],
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        let structure = self.fdt.structure;
+        loop {
+            match be32(structure, self.offset)? {
+                FDT_BEGIN_NODE => {
+                    let name = cstr(structure, self.offset + 4)?;
+                    if self.depth + 1 >= MAX_DEPTH {
+                        return None;
+                    }
+                    let (address_cells, size_cells) = self.cells[self.depth];
+                    self.offset = align(self.offset + 4 + name.len() + 1);
+                    let node = Node {
+                        fdt: self.fdt,
+                        name,
+                        depth: self.depth,
+                        offset: self.offset,
+                        address_cells,
+                        size_cells,
+                    };
+                    self.depth += 1;
+                    self.cells[self.depth] = (2, 1);
+                    return Some(node);
+                }
+                FDT_PROP => {
+                    let len = be32(structure, self.offset + 4)? as usize;
+                    let name = cstr(self.fdt.strings, be32(structure, self.offset + 8)? as usize)?;
+                    let value = be32(structure, self.offset + 12);
+                    // properties come before subnodes, which use these
+                    match (name, value) {
+                        ("#address-cells", Some(cells)) => self.cells[self.depth].0 = cells,
+                        ("#size-cells", Some(cells)) => self.cells[self.depth].1 = cells,
+                        _ => (),
+                    }
+                    self.offset = align(self.offset + 12 + len);
+                }
+                FDT_END_NODE => {
+                    self.depth = self.depth.checked_sub(1)?;
+                    self.offset += 4;
+                }
+                FDT_NOP => self.offset += 4,
+                // FDT_END
+                _ => return None,
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    /// name with its unit address, such as `serial@a00003f8`
+    pub name: &'a str,
+    /// 0 for the root
+    pub depth: usize,
+    /// offset of the first property in the structure block
+    offset: usize,
+    /// cells of addresses and sizes in `reg`, set by the parent
+    address_cells: u32,
+    size_cells: u32,
+}
+
+impl<'a> Node<'a> {
+    /// whether the node is `name`, with or without its unit address
+    pub fn is(&self, name: &str) -> bool {
+        self.name == name || self.name.split('@').next() == Some(name)
+    }
+
+    /// Names and values of the properties.
+    pub fn properties(&self) -> impl Iterator<Item = (&'a str, &'a [u8])> + 'a {
+        let fdt = self.fdt;
+        let mut offset = self.offset;
+        core::iter::from_fn(move || loop {
+            match be32(fdt.structure, offset)? {
+                FDT_PROP => {
+                    let len = be32(fdt.structure, offset + 4)? as usize;
+                    let name = cstr(fdt.strings, be32(fdt.structure, offset + 8)? as usize)?;
+                    let value = fdt.structure.get(offset + 12..offset + 12 + len)?;
+                    offset = align(offset + 12 + len);
+                    return Some((name, value));
+                }
+                FDT_NOP => offset += 4,
+                _ => return None,
+            }
+        })
+    }
+
+    pub fn property(&self, name: &str) -> Option<&'a [u8]> {
+        self.properties()
+            .find(|(property, _)| *property == name)
+            .map(|(_, value)| value)
+    }
+
+    /// The first string of a property.
+    pub fn string(&self, name: &str) -> Option<&'a str> {
+        cstr(self.property(name)?, 0)
+    }
+
+    pub fn u32(&self, name: &str) -> Option<u32> {
+        be32(self.property(name)?, 0)
+    }
+
+    /// whether `compatible` is one of the strings of the compatible property
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.property("compatible").is_some_and(|strings| {
+            strings
+                .split(|byte| *byte == 0)
+                .any(|string| string == compatible.as_bytes())
+        })
+    }
+
+    /// Address and size of the regions of `reg`.
+    pub fn reg(&self) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let (address_cells, size_cells) = (self.address_cells as usize, self.size_cells as usize);
+        let entry = (address_cells + size_cells) * 4;
+        let value = match entry {
+            0 => &[],
+            _ => self.property("reg").unwrap_or(&[]),
+        };
+        // a number of up to two cells, truncated to usize
+        let number = |bytes: &[u8], cells: usize| {
+            (0..cells).fold(0_u64, |number, i| {
+                number << 32 | be32(bytes, i * 4).unwrap_or(0) as u64
+            }) as usize
+        };
+        value.chunks_exact(entry.max(1)).map(move |bytes| {
+            (
+                number(bytes, address_cells),
+                number(&bytes[address_cells * 4..], size_cells),
+            )
+        })
+    }
+}
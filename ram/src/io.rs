@@ -3,10 +3,55 @@
 //! - Timer
 //! - KeyBoard
 //! - Vga
+//! - Audio
+//! - Disk
+//! - init: addresses of the devices from the device tree
 
+use crate::fdt::Fdt;
 use core::fmt::Write;
 use rconfig::ios::*;
 
+// addresses of the devices, those of rconfig until `init` finds others
+static mut SERIAL: usize = SERIAL_PORT as usize;
+static mut TIMER: usize = TIMER_ADDR as usize;
+static mut KBD: usize = KBD_ADDR as usize;
+static mut VGA: usize = VGA_ADDR as usize;
+static mut VGACTL: usize = VGACTL_ADDR as usize;
+static mut AUDIO: usize = AUDIO_ADDR as usize;
+static mut SBUF: usize = AUDIO_SBUF_ADDR as usize;
+static mut DISK: usize = DISK_ADDR as usize;
+
+/// Take the addresses of the devices from `fdt`.
+pub fn init(fdt: &Fdt) {
+    let base = |compatible| Some(fdt.compatible(compatible)?.reg().next()?.0);
+    unsafe {
+        if let Some(addr) = base("remu,serial") {
+            SERIAL = addr;
+        }
+        if let Some(addr) = base("remu,timer") {
+            TIMER = addr;
+        }
+        if let Some(addr) = base("remu,keyboard") {
+            KBD = addr;
+        }
+        if let Some(addr) = base("simple-framebuffer") {
+            VGA = addr;
+        }
+        if let Some(addr) = base("remu,vgactl") {
+            VGACTL = addr;
+        }
+        if let Some(addr) = base("remu,audio") {
+            AUDIO = addr;
+        }
+        if let Some(addr) = base("remu,audio-sbuf") {
+            SBUF = addr;
+        }
+        if let Some(addr) = base("remu,disk") {
+            DISK = addr;
+        }
+    }
+}
+
 pub trait IO {
     type Input;
     type Output;
@@ -18,14 +63,16 @@ pub struct SerialPort;
 pub struct Timer;
 pub struct KeyBoard;
 pub struct Vga;
+pub struct Audio;
+pub struct Disk;
 
 impl IO for Timer {
     type Input = u64;
     type Output = ();
     fn read() -> Self::Input {
         unsafe {
-            let clock_low32 = (TIMER_ADDR as *mut u32).read_volatile();
-            let clock_high32 = ((TIMER_ADDR + 4) as *mut u32).read_volatile();
+            let clock_low32 = (TIMER as *mut u32).read_volatile();
+            let clock_high32 = ((TIMER + 4) as *mut u32).read_volatile();
             ((clock_high32 as u64) << 32) | (clock_low32 as u64)
         }
     }
@@ -36,7 +83,7 @@ impl IO for KeyBoard {
     type Output = ();
     fn read() -> Self::Input {
         unsafe {
-            let code = (KBD_ADDR as *mut u32).read_volatile();
+            let code = (KBD as *mut u32).read_volatile();
             match code {
                 0 => None,
                 _ => Some(KBEvent::from(code)),
@@ -49,7 +96,7 @@ impl IO for SerialPort {
     type Input = char;
     type Output = ();
     fn read() -> Self::Input {
-        unsafe { (SERIAL_PORT as *mut u8).read_volatile() as char }
+        unsafe { (SERIAL as *mut u8).read_volatile() as char }
     }
 }
 
@@ -58,7 +105,7 @@ impl Write for SerialPort {
         let buf = s.as_bytes();
         for c in buf {
             unsafe {
-                (SERIAL_PORT as *mut u8).write_volatile(*c);
+                (SERIAL as *mut u8).write_volatile(*c);
             }
         }
         Ok(())
@@ -66,23 +113,167 @@ impl Write for SerialPort {
 }
 
 impl Vga {
+    /// Width and height of the screen.
+    pub fn size() -> (usize, usize) {
+        let size = unsafe { (VGACTL as *mut u32).read_volatile() };
+        ((size >> 16) as usize, (size & 0xffff) as usize)
+    }
+
+    /// Tell the screen the frame is complete.
+    pub fn sync() {
+        unsafe { ((VGACTL + 4) as *mut u32).write_volatile(1) }
+    }
+
+    /// Draw a whole frame of `0x00RRGGBB` pixels and sync it.
     pub fn write_all(buffer: &[u32]) {
         unsafe {
             for (index, item) in buffer.iter().enumerate() {
-                ((VGA_ADDR + (index * 4) as u64) as *mut u32).write(*item);
+                ((VGA + index * 4) as *mut u32).write(*item);
             }
         }
+        Vga::sync();
     }
 
     pub fn write(buffer: &[u32], start: usize) {
         unsafe {
             for (index, item) in buffer.iter().enumerate() {
-                ((VGA_ADDR + ((index + start) * 4) as u64) as *mut u32).write(*item);
+                ((VGA + (index + start) * 4) as *mut u32).write(*item);
+            }
+        }
+    }
+}
+
+// registers of the audio device
+const AUDIO_FREQ: usize = 0x00;
+const AUDIO_CHANNELS: usize = 0x04;
+const AUDIO_FORMAT: usize = 0x08;
+const AUDIO_SBUF_SIZE: usize = 0x0c;
+const AUDIO_INIT: usize = 0x10;
+const AUDIO_COUNT: usize = 0x14;
+const AUDIO_HEAD: usize = 0x18;
+
+impl Audio {
+    fn reg(offset: usize) -> *mut u32 {
+        unsafe { (AUDIO + offset) as *mut u32 }
+    }
+
+    /// Start playing `channels` interleaved channels at `freq` Hz, of 16-bit
+    /// signed or 8-bit unsigned samples as `bits` says.
+    pub fn init(freq: u32, channels: u32, bits: u32) {
+        unsafe {
+            Audio::reg(AUDIO_FREQ).write_volatile(freq);
+            Audio::reg(AUDIO_CHANNELS).write_volatile(channels);
+            Audio::reg(AUDIO_FORMAT).write_volatile(bits);
+            Audio::reg(AUDIO_INIT).write_volatile(1);
+        }
+    }
+
+    /// Bytes queued and not played yet.
+    pub fn queued() -> usize {
+        unsafe { Audio::reg(AUDIO_COUNT).read_volatile() as usize }
+    }
+
+    /// Queue `samples`, waiting for room in the stream buffer as needed,
+    /// and dropping them without an audio device.
+    pub fn play(mut samples: &[u8]) {
+        unsafe {
+            let size = Audio::reg(AUDIO_SBUF_SIZE).read_volatile() as usize;
+            if size == 0 {
+                return;
+            }
+            while !samples.is_empty() {
+                let head = Audio::reg(AUDIO_HEAD).read_volatile() as usize;
+                let count = Audio::reg(AUDIO_COUNT).read_volatile() as usize;
+                let n = samples.len().min(size.saturating_sub(count));
+                for (i, sample) in samples[..n].iter().enumerate() {
+                    ((SBUF + (head + count + i) % size) as *mut u8).write_volatile(*sample);
+                }
+                Audio::reg(AUDIO_COUNT).write_volatile((count + n) as u32);
+                samples = &samples[n..];
             }
         }
     }
 }
 
+// registers of the disk, then its data window
+const DISK_CMD: usize = 0x00;
+const DISK_STATUS: usize = 0x04;
+const DISK_SECTOR: usize = 0x08;
+const DISK_COUNT: usize = 0x0c;
+const DISK_SECTORS: usize = 0x10;
+const DISK_FLAGS: usize = 0x14;
+const DISK_WINDOW: usize = 0x1000;
+/// sectors the data window holds
+const DISK_WINDOW_SECTORS: usize = 8;
+
+const DISK_READ: u32 = 1;
+const DISK_WRITE: u32 = 2;
+const DISK_FLUSH: u32 = 3;
+
+pub const SECTOR_SIZE: usize = 512;
+
+impl Disk {
+    fn reg(offset: usize) -> *mut u32 {
+        unsafe { (DISK + offset) as *mut u32 }
+    }
+
+    /// Run `command` on `count` sectors from `sector`, false if it failed.
+    fn command(command: u32, sector: usize, count: usize) -> bool {
+        unsafe {
+            Disk::reg(DISK_SECTOR).write_volatile(sector as u32);
+            Disk::reg(DISK_COUNT).write_volatile(count as u32);
+            Disk::reg(DISK_CMD).write_volatile(command);
+            Disk::reg(DISK_STATUS).read_volatile() == 0
+        }
+    }
+
+    /// Number of sectors, 0 without a disk.
+    pub fn sectors() -> usize {
+        unsafe { Disk::reg(DISK_SECTORS).read_volatile() as usize }
+    }
+
+    pub fn read_only() -> bool {
+        unsafe { Disk::reg(DISK_FLAGS).read_volatile() & 1 == 1 }
+    }
+
+    /// Read the sectors from `sector` into `buffer`, a whole number of
+    /// sectors long.
+    pub fn read(sector: usize, buffer: &mut [u8]) -> bool {
+        for (i, chunk) in buffer
+            .chunks_mut(DISK_WINDOW_SECTORS * SECTOR_SIZE)
+            .enumerate()
+        {
+            let count = chunk.len() / SECTOR_SIZE;
+            if !Disk::command(DISK_READ, sector + i * DISK_WINDOW_SECTORS, count) {
+                return false;
+            }
+            for (j, byte) in chunk.iter_mut().enumerate() {
+                *byte = unsafe { ((DISK + DISK_WINDOW + j) as *const u8).read_volatile() };
+            }
+        }
+        true
+    }
+
+    /// Write `buffer`, a whole number of sectors long, to the sectors from
+    /// `sector`.
+    pub fn write(sector: usize, buffer: &[u8]) -> bool {
+        for (i, chunk) in buffer.chunks(DISK_WINDOW_SECTORS * SECTOR_SIZE).enumerate() {
+            for (j, byte) in chunk.iter().enumerate() {
+                unsafe { ((DISK + DISK_WINDOW + j) as *mut u8).write_volatile(*byte) };
+            }
+            let count = chunk.len() / SECTOR_SIZE;
+            if !Disk::command(DISK_WRITE, sector + i * DISK_WINDOW_SECTORS, count) {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn flush() -> bool {
+        Disk::command(DISK_FLUSH, 0, 0)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Key {
     Esc,
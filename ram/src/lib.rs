@@ -2,6 +2,7 @@
 #![no_main]
 
 pub mod cte;
+pub mod fdt;
 pub mod io;
 pub mod klib;
 pub mod mpe;
@@ -1,36 +1,363 @@
-use remu::exes::{Exe, ELF};
-use remu::isas::{ISA, RV32CPU};
-use remu::rdb::Debugger;
-use remu::{info, warn};
+use remu::config::parse_size;
+use remu::ioe::replay::InputLog;
+use remu::ioe::{DiskMode, DISK_ADDR};
+use remu::isas::{RegisterModel, TimeTravel};
+use remu::machine::{Machine, Stop};
+use remu::rdb::{gdbstub, Debugger};
+use remu::settings::{Devices, Disk, Region, Settings};
+use remu::trace::Trace;
+use remu::util::set_log_file;
+use remu::{fatal, info, warn};
 use std::process::exit;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage: run <image> [options]
+
+Options:
+  --debug                Start in the debugger
+  -x <script>            Run the debugger commands in <script>, without a terminal
+  --gdb <host:port|path> Serve the GDB remote protocol
+  --trace <list>         Trace itrace, mtrace and ftrace, comma separated
+  --max-insns <count>    Stop after <count> instructions
+  --timeout <seconds>    Stop after <seconds> of wall-clock time
+  --log-file <path>      Write the log and traces to <path>
+  --config <file>        Build the machine described in <file>, the options
+                         below overriding it
+  --mem-size <size>      Bytes of RAM from address 0, such as 256M (default 2560M)
+  --devices <list>       Devices among serial, timer, keyboard, vga and audio,
+                         or none
+  --serial-in <file>     Read the serial port input from <file>
+  --dump-frames <file>   Save the frames the program syncs to <file>, PNG or
+                         PPM by its extension, {} standing for the frame number
+  --dump-every <n>       Save one frame out of <n> (default 1)
+  --audio-out <file>     Write the samples the audio device plays to a WAV file
+  --disk <file>          Attach a disk holding the sectors of <file>
+  --disk-mode <mode>     rw to write to <file> (default), ro to refuse writes or
+                         cow to keep them in memory
+  --load-addr <addr>     Load <image> as a raw binary at <addr> and start there
+  --record <log>         Record device inputs to <log>
+  --replay <log>         Replay device inputs from <log>
+
+The exit status is the exit code of the program, 124 when --max-insns or
+--timeout stops it and 1 when it fails.";
+
+/// exit status when a limit stops the program, as timeout(1) has
+const EXIT_LIMIT: i32 = 124;
+
+#[derive(Default)]
+struct Options {
+    image: String,
+    debug: bool,
+    script: Option<String>,
+    gdb: Option<String>,
+    record: Option<String>,
+    replay: Option<String>,
+    trace: Trace,
+    max_insns: Option<u64>,
+    timeout: Option<Duration>,
+    log_file: Option<String>,
+    settings: Settings,
+    dump_frames: Option<String>,
+    dump_every: Option<u64>,
+    audio_out: Option<String>,
+    disk: Option<String>,
+    disk_mode: Option<DiskMode>,
+}
+
+fn parse_options(args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let args: Vec<_> = args.collect();
+    let mut options = Options::default();
+    // the machine description comes first for the other options to override
+    if let Some(i) = args.iter().position(|arg| arg == "--config") {
+        let path = args.get(i + 1).ok_or("--config requires a value")?;
+        options.settings = Settings::load(path).map_err(|e| e.to_string())?;
+    }
+    let mut args = args.into_iter();
+    let mut image = None;
+    while let Some(arg) = args.next() {
+        if arg == "--debug" {
+            options.debug = true;
+            continue;
+        }
+        if !arg.starts_with('-') {
+            if image.replace(arg).is_some() {
+                return Err("only one image can be run".to_string());
+            }
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} requires a value", arg))?;
+        let invalid = || format!("invalid value {} for {}", value, arg);
+        match arg.as_str() {
+            "-x" => options.script = Some(value),
+            "--gdb" => options.gdb = Some(value),
+            "--record" => options.record = Some(value),
+            "--replay" => options.replay = Some(value),
+            "--trace" => options.trace = Trace::parse(&value).map_err(|e| e.to_string())?,
+            "--max-insns" => options.max_insns = Some(value.parse().map_err(|_| invalid())?),
+            "--timeout" => {
+                let seconds = value.parse::<f64>().map_err(|_| invalid())?;
+                options.timeout =
+                    Some(Duration::try_from_secs_f64(seconds).map_err(|_| invalid())?);
+            }
+            "--log-file" => options.log_file = Some(value),
+            "--config" => (),
+            "--mem-size" => {
+                let size = parse_size(&value).ok_or_else(invalid)?;
+                options.settings.ram = vec![Region { base: 0, size }];
+            }
+            "--devices" => {
+                options.settings.devices = Devices::parse(&value).map_err(|e| e.to_string())?
+            }
+            "--serial-in" => options.settings.serial_in = Some(value.into()),
+            "--dump-frames" => options.dump_frames = Some(value),
+            "--audio-out" => options.audio_out = Some(value),
+            "--disk" => options.disk = Some(value),
+            "--disk-mode" => options.disk_mode = Some(value.parse().map_err(|_| invalid())?),
+            "--dump-every" => match value.parse() {
+                Ok(0) | Err(_) => return Err(invalid()),
+                Ok(every) => options.dump_every = Some(every),
+            },
+            "--load-addr" => {
+                let addr = parse_size(&value).ok_or_else(invalid)?;
+                options.settings.boot.load_addr = Some(u32::try_from(addr).map_err(|_| invalid())?);
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    options.image = image.ok_or("no image given")?;
+    if options.dump_frames.is_some() || options.dump_every.is_some() {
+        let vga = options
+            .settings
+            .devices
+            .vga
+            .as_mut()
+            .ok_or("no vga to dump frames of")?;
+        vga.dump = options
+            .dump_frames
+            .take()
+            .map(Into::into)
+            .or(vga.dump.take());
+        vga.dump_every = options.dump_every.unwrap_or(vga.dump_every);
+    }
+    if let Some(path) = options.audio_out.take() {
+        let audio = options
+            .settings
+            .devices
+            .audio
+            .as_mut()
+            .ok_or("no audio device to play through, see --devices")?;
+        audio.output = Some(path.into());
+    }
+    if let Some(image) = options.disk.take() {
+        // a disk of the config keeps its address and mode
+        match options.settings.devices.disk.as_mut() {
+            Some(disk) => disk.image = image.into(),
+            None => {
+                options.settings.devices.disk = Some(Disk {
+                    base: DISK_ADDR,
+                    image: image.into(),
+                    mode: DiskMode::default(),
+                })
+            }
+        }
+    }
+    if let Some(mode) = options.disk_mode {
+        let disk = options
+            .settings
+            .devices
+            .disk
+            .as_mut()
+            .ok_or("no disk, see --disk")?;
+        disk.mode = mode;
+    }
+    if options.record.is_some() && options.replay.is_some() {
+        return Err("--record and --replay cannot be used together".to_string());
+    }
+    // only a plain run counts instructions and time
+    let limited = options.max_insns.is_some() || options.timeout.is_some();
+    if limited && (options.debug || options.script.is_some() || options.gdb.is_some()) {
+        return Err(
+            "--max-insns and --timeout cannot be used with --debug, -x or --gdb".to_string(),
+        );
+    }
+    options.settings.validate().map_err(|e| e.to_string())?;
+    Ok(options)
+}
+
+/// Run until the program exits, returning the exit status.
+fn run(machine: &mut Machine, options: &Options) -> i32 {
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+    let mut count = 0_u64;
+    let stop = machine.run_until(options.max_insns, |_| {
+        count += 1;
+        // the clock is only read now and then
+        count.is_multiple_of(0x1000) && deadline.is_some_and(|d| Instant::now() >= d)
+    });
+    let (pc, icount) = (machine.cpu().pc(), machine.cpu().icount());
+    match stop {
+        Stop::Exit(code) => {
+            info!("Program exited with code {}", code);
+            code as u8 as i32
+        }
+        Stop::Budget => {
+            warn!("Stopped after {} instructions at {:#x}", icount, pc);
+            EXIT_LIMIT
+        }
+        Stop::Condition => {
+            warn!("Timed out after {} instructions at {:#x}", icount, pc);
+            EXIT_LIMIT
+        }
+        Stop::Error(e) => {
+            warn!("Program exited with error: {}", e);
+            1
+        }
+    }
+}
 
 fn main() {
-    let args = std::env::args().collect::<Vec<String>>();
-    let mut debugger = Debugger::new();
-    let mut cpu = RV32CPU::default();
-    let mut exe = {
-        if args.len() > 1 {
-            ELF::parse_path(&args[1]).unwrap()
-        } else {
-            println!("Usage: {} <elf>", args[0]);
-            std::process::exit(1);
+    if std::env::args().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        exit(0);
+    }
+    let options = match parse_options(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            exit(2);
         }
     };
-    exe.load_binary(&mut cpu).unwrap();
-    if args.len() == 2 {
-        if let Err(e) = cpu.run() {
-            match e {
-                remu::error::RError::Ebreak(exitcode) => {
-                    info!("Program exited with code {}", exitcode);
-                    exit(0);
-                }
-                _ => {
-                    warn!("Program exited with error: {:?}", e);
-                    exit(1);
-                }
+    if let Some(path) = &options.log_file {
+        if let Err(e) = set_log_file(path) {
+            fatal!("{}", e);
+            exit(1);
+        }
+    }
+    let mut machine = Machine::builder()
+        .settings(options.settings.clone())
+        .trace(options.trace)
+        .build()
+        .unwrap_or_else(|e| {
+            fatal!("{}", e);
+            exit(1);
+        });
+    if let Err(e) = machine.load(&options.image) {
+        fatal!("{}: {}", options.image, e);
+        exit(1);
+    }
+    let log = match (&options.record, &options.replay) {
+        (Some(path), _) => Some(InputLog::record_path(path)),
+        (_, Some(path)) => Some(InputLog::replay_path(path)),
+        _ => None,
+    };
+    match log.transpose() {
+        Ok(Some(log)) => machine.cpu_mut().set_input_log(log),
+        Ok(None) => (),
+        Err(e) => {
+            fatal!("{}", e);
+            exit(1);
+        }
+    }
+    let mut debugger = Debugger::new();
+    if let Some(elf) = machine.elf() {
+        debugger.load_symbols(elf);
+    }
+    let cpu = machine.cpu_mut();
+    if let Some(addr) = &options.gdb {
+        if let Err(e) = gdbstub::listen(addr).and_then(|mut stub| stub.serve(cpu)) {
+            fatal!("{}", e);
+            exit(1);
+        }
+    } else if let Some(script) = &options.script {
+        // batch mode fails unless the program exits, with its exit code
+        debugger.set_batch(true);
+        if let Err(e) = debugger.source(script) {
+            fatal!("{}", e);
+            exit(1);
+        }
+        debugger.debug(cpu);
+        match debugger.exit_code() {
+            Some(0) => exit(0),
+            Some(code) => {
+                warn!("Program exited with code {}", code);
+                exit(code as u8 as i32);
+            }
+            None => {
+                warn!("Program did not exit");
+                exit(1);
             }
         }
+    } else if options.debug {
+        debugger.debug(cpu);
+        exit(debugger.exit_code().map_or(0, |code| code as u8 as i32));
     } else {
-        debugger.debug(&mut cpu);
+        exit(run(&mut machine, &options));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        parse_options(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn test_options() {
+        let options = parse("a.out --trace itrace,ftrace --max-insns 1000").unwrap();
+        assert_eq!(options.image, "a.out");
+        assert!(options.trace.itrace && options.trace.ftrace);
+        assert_eq!(options.max_insns, Some(1000));
+
+        let options = parse("--mem-size 256M --load-addr 0x80000000 --timeout 1.5 img").unwrap();
+        assert_eq!(options.settings.ram[0].end(), 256 << 20);
+        assert_eq!(options.settings.boot.load_addr, Some(0x80000000));
+        assert_eq!(options.timeout, Some(Duration::from_millis(1500)));
+
+        assert!(parse("").is_err());
+        assert!(parse("a.out b.out").is_err());
+        assert!(parse("a.out --max-insns").is_err());
+        assert!(parse("a.out --debug --max-insns 1000").is_err());
+        assert!(parse("a.out -x script.rdb --timeout 10").is_err());
+        assert!(parse("a.out --gdb :1234 --timeout 10").is_err());
+        assert!(parse("a.out --mem-size 8G").is_err());
+        // RAM up to the devices, or over them once they are gone
+        assert!(parse("a.out --mem-size 3G").is_err());
+        assert!(parse("a.out --mem-size 4G --devices none").is_ok());
+        assert!(parse("a.out --config /nonexistent").is_err());
+        assert!(parse("a.out --devices serial,gpu").is_err());
+        assert!(parse("a.out --bogus 1").is_err());
+        let options = parse("a.out --dump-frames f{}.png --dump-every 60").unwrap();
+        let vga = options.settings.devices.vga.unwrap();
+        assert_eq!(vga.dump, Some("f{}.png".into()));
+        assert_eq!(vga.dump_every, 60);
+        assert!(parse("a.out --devices serial --dump-frames f.png").is_err());
+        assert!(parse("a.out --dump-every 0").is_err());
+        let options = parse("a.out --devices serial,audio --audio-out tone.wav").unwrap();
+        let audio = options.settings.devices.audio.unwrap();
+        assert_eq!(audio.output, Some("tone.wav".into()));
+        assert!(parse("a.out --audio-out tone.wav").is_err());
+        let options = parse("a.out --disk-mode cow --disk disk.img").unwrap();
+        let disk = options.settings.devices.disk.unwrap();
+        assert_eq!(
+            (disk.image, disk.mode),
+            ("disk.img".into(), DiskMode::CopyOnWrite)
+        );
+        assert!(parse("a.out --disk-mode ro").is_err());
+        assert!(parse("a.out --disk disk.img --disk-mode rx").is_err());
+
+        let config = std::env::temp_dir().join(format!("remu-disk-{}.toml", std::process::id()));
+        std::fs::write(
+            &config,
+            "[[ram]]\nsize = 0x1000\n[disk]\nbase = 0xb0000000\nimage = \"a.img\"\nmode = \"ro\"\n",
+        )
+        .unwrap();
+        let options = parse(&format!("a.out --config {} --disk b.img", config.display()));
+        std::fs::remove_file(&config).unwrap();
+        let disk = options.unwrap().settings.devices.disk.unwrap();
+        assert_eq!(disk.image, std::path::PathBuf::from("b.img"));
+        assert_eq!((disk.base, disk.mode), (0xb0000000, DiskMode::ReadOnly));
     }
 }
@@ -1,105 +1,136 @@
-use remu::exes::{Exe, ELF};
+use remu::ioe::audio::SdlSink;
 use remu::ioe::keyboard::KBEvent;
-use remu::isas::{ISA, RV32CPU};
+use remu::ioe::replay::InputLog;
+use remu::ioe::{Audio, Keyboard, Screen};
+use remu::machine::{Machine, Stop};
+use remu::settings::Settings;
 use remu::{fatal, info, warn};
 use sdl2::event::Event;
 use sdl2::pixels::PixelFormatEnum;
 
-const WIDTH: u32 = 400;
-const HEIGHT: u32 = 300;
+const USAGE: &str = "Usage: sdl <elf> [--config <file>] [--record <log> | --replay <log>]";
+
+/// instructions run between two looks at the window
+const SLICE: u64 = 1000;
 
 fn main() {
     let args = std::env::args().collect::<Vec<String>>();
-
-    // init cpu and load binary
-    let mut cpu = RV32CPU::default();
-    let mut exe = {
-        if args.len() >= 2 {
-            ELF::parse_path(&args[1]).unwrap()
-        } else {
-            fatal!("Usage: {} <elf> [args]", args[0]);
+    let Some(image) = args.get(1) else {
+        fatal!("{}", USAGE);
+        std::process::exit(1);
+    };
+    let mut settings = Settings::default();
+    let mut log = None;
+    for pair in args[2..].chunks(2) {
+        let [option, value] = pair else {
+            fatal!("{}", USAGE);
+            std::process::exit(1);
+        };
+        let result = match option.as_str() {
+            "--config" => Settings::load(value).map(|loaded| settings = loaded),
+            "--record" => InputLog::record_path(value).map(|record| log = Some(record)),
+            "--replay" => InputLog::replay_path(value).map(|replay| log = Some(replay)),
+            option => {
+                fatal!("unknown option {}\n{}", option, USAGE);
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = result {
+            fatal!("{}", e);
             std::process::exit(1);
         }
+    }
+    let Some(vga) = &settings.devices.vga else {
+        fatal!("the machine has no vga to show");
+        std::process::exit(1);
     };
-    exe.load_binary(&mut cpu).unwrap();
+    let (width, height) = (vga.width, vga.height);
+
+    let mut machine = Machine::builder()
+        .settings(settings)
+        .build()
+        .and_then(|mut machine| machine.load(image).map(|_| machine))
+        .unwrap_or_else(|e| {
+            fatal!("{}: {}", image, e);
+            std::process::exit(1);
+        });
+    if let Some(log) = log {
+        machine.cpu_mut().set_input_log(log);
+    }
 
     // init devices, i.e. vga, keyboard
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let window = video_subsystem
-        .window("REMU", WIDTH, HEIGHT)
+        .window("REMU", width, height)
         .position_centered()
         .opengl()
         .build()
         .unwrap();
     let mut canvas = window.into_canvas().build().unwrap();
+    // play through the speakers unless the samples go to a file
+    let speakers =
+        matches!(&machine.settings().devices.audio, Some(audio) if audio.output.is_none());
+    if speakers {
+        let subsystem = sdl_context.audio().unwrap();
+        if let Some(audio) = machine.device::<Audio>() {
+            audio.set_sink(SdlSink::new(subsystem));
+        }
+    }
     let texture_creator = canvas.texture_creator();
     let mut texture = texture_creator
-        .create_texture_streaming(PixelFormatEnum::RGB24, WIDTH, HEIGHT)
+        .create_texture_streaming(PixelFormatEnum::RGB24, width, height)
         .unwrap();
     canvas.clear();
     canvas.copy(&texture, None, None).unwrap();
     canvas.present();
 
     let mut event_pump = sdl_context.event_pump().unwrap();
-    let mut pixels = [0_u8; (WIDTH * HEIGHT * 3) as usize];
+    let mut pixels = vec![0_u8; (width * height * 3) as usize];
     let mut last = std::time::Instant::now();
     let start_time = std::time::Instant::now();
     let mut step = 0;
     'running: loop {
-        match cpu.step() {
-            Ok(_) => {}
-            Err(remu::error::RError::Ebreak(code)) => {
-                if code == 0 {
-                    info!("Program exited normally");
-                    break 'running;
-                } else {
-                    warn!("Program exited with code {}", code);
-                    break 'running;
-                }
+        match machine.run_for(SLICE) {
+            Stop::Budget | Stop::Condition => {}
+            Stop::Exit(0) => {
+                info!("Program exited normally");
+                break 'running;
             }
-            Err(e) => {
+            Stop::Exit(code) => {
+                warn!("Program exited with code {}", code);
+                break 'running;
+            }
+            Stop::Error(e) => {
                 println!("Error: {:?}", e);
                 break 'running;
             }
         }
-        step += 1;
-        if step % 1000 == 0 {
-            let now = std::time::Instant::now();
-            if now - last >= std::time::Duration::from_millis(1000 / 15) {
-                last = now;
-                for event in event_pump.poll_iter() {
-                    match event {
-                        Event::Quit { .. } => break 'running,
-                        Event::KeyUp { .. } | Event::KeyDown { .. } => {
-                            for device in cpu.mems.devices.iter_mut() {
-                                if device.name() == "keyboard" {
-                                    let code = KBEvent::from(event);
-                                    device.write(0, u32::from(code) as u64);
-                                    break;
-                                }
-                            }
+        step += SLICE;
+        let now = std::time::Instant::now();
+        if now - last >= std::time::Duration::from_millis(1000 / 15) {
+            last = now;
+            for event in event_pump.poll_iter() {
+                match event {
+                    Event::Quit { .. } => break 'running,
+                    Event::KeyUp { .. } | Event::KeyDown { .. } => {
+                        if let Some(keyboard) = machine.device::<Keyboard>() {
+                            keyboard.push(KBEvent::from(event));
                         }
-                        _ => {}
                     }
+                    _ => {}
                 }
-                for device in cpu.mems.devices.iter_mut() {
-                    if device.name() == "vga" {
-                        for i in 0..WIDTH * HEIGHT {
-                            let value = device.read(i as u64).unwrap();
-                            // r
-                            pixels[i as usize * 3] = (value & 0xff) as u8;
-                            // g
-                            pixels[i as usize * 3 + 1] = ((value >> 8) & 0xff) as u8;
-                            // b
-                            pixels[i as usize * 3 + 2] = ((value >> 16) & 0xff) as u8;
-                        }
-                        texture.update(None, &pixels, WIDTH as usize * 3).unwrap();
-                        canvas.copy(&texture, None, None).unwrap();
-                        canvas.present();
-                        break;
-                    }
+            }
+            if let Some(screen) = machine.device::<Screen>() {
+                // pixels are 0x00RRGGBB
+                for (i, value) in screen.pixels().iter().enumerate() {
+                    pixels[i * 3] = (value >> 16) as u8;
+                    pixels[i * 3 + 1] = (value >> 8) as u8;
+                    pixels[i * 3 + 2] = *value as u8;
                 }
+                texture.update(None, &pixels, width as usize * 3).unwrap();
+                canvas.copy(&texture, None, None).unwrap();
+                canvas.present();
             }
         }
     }
@@ -0,0 +1,254 @@
+//! The subset of TOML machine descriptions are written in: `[table]` and
+//! `[[array]]` headers, `key = value` pairs with integer, string and boolean
+//! values, and `#` comments.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Integer(u64),
+    String(String),
+    Boolean(bool),
+}
+
+/// A table of the file, `[[array]]` giving one table per header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    /// empty for the keys before the first header
+    pub name: String,
+    /// line of the header
+    pub line: usize,
+    pub entries: Vec<(String, Value)>,
+}
+
+/// A size in bytes, decimal or `0x` hexadecimal with an optional `K`, `M`
+/// or `G` suffix.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let (number, unit) = match size.char_indices().last()? {
+        (i, 'k' | 'K') => (&size[..i], 1 << 10),
+        (i, 'm' | 'M') => (&size[..i], 1 << 20),
+        (i, 'g' | 'G') => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    let number = match number.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => number.parse().ok()?,
+    };
+    number.checked_mul(unit)
+}
+
+fn parse_integer(text: &str) -> Option<u64> {
+    let text = text.replace('_', "");
+    let (digits, radix) = match text.get(..2) {
+        Some("0x") => (&text[2..], 16),
+        Some("0o") => (&text[2..], 8),
+        Some("0b") => (&text[2..], 2),
+        _ => (text.as_str(), 10),
+    };
+    if !digits.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    u64::from_str_radix(digits, radix).ok()
+}
+
+/// Parse the value at the start of `text`, returning it and what follows.
+fn parse_value(text: &str) -> Result<(Value, &str), String> {
+    if let Some(rest) = text.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or("unterminated string")?;
+        return Ok((Value::String(rest[..end].to_string()), &rest[end + 1..]));
+    }
+    if let Some(rest) = text.strip_prefix('"') {
+        let mut string = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(string), &rest[i + 1..])),
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some(c @ ('"' | '\\')) => string.push(c),
+                    _ => return Err("invalid escape in string".to_string()),
+                },
+                c => string.push(c),
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+    let end = text
+        .find(|c: char| c.is_whitespace() || c == '#')
+        .unwrap_or(text.len());
+    let value = match &text[..end] {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        word => Value::Integer(parse_integer(word).ok_or(format!("invalid value {}", word))?),
+    };
+    Ok((value, &text[end..]))
+}
+
+fn is_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Parse `text` into its tables, in order. Errors name the line they are on.
+pub fn parse(text: &str) -> Result<Vec<Table>, String> {
+    let mut tables = vec![Table {
+        name: String::new(),
+        line: 0,
+        entries: vec![],
+    }];
+    for (i, line) in text.lines().enumerate() {
+        let number = i + 1;
+        let error = |message: &str| format!("line {}: {}", number, message);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let (header, array) = match header.strip_prefix('[') {
+                Some(header) => (header.split_once("]]"), true),
+                None => (header.split_once(']'), false),
+            };
+            let (name, rest) = header.ok_or_else(|| error("unterminated table header"))?;
+            let name = name.trim();
+            let rest = rest.trim_start();
+            if !is_key(name) || !(rest.is_empty() || rest.starts_with('#')) {
+                return Err(error("invalid table header"));
+            }
+            if !array && tables.iter().any(|table| table.name == name) {
+                return Err(error(&format!("table {} is defined twice", name)));
+            }
+            tables.push(Table {
+                name: name.to_string(),
+                line: number,
+                entries: vec![],
+            });
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected key = value"))?;
+        let key = key.trim();
+        if !is_key(key) {
+            return Err(error(&format!("invalid key {}", key)));
+        }
+        let (value, rest) = parse_value(value.trim_start()).map_err(|e| error(&e))?;
+        let rest = rest.trim_start();
+        if !(rest.is_empty() || rest.starts_with('#')) {
+            return Err(error(&format!("unexpected {} after value", rest)));
+        }
+        let table = tables.last_mut().unwrap();
+        if table.entries.iter().any(|(k, _)| k == key) {
+            return Err(error(&format!("key {} is defined twice", key)));
+        }
+        table.entries.push((key.to_string(), value));
+    }
+    Ok(tables)
+}
+
+impl Table {
+    /// `message` prefixed with where the table is.
+    pub fn error(&self, message: String) -> String {
+        match self.name.as_str() {
+            "" => message,
+            name => format!("line {}: [{}] {}", self.line, name, message),
+        }
+    }
+
+    /// Fail on keys other than `known`.
+    pub fn check_keys(&self, known: &[&str]) -> Result<(), String> {
+        match self
+            .entries
+            .iter()
+            .find(|(key, _)| !known.contains(&key.as_str()))
+        {
+            Some((key, _)) => Err(self.error(format!("unknown key {}", key))),
+            None => Ok(()),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+    }
+
+    pub fn integer(&self, key: &str) -> Result<Option<u64>, String> {
+        match self.get(key) {
+            Some(Value::Integer(value)) => Ok(Some(*value)),
+            Some(_) => Err(self.error(format!("{} must be an integer", key))),
+            None => Ok(None),
+        }
+    }
+
+    /// An integer, or a string such as `"128M"`.
+    pub fn size(&self, key: &str) -> Result<Option<u64>, String> {
+        match self.get(key) {
+            Some(Value::Integer(value)) => Ok(Some(*value)),
+            Some(Value::String(size)) => match parse_size(size) {
+                Some(size) => Ok(Some(size)),
+                None => Err(self.error(format!("invalid size {} for {}", size, key))),
+            },
+            Some(_) => Err(self.error(format!("{} must be a size", key))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn string(&self, key: &str) -> Result<Option<&str>, String> {
+        match self.get(key) {
+            Some(Value::String(value)) => Ok(Some(value)),
+            Some(_) => Err(self.error(format!("{} must be a string", key))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn boolean(&self, key: &str) -> Result<Option<bool>, String> {
+        match self.get(key) {
+            Some(Value::Boolean(value)) => Ok(Some(*value)),
+            Some(_) => Err(self.error(format!("{} must be true or false", key))),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "isa = \"rv32i\" # the base\n\
+                    \n\
+                    [[ram]]\n\
+                    base = 0x8000_0000\n\
+                    size = '128M'\n\
+                    [[ram]]\n\
+                    base = 0\n\
+                    [serial]\n\
+                    input = \"a \\\"b\\\"\"\n\
+                    enabled = false\n";
+        let tables = parse(text).unwrap();
+        assert_eq!(tables.len(), 4);
+        assert_eq!(tables[0].string("isa"), Ok(Some("rv32i")));
+        assert_eq!(tables[1].name, "ram");
+        assert_eq!(tables[1].integer("base"), Ok(Some(0x80000000)));
+        assert_eq!(tables[1].size("size"), Ok(Some(128 << 20)));
+        assert_eq!(tables[2].line, 6);
+        assert_eq!(tables[3].string("input"), Ok(Some("a \"b\"")));
+        assert_eq!(tables[3].boolean("enabled"), Ok(Some(false)));
+        assert!(tables[3].integer("input").is_err());
+        assert!(tables[3].check_keys(&["input"]).is_err());
+
+        assert_eq!(
+            parse("a = 1\nb 2").unwrap_err(),
+            "line 2: expected key = value"
+        );
+        assert!(parse("[ram]\n[ram]").is_err());
+        assert!(parse("a = 1\na = 2").is_err());
+        assert!(parse("a = 0xg").is_err());
+        assert!(parse("a = \"b").is_err());
+        assert!(parse("a = 1 2").is_err());
+        assert_eq!(parse_size("0x1000k"), Some(0x400000));
+    }
+}
@@ -0,0 +1,302 @@
+//! The flattened device tree (DTB) describing a machine to the software it
+//! boots, written at the end of RAM with its address in a1.
+
+use crate::error::RError;
+use crate::isas::ISA;
+use crate::settings::Settings;
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+/// size of the header, followed by the memory reservation block
+const HEADER_SIZE: usize = 40;
+
+/// ticks per second of the timer, which counts milliseconds
+const TIMEBASE_FREQUENCY: u32 = 1000;
+
+fn align(bytes: &mut Vec<u8>) {
+    bytes.resize(bytes.len().next_multiple_of(4), 0);
+}
+
+/// Builder of a flattened device tree, nodes being written in order.
+#[derive(Default)]
+pub struct Fdt {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    reserved: Vec<(u64, u64)>,
+}
+
+impl Fdt {
+    pub fn new() -> Fdt {
+        Fdt::default()
+    }
+
+    fn token(&mut self, token: u32) {
+        self.structure.extend(token.to_be_bytes());
+    }
+
+    pub fn begin_node(&mut self, name: &str) {
+        self.token(FDT_BEGIN_NODE);
+        self.structure.extend(name.as_bytes());
+        self.structure.push(0);
+        align(&mut self.structure);
+    }
+
+    pub fn end_node(&mut self) {
+        self.token(FDT_END_NODE);
+    }
+
+    /// offset of `name` in the strings block, adding it unless it is there
+    fn string(&mut self, name: &str) -> u32 {
+        let mut offset = 0;
+        for string in self.strings.split(|byte| *byte == 0) {
+            if string == name.as_bytes() && offset < self.strings.len() {
+                return offset as u32;
+            }
+            offset += string.len() + 1;
+        }
+        let offset = self.strings.len();
+        self.strings.extend(name.as_bytes());
+        self.strings.push(0);
+        offset as u32
+    }
+
+    pub fn property(&mut self, name: &str, value: &[u8]) {
+        let name = self.string(name);
+        self.token(FDT_PROP);
+        self.token(value.len() as u32);
+        self.token(name);
+        self.structure.extend(value);
+        align(&mut self.structure);
+    }
+
+    /// A property of 32-bit cells.
+    pub fn property_cells(&mut self, name: &str, cells: &[u32]) {
+        let value: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes()).collect();
+        self.property(name, &value);
+    }
+
+    /// A property of NUL terminated strings, such as `compatible`.
+    pub fn property_strings(&mut self, name: &str, strings: &[&str]) {
+        let value: Vec<u8> = strings
+            .iter()
+            .flat_map(|string| string.bytes().chain([0]))
+            .collect();
+        self.property(name, &value);
+    }
+
+    /// Keep the software from using `size` bytes at `addr`.
+    pub fn reserve(&mut self, addr: u64, size: u64) {
+        self.reserved.push((addr, size));
+    }
+
+    /// bytes of the tree once finished
+    pub fn size(&self) -> usize {
+        HEADER_SIZE + 16 * (self.reserved.len() + 1) + self.structure.len() + 4 + self.strings.len()
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.token(FDT_END);
+        let off_mem_rsvmap = HEADER_SIZE;
+        let off_dt_struct = off_mem_rsvmap + 16 * (self.reserved.len() + 1);
+        let off_dt_strings = off_dt_struct + self.structure.len();
+        let total_size = off_dt_strings + self.strings.len();
+        let header = [
+            FDT_MAGIC,
+            total_size as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            // version 17, compatible back to version 16
+            17,
+            16,
+            // boot_cpuid_phys
+            0,
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ];
+        let mut bytes: Vec<u8> = header.iter().flat_map(|word| word.to_be_bytes()).collect();
+        for (addr, size) in self.reserved.iter().chain([&(0, 0)]) {
+            bytes.extend(addr.to_be_bytes());
+            bytes.extend(size.to_be_bytes());
+        }
+        bytes.extend(self.structure);
+        bytes.extend(self.strings);
+        bytes
+    }
+}
+
+/// The device tree of the machine of `settings`, without reservations.
+pub fn build(settings: &Settings) -> Fdt {
+    let mut fdt = Fdt::new();
+    let devices = &settings.devices;
+    fdt.begin_node("");
+    fdt.property_cells("#address-cells", &[1]);
+    fdt.property_cells("#size-cells", &[1]);
+    fdt.property_strings("compatible", &["remu"]);
+    fdt.property_strings("model", &["remu"]);
+
+    fdt.begin_node("chosen");
+    if let Some(base) = devices.serial {
+        fdt.property_strings("stdout-path", &[&format!("/soc/serial@{:x}", base)]);
+    }
+    fdt.end_node();
+
+    fdt.begin_node("cpus");
+    fdt.property_cells("#address-cells", &[1]);
+    fdt.property_cells("#size-cells", &[0]);
+    fdt.property_cells("timebase-frequency", &[TIMEBASE_FREQUENCY]);
+    fdt.begin_node("cpu@0");
+    fdt.property_strings("device_type", &["cpu"]);
+    fdt.property_cells("reg", &[0]);
+    fdt.property_strings("status", &["okay"]);
+    fdt.property_strings("compatible", &["riscv"]);
+    fdt.property_strings("riscv,isa", &[&settings.isa.to_string()]);
+    fdt.property_strings("mmu-type", &["riscv,none"]);
+    fdt.begin_node("interrupt-controller");
+    fdt.property_cells("#interrupt-cells", &[1]);
+    fdt.property("interrupt-controller", &[]);
+    fdt.property_strings("compatible", &["riscv,cpu-intc"]);
+    fdt.end_node();
+    fdt.end_node();
+    fdt.end_node();
+
+    for region in settings.ram.iter() {
+        fdt.begin_node(&format!("memory@{:x}", region.base));
+        fdt.property_strings("device_type", &["memory"]);
+        // a region of 4G has a size beyond one cell
+        fdt.property_cells(
+            "reg",
+            &[region.base as u32, region.size.min(u32::MAX as u64) as u32],
+        );
+        fdt.end_node();
+    }
+
+    // remu has no CLINT or PLIC, its devices raise no interrupt
+    fdt.begin_node("soc");
+    fdt.property_cells("#address-cells", &[1]);
+    fdt.property_cells("#size-cells", &[1]);
+    fdt.property_strings("compatible", &["simple-bus"]);
+    fdt.property("ranges", &[]);
+    for (name, region) in devices.regions() {
+        let (node, compatible) = match name {
+            "vga" => ("framebuffer", "simple-framebuffer"),
+            "vgactl" => ("vgactl", "remu,vgactl"),
+            "serial" => ("serial", "remu,serial"),
+            "timer" => ("timer", "remu,timer"),
+            "keyboard" => ("keyboard", "remu,keyboard"),
+            "audio" => ("audio", "remu,audio"),
+            "sbuf" => ("sbuf", "remu,audio-sbuf"),
+            "disk" => ("disk", "remu,disk"),
+            name => (name, name),
+        };
+        fdt.begin_node(&format!("{}@{:x}", node, region.base));
+        fdt.property_strings("compatible", &[compatible]);
+        fdt.property_cells("reg", &[region.base as u32, region.size as u32]);
+        if let (Some(vga), "vga") = (&devices.vga, name) {
+            fdt.property_cells("width", &[vga.width]);
+            fdt.property_cells("height", &[vga.height]);
+            fdt.property_cells("stride", &[vga.width * 4]);
+            fdt.property_strings("format", &["a8r8g8b8"]);
+        }
+        fdt.end_node();
+    }
+    fdt.end_node();
+
+    fdt.end_node();
+    fdt
+}
+
+/// Write the device tree of `settings` at the end of the highest RAM region
+/// and start with its address in a1 and the hart id in a0, as the RISC-V
+/// boot convention has it. Returns the address of the tree.
+pub fn boot(cpu: &mut impl ISA, settings: &Settings) -> Result<u32, RError> {
+    let mut fdt = build(settings);
+    // the tree reserves itself
+    let size = (fdt.size() + 16) as u64;
+    let ram = settings
+        .ram
+        .iter()
+        .max_by_key(|region| region.base)
+        .ok_or_else(|| RError::Other("no RAM for the device tree".to_string()))?;
+    if ram.size < size {
+        return Err(RError::Other(format!(
+            "ram at {:#x} is too small for a device tree of {} bytes",
+            ram.base, size
+        )));
+    }
+    let addr = (ram.end() - size) & !7;
+    fdt.reserve(addr, size);
+    let bytes = fdt.finish();
+    for (i, byte) in bytes.iter().enumerate() {
+        cpu.store_mem(addr as u32 + i as u32, 1, *byte as u32);
+    }
+    cpu.write_register_by_name("a0", 0);
+    cpu.write_register_by_name("a1", addr as u32);
+    Ok(addr as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isas::{MemoryModel, RegisterModel, RV32CPU};
+
+    fn word(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_fdt() {
+        let mut fdt = Fdt::new();
+        fdt.begin_node("");
+        fdt.property_strings("compatible", &["a", "b"]);
+        fdt.property_cells("reg", &[0x1000]);
+        fdt.begin_node("node@1");
+        fdt.property_cells("reg", &[1]);
+        fdt.end_node();
+        fdt.end_node();
+        fdt.reserve(0x2000, 0x100);
+        let size = fdt.size();
+        let bytes = fdt.finish();
+        assert_eq!(bytes.len(), size);
+        assert_eq!(word(&bytes, 0), FDT_MAGIC);
+        assert_eq!(word(&bytes, 4), size as u32);
+        // the strings are shared between properties
+        let strings = word(&bytes, 12) as usize;
+        assert_eq!(&bytes[strings..], b"compatible\0reg\0");
+        // the reservation, then the terminating entry
+        assert_eq!(word(&bytes, 40 + 4), 0x2000);
+        assert_eq!(word(&bytes, 40 + 12), 0x100);
+        assert_eq!(&bytes[56..72], &[0; 16]);
+        let structure = word(&bytes, 8) as usize;
+        assert_eq!(word(&bytes, structure), FDT_BEGIN_NODE);
+        // the root has an empty name, padded to 4 bytes
+        assert_eq!(word(&bytes, structure + 8), FDT_PROP);
+        assert_eq!(word(&bytes, structure + 12), 4);
+        assert_eq!(&bytes[structure + 20..structure + 24], b"a\0b\0");
+        assert_eq!(word(&bytes, strings - 4), FDT_END);
+    }
+
+    #[test]
+    fn test_boot() {
+        let settings = Settings::default();
+        let mut cpu = RV32CPU::default();
+        let addr = boot(&mut cpu, &settings).unwrap();
+        assert_eq!(cpu.read_register_by_name("a1"), Some(addr));
+        assert_eq!(addr % 8, 0);
+        assert!(addr as u64 + 0x800 > settings.ram[0].end());
+        assert_eq!(cpu.load_mem(addr, 4), Some(FDT_MAGIC.swap_bytes()));
+        let size = cpu.load_mem(addr + 4, 4).unwrap().swap_bytes();
+        assert!(addr as u64 + size as u64 <= settings.ram[0].end());
+        let bytes: Vec<u8> = (0..size)
+            .map(|i| cpu.load_mem(addr + i, 1).unwrap() as u8)
+            .collect();
+        let contains = |text: &[u8]| bytes.windows(text.len()).any(|w| w == text);
+        assert!(contains(b"rv32i_zicsr\0"));
+        assert!(contains(b"serial@a00003f8\0"));
+        assert!(contains(b"/soc/serial@a00003f8\0"));
+        assert!(contains(b"memory@0\0"));
+    }
+}
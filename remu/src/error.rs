@@ -10,6 +10,7 @@ pub enum RError {
     InvalidCode(u32),
     InvalidRegister(u32),
     InvalidMem(u32),
+    InvalidStore(u32),
     InvalidAssembly(String),
     AddressMisaligned(u32),
     IOError(String),
@@ -31,6 +32,7 @@ impl Display for RError {
                 write!(f, "{}: {}", ("InvalidRegister").bold().red(), reg)
             }
             RError::InvalidMem(s) => write!(f, "{}: {:#x}", ("InvalidMem").bold().red(), s),
+            RError::InvalidStore(s) => write!(f, "{}: {:#x}", ("InvalidStore").bold().red(), s),
             RError::AddressMisaligned(s) => {
                 write!(f, "{}: {:#x}", ("AddressMisaligned").bold().red(), s)
             }
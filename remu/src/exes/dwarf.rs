@@ -0,0 +1,488 @@
+//! Line number information from the DWARF `.debug_line` section, versions
+//! 2 to 5, see chapter 6.2 of the DWARF 5 standard.
+
+use super::ELF;
+use crate::error::RError;
+
+// standard opcodes
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_NEGATE_STMT: u8 = 6;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+
+// extended opcodes
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+// line number header entry formats
+const DW_LNCT_PATH: u64 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+
+/// A row of the line number matrix: the instructions from `addr` up to the
+/// next row come from `line` of `file`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineRow {
+    pub addr: u64,
+    /// index into the files of the table
+    pub file: usize,
+    pub line: u32,
+    /// whether `addr` is a recommended breakpoint location for the line
+    pub is_stmt: bool,
+    /// the first address after a sequence of instructions
+    pub end_sequence: bool,
+}
+
+/// The line number matrix of a whole program, sorted by address.
+#[derive(Debug, Default)]
+pub struct LineTable {
+    pub files: Vec<String>,
+    pub rows: Vec<LineRow>,
+}
+
+fn invalid(what: &str) -> RError {
+    RError::Other(format!("invalid .debug_line: {}", what))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], RError> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or(invalid("unexpected end"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// little endian unsigned integer of `len` bytes
+    fn uint(&mut self, len: usize) -> Result<u64, RError> {
+        let bytes = self.bytes(len)?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, byte| value << 8 | *byte as u64))
+    }
+
+    fn u8(&mut self) -> Result<u8, RError> {
+        Ok(self.uint(1)? as u8)
+    }
+
+    fn uleb128(&mut self) -> Result<u64, RError> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn sleb128(&mut self) -> Result<i64, RError> {
+        let mut value = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    /// NUL-terminated string
+    fn string(&mut self) -> Result<String, RError> {
+        let len = self.bytes[self.pos..]
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or(invalid("unterminated string"))?;
+        let string = String::from_utf8_lossy(self.bytes(len)?).to_string();
+        self.pos += 1;
+        Ok(string)
+    }
+}
+
+/// string at `offset` of a string section
+fn string_at(section: &[u8], offset: u64) -> Result<String, RError> {
+    let mut reader = Reader::new(section);
+    reader.pos = offset as usize;
+    if reader.is_empty() {
+        return Err(invalid("string offset out of range"));
+    }
+    reader.string()
+}
+
+fn join(dir: &str, file: &str) -> String {
+    if file.starts_with('/') || dir.is_empty() {
+        file.to_string()
+    } else {
+        format!("{}/{}", dir.trim_end_matches('/'), file)
+    }
+}
+
+/// The string sections referred to by the line number program headers.
+struct Strings<'a> {
+    line_str: &'a [u8],
+    str: &'a [u8],
+}
+
+impl Strings<'_> {
+    /// Read the attribute of `form` as a string or a number.
+    fn attribute(
+        &self,
+        reader: &mut Reader,
+        form: u64,
+        offset_size: usize,
+    ) -> Result<(Option<String>, u64), RError> {
+        Ok(match form {
+            DW_FORM_STRING => (Some(reader.string()?), 0),
+            DW_FORM_LINE_STRP => (
+                Some(string_at(self.line_str, reader.uint(offset_size)?)?),
+                0,
+            ),
+            DW_FORM_STRP => (Some(string_at(self.str, reader.uint(offset_size)?)?), 0),
+            DW_FORM_UDATA => (None, reader.uleb128()?),
+            DW_FORM_DATA1 => (None, reader.uint(1)?),
+            DW_FORM_DATA2 => (None, reader.uint(2)?),
+            DW_FORM_DATA4 => (None, reader.uint(4)?),
+            DW_FORM_DATA8 => (None, reader.uint(8)?),
+            DW_FORM_DATA16 => {
+                reader.bytes(16)?;
+                (None, 0)
+            }
+            DW_FORM_BLOCK => {
+                let len = reader.uleb128()? as usize;
+                reader.bytes(len)?;
+                (None, 0)
+            }
+            _ => return Err(invalid(&format!("unsupported form {:#x}", form))),
+        })
+    }
+
+    /// DWARF 5 directory or file name table: each entry has the
+    /// attributes listed by the entry format.
+    fn entries(
+        &self,
+        reader: &mut Reader,
+        offset_size: usize,
+    ) -> Result<Vec<(String, u64)>, RError> {
+        let format_count = reader.u8()?;
+        let mut format = vec![];
+        for _ in 0..format_count {
+            format.push((reader.uleb128()?, reader.uleb128()?));
+        }
+        let count = reader.uleb128()?;
+        let mut entries = vec![];
+        for _ in 0..count {
+            let mut path = String::new();
+            let mut dir = 0;
+            for (content, form) in format.iter() {
+                let (string, value) = self.attribute(reader, *form, offset_size)?;
+                match *content {
+                    DW_LNCT_PATH => path = string.unwrap_or_default(),
+                    DW_LNCT_DIRECTORY_INDEX => dir = value,
+                    _ => (),
+                }
+            }
+            entries.push((path, dir));
+        }
+        Ok(entries)
+    }
+}
+
+/// Registers of the line number program state machine.
+struct Registers {
+    addr: u64,
+    file: u64,
+    line: i64,
+    is_stmt: bool,
+}
+
+impl LineTable {
+    pub fn parse(debug_line: &[u8], line_str: &[u8], str: &[u8]) -> Result<LineTable, RError> {
+        let strings = Strings { line_str, str };
+        let mut table = LineTable::default();
+        let mut reader = Reader::new(debug_line);
+        while !reader.is_empty() {
+            table.parse_unit(&mut reader, &strings)?;
+        }
+        // sequences may come in any order, the end of one sorts before a
+        // sequence starting at the same address
+        table.rows.sort_by_key(|row| (row.addr, !row.end_sequence));
+        Ok(table)
+    }
+
+    /// The line table of `elf`, empty when it has no debug information.
+    pub fn from_elf(elf: &ELF) -> Result<LineTable, RError> {
+        match elf.section(".debug_line") {
+            Some(debug_line) => LineTable::parse(
+                debug_line,
+                elf.section(".debug_line_str").unwrap_or_default(),
+                elf.section(".debug_str").unwrap_or_default(),
+            ),
+            None => Ok(LineTable::default()),
+        }
+    }
+
+    fn parse_unit(&mut self, reader: &mut Reader, strings: &Strings) -> Result<(), RError> {
+        let (unit_length, offset_size) = match reader.uint(4)? {
+            0xffffffff => (reader.uint(8)?, 8),
+            length => (length, 4),
+        };
+        let end = reader.pos + unit_length as usize;
+        let version = reader.uint(2)?;
+        if !(2..=5).contains(&version) {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        if version >= 5 {
+            // address size and segment selector size
+            reader.bytes(2)?;
+        }
+        let header_length = reader.uint(offset_size)?;
+        let program = reader.pos + header_length as usize;
+        let min_inst_length = reader.u8()? as u64;
+        if version >= 4 {
+            // maximum operations per instruction, only for VLIW
+            reader.u8()?;
+        }
+        let default_is_stmt = reader.u8()? != 0;
+        let line_base = reader.u8()? as i8 as i64;
+        let line_range = reader.u8()?;
+        let opcode_base = reader.u8()?;
+        if line_range == 0 {
+            return Err(invalid("line range is 0"));
+        }
+        let opcode_lengths = reader
+            .bytes(opcode_base.saturating_sub(1) as usize)?
+            .to_vec();
+
+        // file names, indexed by file register values
+        let mut files = vec![];
+        if version >= 5 {
+            let dirs = strings.entries(reader, offset_size)?;
+            for (path, dir) in strings.entries(reader, offset_size)? {
+                let dir = dirs.get(dir as usize).map(|(dir, _)| dir.as_str());
+                files.push(join(dir.unwrap_or_default(), &path));
+            }
+        } else {
+            // directory 0 and file 0 are the compilation directory and file,
+            // only known from .debug_info
+            let mut dirs = vec![String::new()];
+            loop {
+                let dir = reader.string()?;
+                if dir.is_empty() {
+                    break;
+                }
+                dirs.push(dir);
+            }
+            files.push(String::new());
+            loop {
+                let path = reader.string()?;
+                if path.is_empty() {
+                    break;
+                }
+                let dir = reader.uleb128()? as usize;
+                reader.uleb128()?;
+                reader.uleb128()?;
+                files.push(join(dirs.get(dir).map_or("", |dir| dir), &path));
+            }
+        }
+        reader.pos = program;
+
+        let base = self.files.len();
+        let reset = || Registers {
+            addr: 0,
+            file: 1,
+            line: 1,
+            is_stmt: default_is_stmt,
+        };
+        let mut regs = reset();
+        while reader.pos < end {
+            let opcode = reader.u8()?;
+            let mut emit = false;
+            let mut end_sequence = false;
+            if opcode >= opcode_base {
+                let adjusted = (opcode - opcode_base) as u64;
+                regs.addr += adjusted / line_range as u64 * min_inst_length;
+                regs.line += line_base + (adjusted % line_range as u64) as i64;
+                emit = true;
+            } else {
+                match opcode {
+                    0 => {
+                        let len = reader.uleb128()? as usize;
+                        let next = reader.pos + len;
+                        match reader.u8()? {
+                            DW_LNE_END_SEQUENCE => {
+                                emit = true;
+                                end_sequence = true;
+                            }
+                            DW_LNE_SET_ADDRESS => regs.addr = reader.uint(len - 1)?,
+                            DW_LNE_DEFINE_FILE => {
+                                let path = reader.string()?;
+                                files.push(path);
+                            }
+                            _ => (),
+                        }
+                        reader.pos = next;
+                    }
+                    DW_LNS_COPY => emit = true,
+                    DW_LNS_ADVANCE_PC => regs.addr += reader.uleb128()? * min_inst_length,
+                    DW_LNS_ADVANCE_LINE => regs.line += reader.sleb128()?,
+                    DW_LNS_SET_FILE => regs.file = reader.uleb128()?,
+                    DW_LNS_NEGATE_STMT => regs.is_stmt = !regs.is_stmt,
+                    DW_LNS_CONST_ADD_PC => {
+                        let adjusted = (255 - opcode_base) as u64;
+                        regs.addr += adjusted / line_range as u64 * min_inst_length;
+                    }
+                    DW_LNS_FIXED_ADVANCE_PC => regs.addr += reader.uint(2)?,
+                    _ => {
+                        // skip the operands of an opcode without effect on rows
+                        for _ in 0..opcode_lengths[opcode as usize - 1] {
+                            reader.uleb128()?;
+                        }
+                    }
+                }
+            }
+            if emit {
+                self.rows.push(LineRow {
+                    addr: regs.addr,
+                    file: base + regs.file as usize,
+                    line: regs.line as u32,
+                    is_stmt: regs.is_stmt,
+                    end_sequence,
+                });
+            }
+            if end_sequence {
+                regs = reset();
+            }
+        }
+        self.files.extend(files);
+        reader.pos = end;
+        Ok(())
+    }
+
+    /// The row covering `addr`, if any.
+    pub fn row(&self, addr: u64) -> Option<&LineRow> {
+        let index = self.rows.partition_point(|row| row.addr <= addr);
+        let row = self.rows.get(index.checked_sub(1)?)?;
+        (!row.end_sequence).then_some(row)
+    }
+
+    /// File and line of the instruction at `addr`.
+    pub fn locate(&self, addr: u64) -> Option<(&str, u32)> {
+        let row = self.row(addr)?;
+        Some((self.files.get(row.file)?.as_str(), row.line))
+    }
+
+    /// Whether `addr` starts a statement of a line, where stepping by
+    /// line stops.
+    pub fn is_stmt(&self, addr: u64) -> bool {
+        self.row(addr)
+            .is_some_and(|row| row.addr == addr && row.is_stmt)
+    }
+
+    /// Addresses to break at for `line` of the files ending with `file`:
+    /// the statements of the line, or of the next line having code.
+    pub fn addresses(&self, file: &str, line: u32) -> Vec<u64> {
+        let matches = |index: usize| {
+            self.files.get(index).is_some_and(|path| {
+                path == file || path.ends_with(&format!("/{}", file.trim_start_matches("./")))
+            })
+        };
+        let candidates = self
+            .rows
+            .iter()
+            .filter(|row| !row.end_sequence && row.is_stmt && matches(row.file))
+            .filter(|row| row.line >= line);
+        let Some(best) = candidates.map(|row| row.line).min() else {
+            return vec![];
+        };
+        // the first row of each run of rows of the line
+        self.rows
+            .iter()
+            .enumerate()
+            .filter(|(_, row)| !row.end_sequence && row.is_stmt && row.line == best)
+            .filter(|(_, row)| matches(row.file))
+            .filter(|(i, row)| {
+                *i == 0 || {
+                    let prev = &self.rows[i - 1];
+                    prev.end_sequence || (prev.file, prev.line) != (row.file, row.line)
+                }
+            })
+            .map(|(_, row)| row.addr)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exes::Exe;
+
+    #[test]
+    fn test_leb128() {
+        let bytes = [0xe5, 0x8e, 0x26, 0x7f, 0x80, 0x7f];
+        let mut reader = Reader::new(&bytes);
+        assert_eq!(reader.uleb128().unwrap(), 624485);
+        assert_eq!(reader.sleb128().unwrap(), -1);
+        assert_eq!(reader.sleb128().unwrap(), -128);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_line_table() {
+        let elf = ELF::parse_path("tests/a_32.out").unwrap();
+        let table = LineTable::from_elf(&elf).unwrap();
+        let (file, line) = table.locate(0x13548).unwrap();
+        assert!(file.ends_with("config/riscv/muldi3.S"));
+        assert_eq!(line, 37);
+        assert_eq!(table.locate(0x1354e).unwrap().1, 38);
+        assert_eq!(table.locate(0x1356c).unwrap().1, 69);
+        assert_eq!(table.locate(0x10190), None);
+        assert!(table.is_stmt(0x13550));
+        assert!(!table.is_stmt(0x13552));
+
+        assert_eq!(table.addresses("muldi3.S", 40), vec![0x13550]);
+        // line 39 has no code, the next line does
+        assert_eq!(table.addresses("riscv/muldi3.S", 39), vec![0x13550]);
+        assert_eq!(table.addresses("div.S", 74), vec![0x13574]);
+        assert_eq!(table.addresses("nope.S", 74), vec![]);
+    }
+}
@@ -134,6 +134,18 @@ macro_rules! show_section_headers {
                 name
             }
 
+            /// Contents of section `name`, `None` if missing or without
+            /// file contents like `.bss`.
+            pub fn section(&self, name: &str) -> Option<&[u8]> {
+                let index = (0..self.header.shnum as usize).find(|&i| self.sh_name(i) == name)?;
+                let header = &self.section_headers[index];
+                if header.sh_type == SType::Nobits as u32 {
+                    return None;
+                }
+                let start = header.offset as usize;
+                self.bytes.get(start..start + header.size as usize)
+            }
+
             pub fn show_section_headers(&self) {
                 println!("There are {} section headers, starting at offset 0x{:x}:", self.header.shnum, self.header.shoff);
                 println!("Section Headers:");
@@ -175,6 +187,19 @@ macro_rules! show_symbol_table {
                 name
             }
 
+            pub fn symbol_entries(&self) -> Vec<SymbolEntry> {
+                self.symbols
+                    .iter()
+                    .filter(|symbol| symbol.name != 0 && symbol.shndx != 0 && symbol.shndx < 0xff00)
+                    .map(|symbol| SymbolEntry {
+                        name: self.symbol_name(symbol),
+                        value: symbol.value as u64,
+                        size: symbol.size as u64,
+                        is_func: symbol.info & 0xf == STType::Func as u8,
+                    })
+                    .collect()
+            }
+
             pub fn find_symbol(&self, name: &str) -> Option<u64> {
                 for symbol in self.symbols.iter() {
                     if self.symbol_name(symbol) == name {
@@ -293,7 +318,8 @@ macro_rules! elf_parse {
 
 mod elf32 {
     use super::super::elformat::*;
-    use super::super::Exe;
+    use super::super::{check_segment, Exe};
+    use super::SymbolEntry;
     use crate::error::RError;
     use crate::isas::ISA;
     use crate::util::LinearParse;
@@ -372,6 +398,8 @@ mod elf32 {
         fn load_binary(&mut self, cpu: &mut impl ISA) -> Result<(), RError> {
             for i in 0..self.header.phnum as usize {
                 if self.program_headers[i].p_type == PType::Load as u32 {
+                    let header = &self.program_headers[i];
+                    check_segment(cpu, header.vaddr, header.memsz)?;
                     let mut index = self.program_headers[i].offset as usize;
                     let mut vaddr = self.program_headers[i].vaddr as usize;
                     for _ in 0..self.program_headers[i].filesz {
@@ -392,7 +420,8 @@ mod elf32 {
 mod elf64 {
 
     use super::super::elformat::*;
-    use super::super::Exe;
+    use super::super::{check_segment, Exe};
+    use super::SymbolEntry;
     use crate::error::RError;
     use crate::isas::ISA;
     use crate::util::LinearParse;
@@ -471,6 +500,8 @@ mod elf64 {
         fn load_binary(&mut self, cpu: &mut impl ISA) -> Result<(), RError> {
             for i in 0..self.header.phnum as usize {
                 if self.program_headers[i].p_type == PType::Load as u32 {
+                    let header = &self.program_headers[i];
+                    check_segment(cpu, header.vaddr as u32, header.memsz as u32)?;
                     let mut index = self.program_headers[i].offset as usize;
                     let mut vaddr = self.program_headers[i].vaddr as usize;
                     for _ in 0..self.program_headers[i].filesz {
@@ -501,6 +532,15 @@ macro_rules! enum_add {
     };
 }
 
+/// A defined symbol with its name resolved, whatever the ELF class
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolEntry {
+    pub name: String,
+    pub value: u64,
+    pub size: u64,
+    pub is_func: bool,
+}
+
 enum_add!(show_header);
 enum_add!(show_program_headers);
 enum_add!(show_section_headers);
@@ -517,6 +557,20 @@ impl ELF {
             ELF::ELF64(elf) => elf.find_symbol(name),
         }
     }
+
+    pub fn symbol_entries(&self) -> Vec<SymbolEntry> {
+        match self {
+            ELF::ELF32(elf) => elf.symbol_entries(),
+            ELF::ELF64(elf) => elf.symbol_entries(),
+        }
+    }
+
+    pub fn section(&self, name: &str) -> Option<&[u8]> {
+        match self {
+            ELF::ELF32(elf) => elf.section(name),
+            ELF::ELF64(elf) => elf.section(name),
+        }
+    }
 }
 
 impl Exe for ELF {
@@ -677,4 +731,13 @@ mod tests {
         assert_eq!(ph.flags, 0x6);
         assert_eq!(ph.align, 0x1000);
     }
+
+    #[test]
+    fn test_section() {
+        use super::ELF;
+        let elf = ELF::parse_path("tests/a_32.out").unwrap();
+        assert_eq!(elf.section(".debug_line").map(|s| s.len()), Some(0x1cd));
+        assert_eq!(elf.section(".bss"), None);
+        assert_eq!(elf.section(".nope"), None);
+    }
 }
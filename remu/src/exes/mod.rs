@@ -1,14 +1,29 @@
+mod dwarf;
 mod elf;
 mod elformat;
 mod simplexe;
 
-pub use elf::ELF;
+pub use dwarf::{LineRow, LineTable};
+pub use elf::{SymbolEntry, ELF};
 pub use simplexe::SimpleExe;
 
 use crate::error::RError;
 use crate::isas::ISA;
 use std::{fs::File, io::Read};
 
+/// Check that the `size` bytes at `addr` an image is loaded to are memory,
+/// the first and last at least.
+pub fn check_segment(cpu: &mut impl ISA, addr: u32, size: u32) -> Result<(), RError> {
+    if size == 0 {
+        return Ok(());
+    }
+    let last = addr.checked_add(size - 1).ok_or(RError::InvalidMem(addr))?;
+    for addr in [addr, last] {
+        cpu.peek_mem(addr, 1).ok_or(RError::InvalidMem(addr))?;
+    }
+    Ok(())
+}
+
 pub trait Exe: Sized {
     fn parse(input: &[u8]) -> Result<Self, RError>;
     fn parse_path(path: &str) -> Result<Self, RError> {
@@ -0,0 +1,395 @@
+use super::IO;
+use crate::error::RError;
+use crate::warn;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// sample rate, in Hz
+const FREQ: u64 = 0x00;
+/// number of interleaved channels
+const CHANNELS: u64 = 0x04;
+/// bits per sample, 8 for unsigned bytes or 16 for signed halfwords
+const FORMAT: u64 = 0x08;
+/// size of the stream buffer, read only
+const SBUF_SIZE: u64 = 0x0c;
+/// written with 1 to start playing with the registers above
+const INIT: u64 = 0x10;
+/// bytes queued in the stream buffer, written by the program after it
+/// queues more
+const COUNT: u64 = 0x14;
+/// offset in the stream buffer of the next byte to play, read only
+const HEAD: u64 = 0x18;
+/// bytes of the register block
+pub const REGISTERS: u64 = 0x20;
+
+/// highest sample rate and channel count played, keeping the byte rate
+/// within a u32
+const MAX_FREQ: u32 = 192000;
+const MAX_CHANNELS: u32 = 8;
+
+/// How samples are played.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spec {
+    pub freq: u32,
+    pub channels: u32,
+    pub bits: u32,
+}
+
+/// Where the audio device plays its samples.
+pub trait AudioSink {
+    fn open(&mut self, spec: Spec) -> Result<(), RError>;
+    /// Take what it can of `samples`, returning how many bytes it took.
+    fn play(&mut self, samples: &[u8]) -> usize;
+}
+
+/// Writes the samples played to a WAV file, as fast as they come.
+pub struct WavSink {
+    file: File,
+    /// bytes of samples written
+    data: u32,
+}
+
+impl WavSink {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, RError> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .map_err(|e| RError::IOError(format!("{}: {}", path.display(), e)))?;
+        Ok(WavSink { file, data: 0 })
+    }
+
+    fn header(spec: Spec, data: u32) -> Vec<u8> {
+        let block = spec.channels * spec.bits / 8;
+        let mut header = b"RIFF".to_vec();
+        header.extend((36 + data).to_le_bytes());
+        header.extend(b"WAVEfmt ");
+        header.extend(16_u32.to_le_bytes());
+        // PCM
+        header.extend(1_u16.to_le_bytes());
+        header.extend((spec.channels as u16).to_le_bytes());
+        header.extend(spec.freq.to_le_bytes());
+        header.extend((spec.freq * block).to_le_bytes());
+        header.extend((block as u16).to_le_bytes());
+        header.extend((spec.bits as u16).to_le_bytes());
+        header.extend(b"data");
+        header.extend(data.to_le_bytes());
+        header
+    }
+
+    /// Rewrite the sizes of the header, so the file is whole even if remu
+    /// exits without dropping the sink.
+    fn update_sizes(&mut self) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(36 + self.data).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data.to_le_bytes())?;
+        self.file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+impl AudioSink for WavSink {
+    fn open(&mut self, spec: Spec) -> Result<(), RError> {
+        self.data = 0;
+        self.file
+            .set_len(0)
+            .and_then(|_| self.file.seek(SeekFrom::Start(0)))
+            .and_then(|_| self.file.write_all(&WavSink::header(spec, 0)))
+            .map_err(|e| RError::IOError(e.to_string()))
+    }
+
+    fn play(&mut self, samples: &[u8]) -> usize {
+        let written = self.file.write_all(samples).and_then(|_| {
+            self.data += samples.len() as u32;
+            self.update_sizes()
+        });
+        if let Err(e) = written {
+            warn!("audio: {}", e);
+        }
+        samples.len()
+    }
+}
+
+/// Plays the samples through SDL, taking no more than a quarter of a
+/// second ahead of what is heard.
+#[cfg(feature = "sdl")]
+pub struct SdlSink {
+    subsystem: sdl2::AudioSubsystem,
+    queue: Option<(sdl2::audio::AudioQueue<i16>, Spec)>,
+}
+
+#[cfg(feature = "sdl")]
+impl SdlSink {
+    pub fn new(subsystem: sdl2::AudioSubsystem) -> Self {
+        SdlSink {
+            subsystem,
+            queue: None,
+        }
+    }
+}
+
+#[cfg(feature = "sdl")]
+impl AudioSink for SdlSink {
+    fn open(&mut self, spec: Spec) -> Result<(), RError> {
+        let desired = sdl2::audio::AudioSpecDesired {
+            freq: Some(spec.freq as i32),
+            channels: Some(spec.channels as u8),
+            samples: None,
+        };
+        let queue = self
+            .subsystem
+            .open_queue::<i16, _>(None, &desired)
+            .map_err(RError::Other)?;
+        queue.resume();
+        self.queue = Some((queue, spec));
+        Ok(())
+    }
+
+    fn play(&mut self, samples: &[u8]) -> usize {
+        let Some((queue, spec)) = self.queue.as_ref() else {
+            return samples.len();
+        };
+        // bytes of a quarter of a second, queued as 16-bit samples
+        let ahead = spec.freq * spec.channels * 2 / 4;
+        if queue.size() >= ahead {
+            return 0;
+        }
+        let (samples, taken): (Vec<i16>, usize) = match spec.bits {
+            8 => (
+                samples.iter().map(|s| ((*s as i16) - 128) << 8).collect(),
+                samples.len(),
+            ),
+            _ => (
+                samples
+                    .chunks_exact(2)
+                    .map(|s| i16::from_le_bytes([s[0], s[1]]))
+                    .collect(),
+                samples.len() & !1,
+            ),
+        };
+        if let Err(e) = queue.queue_audio(&samples) {
+            warn!("audio: {}", e);
+        }
+        taken
+    }
+}
+
+/// A sound card as NEMU has it: registers at `ctl`, and a ring of samples
+/// at `sbuf` the program fills behind `head` and tells of through `count`.
+/// Without a sink the samples are dropped as soon as they are queued.
+pub struct Audio {
+    ctl: u64,
+    sbuf: u64,
+    buffer: Vec<u8>,
+    spec: Spec,
+    head: usize,
+    count: usize,
+    sink: Option<Box<dyn AudioSink>>,
+}
+
+impl Audio {
+    pub(crate) fn new(ctl: u64, sbuf: u64, size: u32) -> Self {
+        Audio {
+            ctl,
+            sbuf,
+            buffer: vec![0; size as usize],
+            spec: Spec {
+                freq: 0,
+                channels: 0,
+                bits: 16,
+            },
+            head: 0,
+            count: 0,
+            sink: None,
+        }
+    }
+
+    pub fn set_sink(&mut self, sink: impl AudioSink + 'static) {
+        self.sink = Some(Box::new(sink));
+    }
+
+    /// Hand the sink the queued samples it takes.
+    fn drain(&mut self) {
+        while self.count > 0 {
+            let end = (self.head + self.count).min(self.buffer.len());
+            let samples = &self.buffer[self.head..end];
+            let taken = match self.sink.as_mut() {
+                Some(sink) => sink.play(samples).min(samples.len()),
+                None => samples.len(),
+            };
+            self.head = (self.head + taken) % self.buffer.len();
+            self.count -= taken;
+            if taken < samples.len() {
+                break;
+            }
+        }
+    }
+
+    fn init(&mut self) {
+        self.head = 0;
+        self.count = 0;
+        let spec = self.spec;
+        if !(1..=MAX_FREQ).contains(&spec.freq)
+            || !(1..=MAX_CHANNELS).contains(&spec.channels)
+            || !matches!(spec.bits, 8 | 16)
+        {
+            warn!("audio: cannot play {:?}", spec);
+            return;
+        }
+        if let Some(sink) = self.sink.as_mut() {
+            if let Err(e) = sink.open(spec) {
+                warn!("audio: {}", e);
+            }
+        }
+    }
+}
+
+impl IO for Audio {
+    fn match_(&self, addr: u64) -> bool {
+        (addr >= self.ctl && addr < self.ctl + REGISTERS)
+            || (addr >= self.sbuf && addr < self.sbuf + self.buffer.len() as u64)
+    }
+
+    fn name(&self) -> &str {
+        "audio"
+    }
+
+    fn read(&mut self, addr: u64) -> Option<u32> {
+        if let Some(offset) = addr.checked_sub(self.ctl).filter(|o| *o < REGISTERS) {
+            let register = match offset & !3 {
+                FREQ => self.spec.freq,
+                CHANNELS => self.spec.channels,
+                FORMAT => self.spec.bits,
+                SBUF_SIZE => self.buffer.len() as u32,
+                COUNT => self.count as u32,
+                HEAD => self.head as u32,
+                _ => 0,
+            };
+            return Some(register >> (offset % 4 * 8));
+        }
+        let offset = (addr - self.sbuf) as usize;
+        Some((0..4).fold(0, |word, i| {
+            let byte = self.buffer.get(offset + i).copied().unwrap_or(0);
+            word | (byte as u32) << (i * 8)
+        }))
+    }
+
+    fn write(&mut self, addr: u64, value: u64) {
+        self.write_bytes(addr, 4, value);
+    }
+
+    fn write_bytes(&mut self, addr: u64, bytes: u8, value: u64) {
+        if let Some(offset) = addr.checked_sub(self.ctl).filter(|o| *o < REGISTERS) {
+            let value = value as u32;
+            match offset {
+                FREQ => self.spec.freq = value,
+                CHANNELS => self.spec.channels = value,
+                FORMAT => self.spec.bits = value,
+                INIT if value != 0 => self.init(),
+                COUNT => {
+                    self.count = (value as usize).min(self.buffer.len());
+                    self.drain();
+                }
+                _ => (),
+            }
+            return;
+        }
+        let offset = (addr - self.sbuf) as usize;
+        for i in 0..bytes as usize {
+            if let Some(byte) = self.buffer.get_mut(offset + i) {
+                *byte = (value >> (i * 8)) as u8;
+            }
+        }
+    }
+
+    /// Give a sink that was full another go.
+    fn update(&mut self) {
+        self.drain();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// takes at most `limit` bytes at a time
+    struct Recorder {
+        played: Rc<RefCell<Vec<u8>>>,
+        limit: usize,
+    }
+
+    impl AudioSink for Recorder {
+        fn open(&mut self, spec: Spec) -> Result<(), RError> {
+            assert_eq!(spec.freq, 8000);
+            Ok(())
+        }
+
+        fn play(&mut self, samples: &[u8]) -> usize {
+            let taken = samples.len().min(self.limit);
+            self.played.borrow_mut().extend(&samples[..taken]);
+            taken
+        }
+    }
+
+    #[test]
+    fn test_ring() {
+        let played = Rc::new(RefCell::new(vec![]));
+        let mut audio = Audio::new(0x200, 0x1000, 8);
+        audio.set_sink(Recorder {
+            played: played.clone(),
+            limit: 4,
+        });
+        audio.write(0x200 + FREQ, 8000);
+        audio.write(0x200 + CHANNELS, 1);
+        audio.write(0x200 + FORMAT, 8);
+        audio.write(0x200 + INIT, 1);
+        assert_eq!(audio.read(0x200 + SBUF_SIZE), Some(8));
+
+        audio.write(0x1000, 0x04030201);
+        audio.write_bytes(0x1004, 2, 0x0605);
+        audio.write(0x200 + COUNT, 6);
+        // the sink took 4 bytes, then none
+        assert_eq!(*played.borrow(), vec![1, 2, 3, 4]);
+        assert_eq!(audio.read(0x200 + HEAD), Some(4));
+        assert_eq!(audio.read(0x200 + COUNT), Some(2));
+
+        // the program queues 4 more bytes behind them, wrapping around
+        audio.write_bytes(0x1006, 2, 0x0807);
+        audio.write_bytes(0x1000, 2, 0x0a09);
+        audio.write(0x200 + COUNT, 6);
+        assert_eq!(*played.borrow(), (1..=10).collect::<Vec<u8>>());
+        assert_eq!(audio.read(0x200 + HEAD), Some(2));
+        assert_eq!(audio.read(0x200 + COUNT), Some(0));
+
+        // out of range, the sink is not opened
+        audio.write(0x200 + FREQ, 0x40000000);
+        audio.write(0x200 + INIT, 1);
+        audio.write(0x200 + FREQ, 8000);
+        audio.write(0x200 + CHANNELS, 9);
+        audio.write(0x200 + INIT, 1);
+    }
+
+    #[test]
+    fn test_wav() {
+        let path = std::env::temp_dir().join(format!("remu-audio-{}.wav", std::process::id()));
+        let mut audio = Audio::new(0x200, 0x1000, 16);
+        audio.set_sink(WavSink::create(&path).unwrap());
+        audio.write(0x200 + FREQ, 8000);
+        audio.write(0x200 + CHANNELS, 2);
+        audio.write(0x200 + INIT, 1);
+        audio.write(0x1000, 0x7fff8000);
+        audio.write(0x200 + COUNT, 4);
+        let wav = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[4..8], &40_u32.to_le_bytes());
+        // 8000 Hz, 2 channels of 16 bits
+        assert_eq!(
+            &wav[22..36],
+            &[2, 0, 0x40, 0x1f, 0, 0, 0, 0x7d, 0, 0, 4, 0, 16, 0]
+        );
+        assert_eq!(&wav[40..], &[4, 0, 0, 0, 0x00, 0x80, 0xff, 0x7f]);
+    }
+}
@@ -0,0 +1,322 @@
+use super::IO;
+use crate::error::RError;
+use crate::warn;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const SECTOR_SIZE: usize = 512;
+
+/// written with a command to run on the sectors `SECTOR` and `COUNT` say
+const CMD: u64 = 0x00;
+/// 0 when the last command succeeded, 1 when it failed
+const STATUS: u64 = 0x04;
+/// first sector of the next command
+const SECTOR: u64 = 0x08;
+/// sectors of the next command, at most those of the window
+const COUNT: u64 = 0x0c;
+/// sectors of the disk, read only
+const SECTORS: u64 = 0x10;
+/// bit 0 set when writes fail, read only
+const FLAGS: u64 = 0x14;
+/// offset of the data window, which sectors are read to and written from
+pub const WINDOW: u64 = 0x1000;
+pub const WINDOW_SIZE: usize = 8 * SECTOR_SIZE;
+
+const CMD_READ: u32 = 1;
+const CMD_WRITE: u32 = 2;
+const CMD_FLUSH: u32 = 3;
+
+const STATUS_OK: u32 = 0;
+const STATUS_ERROR: u32 = 1;
+
+/// What writes do to the image backing a disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiskMode {
+    /// written through to the image
+    #[default]
+    ReadWrite,
+    /// refused
+    ReadOnly,
+    /// kept in memory, the image never changing
+    CopyOnWrite,
+}
+
+impl std::str::FromStr for DiskMode {
+    type Err = RError;
+
+    fn from_str(mode: &str) -> Result<Self, RError> {
+        match mode {
+            "rw" | "read-write" => Ok(DiskMode::ReadWrite),
+            "ro" | "read-only" => Ok(DiskMode::ReadOnly),
+            "cow" | "copy-on-write" => Ok(DiskMode::CopyOnWrite),
+            mode => Err(RError::Other(format!(
+                "unknown disk mode {}, expected rw, ro or cow",
+                mode
+            ))),
+        }
+    }
+}
+
+/// A disk of sectors backed by an image file. The program picks sectors
+/// with `SECTOR` and `COUNT`, then writes `CMD` to copy them between the
+/// image and the data window, with no DMA.
+pub struct Disk {
+    base: u64,
+    file: File,
+    mode: DiskMode,
+    /// bytes of the image
+    len: u64,
+    sectors: u32,
+    /// sectors written in copy-on-write mode
+    written: HashMap<u32, Vec<u8>>,
+    window: Vec<u8>,
+    status: u32,
+    sector: u32,
+    count: u32,
+}
+
+impl Disk {
+    pub(crate) fn open(base: u64, path: impl AsRef<Path>, mode: DiskMode) -> Result<Self, RError> {
+        let path = path.as_ref();
+        let error = |e: std::io::Error| RError::IOError(format!("{}: {}", path.display(), e));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(mode == DiskMode::ReadWrite)
+            .open(path)
+            .map_err(error)?;
+        let len = file.metadata().map_err(error)?.len();
+        let sectors = len.div_ceil(SECTOR_SIZE as u64);
+        let sectors = u32::try_from(sectors)
+            .map_err(|_| RError::Other(format!("{}: too large for a disk", path.display())))?;
+        Ok(Disk {
+            base,
+            file,
+            mode,
+            len,
+            sectors,
+            written: HashMap::new(),
+            window: vec![0; WINDOW_SIZE],
+            status: STATUS_OK,
+            sector: 0,
+            count: 0,
+        })
+    }
+
+    fn read_sector(&mut self, sector: u32, buffer: &mut [u8]) -> std::io::Result<()> {
+        if let Some(data) = self.written.get(&sector) {
+            buffer.copy_from_slice(data);
+            return Ok(());
+        }
+        self.file
+            .seek(SeekFrom::Start(sector as u64 * SECTOR_SIZE as u64))?;
+        // the last sector may be short
+        buffer.fill(0);
+        let mut read = 0;
+        loop {
+            match self.file.read(&mut buffer[read..])? {
+                0 => return Ok(()),
+                n => read += n,
+            }
+        }
+    }
+
+    fn write_sector(&mut self, sector: u32, buffer: &[u8]) -> std::io::Result<()> {
+        match self.mode {
+            DiskMode::CopyOnWrite => {
+                self.written.insert(sector, buffer.to_vec());
+                Ok(())
+            }
+            _ => {
+                let offset = sector as u64 * SECTOR_SIZE as u64;
+                // the image keeps its length, the end of a short sector
+                // being dropped
+                let end = (self.len - offset).min(buffer.len() as u64) as usize;
+                self.file.seek(SeekFrom::Start(offset))?;
+                self.file.write_all(&buffer[..end])
+            }
+        }
+    }
+
+    fn command(&mut self, command: u32) -> Result<(), String> {
+        let (sector, count) = (self.sector, self.count as usize);
+        if matches!(command, CMD_READ | CMD_WRITE)
+            && (count * SECTOR_SIZE > WINDOW_SIZE
+                || sector as u64 + count as u64 > self.sectors as u64)
+        {
+            return Err(format!("sectors [{}, +{}) out of range", sector, count));
+        }
+        match command {
+            CMD_READ => {
+                let mut window = std::mem::take(&mut self.window);
+                let result = window
+                    .chunks_exact_mut(SECTOR_SIZE)
+                    .take(count)
+                    .zip(sector..)
+                    .try_for_each(|(buffer, sector)| self.read_sector(sector, buffer));
+                self.window = window;
+                result.map_err(|e| e.to_string())
+            }
+            CMD_WRITE if self.mode == DiskMode::ReadOnly => Err("read-only".to_string()),
+            CMD_WRITE => {
+                let window = std::mem::take(&mut self.window);
+                let result = window
+                    .chunks_exact(SECTOR_SIZE)
+                    .take(count)
+                    .zip(sector..)
+                    .try_for_each(|(buffer, sector)| self.write_sector(sector, buffer));
+                self.window = window;
+                result.map_err(|e| e.to_string())
+            }
+            CMD_FLUSH => self.file.flush().map_err(|e| e.to_string()),
+            command => Err(format!("unknown command {}", command)),
+        }
+    }
+}
+
+impl IO for Disk {
+    fn match_(&self, addr: u64) -> bool {
+        addr >= self.base && addr < self.base + WINDOW + WINDOW_SIZE as u64
+    }
+
+    fn name(&self) -> &str {
+        "disk"
+    }
+
+    fn read(&mut self, addr: u64) -> Option<u32> {
+        let offset = addr - self.base;
+        if offset < WINDOW {
+            let register = match offset & !3 {
+                STATUS => self.status,
+                SECTOR => self.sector,
+                COUNT => self.count,
+                SECTORS => self.sectors,
+                FLAGS => (self.mode == DiskMode::ReadOnly) as u32,
+                _ => 0,
+            };
+            return Some(register >> (offset % 4 * 8));
+        }
+        let offset = (offset - WINDOW) as usize;
+        Some((0..4).fold(0, |word, i| {
+            let byte = self.window.get(offset + i).copied().unwrap_or(0);
+            word | (byte as u32) << (i * 8)
+        }))
+    }
+
+    fn write(&mut self, addr: u64, value: u64) {
+        self.write_bytes(addr, 4, value);
+    }
+
+    fn write_bytes(&mut self, addr: u64, bytes: u8, value: u64) {
+        let offset = addr - self.base;
+        if offset < WINDOW {
+            let value = value as u32;
+            match offset {
+                CMD => {
+                    self.status = match self.command(value) {
+                        Ok(()) => STATUS_OK,
+                        Err(e) => {
+                            warn!("disk: {}", e);
+                            STATUS_ERROR
+                        }
+                    }
+                }
+                SECTOR => self.sector = value,
+                COUNT => self.count = value,
+                _ => (),
+            }
+            return;
+        }
+        let offset = (offset - WINDOW) as usize;
+        for i in 0..bytes as usize {
+            if let Some(byte) = self.window.get_mut(offset + i) {
+                *byte = (value >> (i * 8)) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// an image of three sectors, each filled with its number, the last
+    /// one short
+    fn image(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("remu-{}-{}.img", name, std::process::id()));
+        let mut bytes = vec![0_u8; SECTOR_SIZE * 2 + 100];
+        for (i, chunk) in bytes.chunks_mut(SECTOR_SIZE).enumerate() {
+            chunk.fill(i as u8);
+        }
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn run(disk: &mut Disk, command: u32, sector: u32, count: u32) -> u32 {
+        disk.write(SECTOR, sector as u64);
+        disk.write(COUNT, count as u64);
+        disk.write(CMD, command as u64);
+        disk.read(STATUS).unwrap()
+    }
+
+    #[test]
+    fn test_disk() {
+        let path = image("disk");
+        let mut disk = Disk::open(0, &path, DiskMode::ReadWrite).unwrap();
+        assert_eq!(disk.read(SECTORS), Some(3));
+
+        assert_eq!(run(&mut disk, CMD_READ, 1, 2), STATUS_OK);
+        assert_eq!(disk.read(WINDOW), Some(0x01010101));
+        assert_eq!(disk.read(WINDOW + 512 + 96), Some(0x02020202));
+        // past the end of the image
+        assert_eq!(disk.read(WINDOW + 512 + 100), Some(0));
+        assert_eq!(run(&mut disk, CMD_READ, 2, 2), STATUS_ERROR);
+        assert_eq!(run(&mut disk, CMD_READ, 0, 9), STATUS_ERROR);
+
+        disk.write_bytes(WINDOW, 1, 0xff);
+        assert_eq!(run(&mut disk, CMD_WRITE, 0, 1), STATUS_OK);
+        assert_eq!(run(&mut disk, CMD_FLUSH, 0, 0), STATUS_OK);
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[..2], &[0xff, 1]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_short_sector() {
+        let path = std::env::temp_dir().join(format!("remu-short-{}.img", std::process::id()));
+        std::fs::write(&path, [7_u8; 700]).unwrap();
+        let mut disk = Disk::open(0, &path, DiskMode::ReadWrite).unwrap();
+        assert_eq!(disk.read(SECTORS), Some(2));
+        assert_eq!(run(&mut disk, CMD_READ, 1, 1), STATUS_OK);
+        assert_eq!(disk.read(WINDOW + 186), Some(0x0707));
+        disk.write(WINDOW + 184, 0x01020304);
+        disk.write(WINDOW + 188, 0x05060708);
+        assert_eq!(run(&mut disk, CMD_WRITE, 1, 1), STATUS_OK);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bytes.len(), 700);
+        assert_eq!(&bytes[696..], &[4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_modes() {
+        let path = image("modes");
+        let mut disk = Disk::open(0, &path, DiskMode::ReadOnly).unwrap();
+        assert_eq!(disk.read(FLAGS), Some(1));
+        assert_eq!(run(&mut disk, CMD_WRITE, 0, 1), STATUS_ERROR);
+
+        let mut disk = Disk::open(0, &path, DiskMode::CopyOnWrite).unwrap();
+        assert_eq!(disk.read(FLAGS), Some(0));
+        assert_eq!(run(&mut disk, CMD_READ, 0, 1), STATUS_OK);
+        disk.write(WINDOW, 0xdeadbeef);
+        assert_eq!(run(&mut disk, CMD_WRITE, 0, 1), STATUS_OK);
+        disk.write(WINDOW, 0);
+        assert_eq!(run(&mut disk, CMD_READ, 0, 2), STATUS_OK);
+        assert_eq!(disk.read(WINDOW), Some(0xdeadbeef));
+        assert_eq!(disk.read(WINDOW + 512), Some(0x01010101));
+        // the image is untouched
+        assert_eq!(&std::fs::read(&path).unwrap()[..4], &[0; 4]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+//! Encoders of frames to image files, pixels being `0x00RRGGBB`.
+
+fn rgb(pixel: u32) -> [u8; 3] {
+    [(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8]
+}
+
+/// A binary PPM (P6) image.
+pub fn ppm(width: usize, height: usize, pixels: &[u32]) -> Vec<u8> {
+    let mut image = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    image.extend(pixels.iter().flat_map(|pixel| rgb(*pixel)));
+    image
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for byte in bytes {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn chunk(image: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    image.extend((data.len() as u32).to_be_bytes());
+    let start = image.len();
+    image.extend(kind);
+    image.extend(data);
+    let crc = crc32(&image[start..]);
+    image.extend(crc.to_be_bytes());
+}
+
+/// An RGB PNG image, stored without compression.
+pub fn png(width: usize, height: usize, pixels: &[u32]) -> Vec<u8> {
+    let mut image = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut header = vec![];
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, no interlace
+    header.extend([8, 2, 0, 0, 0]);
+    chunk(&mut image, b"IHDR", &header);
+
+    // rows start with filter type 0, none
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in pixels.chunks(width.max(1)).take(height) {
+        raw.push(0);
+        raw.extend(row.iter().flat_map(|pixel| rgb(*pixel)));
+    }
+    // a zlib stream of stored deflate blocks
+    let mut data = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        data.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        data.push(blocks.peek().is_none() as u8);
+        data.extend((block.len() as u16).to_le_bytes());
+        data.extend((!(block.len() as u16)).to_le_bytes());
+        data.extend(block);
+    }
+    data.extend(adler32(&raw).to_be_bytes());
+    chunk(&mut image, b"IDAT", &data);
+    chunk(&mut image, b"IEND", &[]);
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image() {
+        let pixels = [0xff0000, 0x00ff00, 0x0000ff, 0x123456];
+        let image = ppm(2, 2, &pixels);
+        assert!(image.starts_with(b"P6\n2 2\n255\n"));
+        assert!(image.ends_with(&[255, 0, 0, 0, 255, 0, 0, 0, 255, 0x12, 0x34, 0x56]));
+
+        assert_eq!(crc32(b"IEND"), 0xae426082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        let image = png(2, 2, &pixels);
+        assert!(image.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x02\0\0\0\x02"));
+        assert!(image.ends_with(b"\0\0\0\0IEND\xae\x42\x60\x82"));
+        // two rows of a filter byte and two pixels in one final block
+        let data = &image[33 + 8..];
+        assert_eq!(&data[..7], &[0x78, 0x01, 1, 14, 0, !14, 0xff]);
+        assert_eq!(&data[7..14], &[0, 255, 0, 0, 0, 255, 0]);
+    }
+}
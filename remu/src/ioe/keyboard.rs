@@ -1,4 +1,5 @@
 use super::IO;
+#[cfg(feature = "sdl")]
 use sdl2::event::Event;
 
 #[derive(Debug, PartialEq)]
@@ -131,12 +132,17 @@ impl Default for Keyboard {
 }
 
 impl Keyboard {
-    fn new(base: u64) -> Self {
+    pub(crate) fn new(base: u64) -> Self {
         Self {
             base,
             events: Vec::new(),
         }
     }
+
+    /// Queue `event` for the program to read.
+    pub fn push(&mut self, event: KBEvent) {
+        self.events.push(event);
+    }
 }
 
 impl IO for Keyboard {
@@ -167,6 +173,7 @@ impl IO for Keyboard {
     }
 }
 
+#[cfg(feature = "sdl")]
 fn sdlcode2u32(sdlcode: sdl2::keyboard::Keycode) -> Key {
     use sdl2::keyboard::Keycode::*;
     match sdlcode {
@@ -227,6 +234,7 @@ fn sdlcode2u32(sdlcode: sdl2::keyboard::Keycode) -> Key {
     }
 }
 
+#[cfg(feature = "sdl")]
 impl From<Event> for KBEvent {
     fn from(event: Event) -> Self {
         match event {
@@ -253,9 +261,6 @@ impl From<Event> for KBEvent {
 
 #[cfg(test)]
 mod tests {
-    use sdl2::event::Event;
-
-    use crate::ioe::keyboard::{KBEvent, Key};
     use crate::ioe::IO;
 
     use super::super::KBD_ADDR;
@@ -266,6 +271,13 @@ mod tests {
         kbd.write(KBD_ADDR, 0x1C);
         assert_eq!(kbd.read(KBD_ADDR), Some(0x1C));
         assert_eq!(kbd.read(KBD_ADDR), Some(0));
+    }
+
+    #[cfg(feature = "sdl")]
+    #[test]
+    fn test_kbd_sdl() {
+        use crate::ioe::keyboard::{KBEvent, Key};
+        use sdl2::event::Event;
 
         let event: Event = Event::KeyDown {
             keycode: Some(sdl2::keyboard::Keycode::A),
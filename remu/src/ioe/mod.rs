@@ -1,21 +1,30 @@
-#[cfg(feature = "sdl")]
+pub mod audio;
+pub mod disk;
+mod image;
 pub mod keyboard;
+pub mod replay;
 mod serial;
 mod timer;
-#[cfg(feature = "sdl")]
 mod vga;
 
-#[cfg(feature = "sdl")]
+pub use audio::Audio;
+pub use disk::{Disk, DiskMode};
 pub use keyboard::Keyboard;
-pub(crate) use serial::SerialPort;
+pub use serial::{capture_serial, take_serial, SerialPort};
 pub(crate) use timer::Timer;
-#[cfg(feature = "sdl")]
 pub use vga::Screen;
 
-pub trait IO {
+/// A device mapped in memory. Being `Any`, a `dyn IO` downcasts to the
+/// device it is.
+pub trait IO: std::any::Any {
     fn match_(&self, addr: u64) -> bool;
     fn read(&mut self, addr: u64) -> Option<u32>;
     fn write(&mut self, addr: u64, value: u64);
+    /// Write the low `bytes` bytes of `value`, for devices telling stores
+    /// of different sizes apart.
+    fn write_bytes(&mut self, addr: u64, _bytes: u8, value: u64) {
+        self.write(addr, value);
+    }
     fn update(&mut self) {
         // do nothing by default
     }
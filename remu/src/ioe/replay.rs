@@ -0,0 +1,225 @@
+//! Record and replay of device reads.
+//!
+//! Every value the guest reads from a device is logged together with the
+//! instruction count at which the read happened: not only the
+//! nondeterministic inputs (serial input, keyboard events, timer) but also
+//! the registers of the VGA, audio and disk devices and the disk data
+//! window, whose contents come from device stores that are skipped on
+//! replay. Feeding the log back makes a captured session run identically
+//! without a terminal, SDL window or disk image, at the cost of logging
+//! whole sectors of disk traffic.
+//!
+//! The log is a plain text file, one event per line:
+//!
+//! ```text
+//! <icount> <device> <addr> <value>
+//! 1024 timer 0xa0000048 0x5f3a2c10
+//! ```
+
+use super::IO;
+use crate::error::RError;
+use crate::warn;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputEvent {
+    /// number of instructions retired before the read
+    pub icount: u64,
+    pub device: String,
+    pub addr: u32,
+    pub value: u32,
+}
+
+impl Display for InputEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {:#x} {:#x}",
+            self.icount, self.device, self.addr, self.value
+        )
+    }
+}
+
+impl InputEvent {
+    fn parse(line: &str) -> Result<Self, RError> {
+        let invalid = || RError::IOError(format!("invalid input log line: {}", line));
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        if tokens.len() != 4 {
+            return Err(invalid());
+        }
+        let hex = |s: &str| u32::from_str_radix(s.trim_start_matches("0x"), 16);
+        Ok(InputEvent {
+            icount: tokens[0].parse().map_err(|_| invalid())?,
+            device: tokens[1].to_string(),
+            addr: hex(tokens[2]).map_err(|_| invalid())?,
+            value: hex(tokens[3]).map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Log of device reads, both for recording and replaying.
+///
+/// Events before `cursor` have already been consumed by the guest. A read
+/// with `cursor` inside the log is answered from the log; a read at the end
+/// of the log goes to the device and is appended, unless the log is
+/// replay-only.
+pub struct InputLog {
+    events: Vec<InputEvent>,
+    cursor: usize,
+    /// whether reads past the end of the log may reach the real device
+    live: bool,
+    sink: Option<Box<dyn Write>>,
+}
+
+impl Default for InputLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputLog {
+    /// An empty log that records every device read.
+    pub fn new() -> Self {
+        InputLog {
+            events: Vec::new(),
+            cursor: 0,
+            live: true,
+            sink: None,
+        }
+    }
+
+    /// Record into memory and also append every new event to `sink`.
+    pub fn record(sink: impl Write + 'static) -> Self {
+        InputLog {
+            sink: Some(Box::new(sink)),
+            ..Self::new()
+        }
+    }
+
+    /// Load a previously recorded log, which is then replayed exactly.
+    pub fn replay(input: impl Read) -> Result<Self, RError> {
+        let mut events = Vec::new();
+        for line in BufReader::new(input).lines() {
+            let line = line.map_err(|e| RError::IOError(e.to_string()))?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            events.push(InputEvent::parse(line)?);
+        }
+        Ok(InputLog {
+            events,
+            cursor: 0,
+            live: false,
+            sink: None,
+        })
+    }
+
+    pub fn record_path(path: &str) -> Result<Self, RError> {
+        let file = File::create(path).map_err(|e| RError::IOError(e.to_string()))?;
+        Ok(Self::record(BufWriter::new(file)))
+    }
+
+    pub fn replay_path(path: &str) -> Result<Self, RError> {
+        let file = File::open(path).map_err(|e| RError::IOError(e.to_string()))?;
+        Self::replay(file)
+    }
+
+    pub fn events(&self) -> &[InputEvent] {
+        &self.events
+    }
+
+    /// whether every recorded event has been fed back to the guest
+    pub fn finished(&self) -> bool {
+        self.cursor == self.events.len()
+    }
+
+    /// Move the replay position back so that reads from `icount` on are
+    /// answered from the log again.
+    pub fn rewind(&mut self, icount: u64) {
+        self.cursor = self.events.partition_point(|e| e.icount < icount);
+    }
+
+    /// Drop the events before `icount`, which will not be replayed again.
+    pub fn forget(&mut self, icount: u64) {
+        let count = self.events.partition_point(|e| e.icount < icount);
+        self.events.drain(..count);
+        self.cursor = self.cursor.saturating_sub(count);
+    }
+
+    /// Serve a guest read of `addr` from `device`, logging it whatever the
+    /// device is.
+    pub(crate) fn read(&mut self, icount: u64, device: &mut dyn IO, addr: u32) -> Option<u32> {
+        if let Some(event) = self.events.get(self.cursor) {
+            if event.icount != icount || event.addr != addr {
+                warn!(
+                    "replay diverged at instruction {}: expected read of {:#x} at {}, got {:#x}",
+                    icount, event.addr, event.icount, addr
+                );
+                return None;
+            }
+            self.cursor += 1;
+            return Some(event.value);
+        }
+        if !self.live {
+            warn!("replay log exhausted at instruction {}", icount);
+            return None;
+        }
+        let value = device.read(addr as u64)?;
+        let event = InputEvent {
+            icount,
+            device: device.name().to_string(),
+            addr,
+            value,
+        };
+        if let Some(sink) = self.sink.as_mut() {
+            if let Err(e) = writeln!(sink, "{}", event).and_then(|_| sink.flush()) {
+                warn!("failed to write input log: {}", e);
+            }
+        }
+        self.events.push(event);
+        self.cursor += 1;
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ioe::TIMER_ADDR;
+    use crate::isas::{MemoryModel, RV32CPU};
+
+    #[test]
+    fn test_record_replay() {
+        let mut cpu = RV32CPU::default();
+        cpu.set_input_log(InputLog::new());
+        let first = cpu.load_mem(TIMER_ADDR as u32, 4).unwrap();
+        let second = cpu.load_mem(TIMER_ADDR as u32 + 4, 4).unwrap();
+        let log = cpu.take_input_log().unwrap();
+        assert_eq!(log.events().len(), 2);
+        assert_eq!(log.events()[0].device, "timer");
+
+        let mut text = Vec::new();
+        for event in log.events() {
+            writeln!(text, "{}", event).unwrap();
+        }
+        let mut replayed = RV32CPU::default();
+        replayed.set_input_log(InputLog::replay(text.as_slice()).unwrap());
+        assert_eq!(replayed.load_mem(TIMER_ADDR as u32, 4), Some(first));
+        assert_eq!(replayed.load_mem(TIMER_ADDR as u32 + 4, 4), Some(second));
+        // the log is exhausted, further reads must not reach the host clock
+        assert_eq!(replayed.load_mem(TIMER_ADDR as u32, 4), None);
+    }
+
+    #[test]
+    fn test_parse_event() {
+        let event = InputEvent::parse("1024 timer 0xa0000048 0x5f3a2c10").unwrap();
+        assert_eq!(event.icount, 1024);
+        assert_eq!(event.addr, 0xa0000048);
+        assert_eq!(event.value, 0x5f3a2c10);
+        assert_eq!(event.to_string(), "1024 timer 0xa0000048 0x5f3a2c10");
+        assert!(InputEvent::parse("1024 timer").is_err());
+    }
+}
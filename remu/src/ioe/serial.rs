@@ -1,12 +1,38 @@
 use super::IO;
+use crate::error::RError;
 use console::Term;
-use std::io::Write;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
 
-#[derive(Debug)]
-pub(crate) struct SerialPort {
+/// what the serial port writes, kept instead of printed while captured
+static CAPTURED: Mutex<Option<String>> = Mutex::new(None);
+
+/// Keep the output of the serial port for `take_serial` instead of
+/// printing it, or go back to printing it.
+pub fn capture_serial(capture: bool) {
+    *CAPTURED.lock().unwrap() = capture.then(String::new);
+}
+
+/// Output of the serial port captured since the last call.
+pub fn take_serial() -> String {
+    CAPTURED
+        .lock()
+        .unwrap()
+        .as_mut()
+        .map(std::mem::take)
+        .unwrap_or_default()
+}
+
+pub struct SerialPort {
     base: u64,
     irq: u8,
     term: Term,
+    /// file read instead of the terminal, -1 being read at its end
+    input: Option<BufReader<File>>,
+    /// callback written to instead of the terminal
+    output: Option<Box<dyn FnMut(u8)>>,
 }
 
 impl SerialPort {
@@ -15,8 +41,25 @@ impl SerialPort {
             base,
             irq,
             term: Term::stdout(),
+            input: None,
+            output: None,
         }
     }
+
+    /// Hand each byte written to `output` instead of printing it.
+    pub fn set_output(&mut self, output: impl FnMut(u8) + 'static) {
+        self.output = Some(Box::new(output));
+    }
+
+    /// A serial port at `base` reading `path` rather than the terminal.
+    pub(crate) fn with_input(base: u64, path: &Path) -> Result<Self, RError> {
+        let file =
+            File::open(path).map_err(|e| RError::IOError(format!("{}: {}", path.display(), e)))?;
+        Ok(Self {
+            input: Some(BufReader::new(file)),
+            ..Self::new(base, 4)
+        })
+    }
 }
 
 impl Default for SerialPort {
@@ -35,10 +78,25 @@ impl IO for SerialPort {
     }
 
     fn read(&mut self, _addr: u64) -> Option<u32> {
+        if let Some(input) = self.input.as_mut() {
+            let mut byte = [0];
+            return match input.read_exact(&mut byte) {
+                Ok(()) => Some(byte[0] as u32),
+                Err(_) => Some(u32::MAX),
+            };
+        }
         self.term.read_char().ok().map(|c| c as u32)
     }
 
     fn write(&mut self, _addr: u64, value: u64) {
+        if let Some(captured) = CAPTURED.lock().unwrap().as_mut() {
+            captured.push(value as u8 as char);
+            return;
+        }
+        if let Some(output) = self.output.as_mut() {
+            output(value as u8);
+            return;
+        }
         print!("{}", value as u8 as char);
         // TODO: maybe influence performance
         std::io::stdout().flush().unwrap();
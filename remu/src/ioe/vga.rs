@@ -1,59 +1,209 @@
-use super::IO;
+use super::{image, IO};
+use crate::error::RError;
+use crate::warn;
+use std::path::{Path, PathBuf};
 
-// 400x300x32, every pixel is 4 bytes by red, green, blue, alpha
+/// control register holding `width << 16 | height`
+const SIZE: u64 = 0;
+/// control register written with 1 when a frame is complete
+const SYNC: u64 = 4;
+
+/// A frame buffer of `width` x `height` pixels, row by row, each a
+/// little endian word `0x00RRGGBB`, so blue is the byte at the lowest
+/// address. Any byte, halfword or word of it can be read and written.
 pub struct Screen {
+    /// address of the control registers
+    ctl: u64,
     address: u64,
     width: usize,
     height: usize,
     buffer: Vec<u32>,
-    // canvas: sdl2::render::Canvas<sdl2::video::Window>,
+    /// frames synced so far
+    frames: u64,
+    /// where to save synced frames, and one out of how many
+    dump: Option<(PathBuf, u64)>,
 }
 
 impl Default for Screen {
     fn default() -> Self {
-        Self::new(super::VGA_ADDR, 400, 300)
+        Self::new(super::VGACTL_ADDR, super::VGA_ADDR, 400, 300)
     }
 }
 
 impl Screen {
-    fn new(address: u64, width: u32, height: u32) -> Self {
+    pub(crate) fn new(ctl: u64, address: u64, width: u32, height: u32) -> Self {
         Screen {
+            ctl,
             address,
             width: width as usize,
             height: height as usize,
             buffer: vec![0; width as usize * height as usize],
-            // canvas,
+            frames: 0,
+            dump: None,
+        }
+    }
+
+    /// Save one synced frame out of `every` to `path`, where `{}` stands
+    /// for the frame number.
+    pub fn dump_frames(&mut self, path: impl Into<PathBuf>, every: u64) {
+        self.dump = Some((path.into(), every.max(1)));
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The pixels, row by row.
+    pub fn pixels(&self) -> &[u32] {
+        &self.buffer
+    }
+
+    /// number of frames the program synced
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Save the frame to `path`, a PNG image if it ends with `.png` and a
+    /// PPM image otherwise.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RError> {
+        let path = path.as_ref();
+        let image = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("png") => image::png(self.width, self.height, &self.buffer),
+            _ => image::ppm(self.width, self.height, &self.buffer),
+        };
+        std::fs::write(path, image)
+            .map_err(|e| RError::IOError(format!("{}: {}", path.display(), e)))
+    }
+
+    fn sync(&mut self) {
+        self.frames += 1;
+        let Some((path, every)) = &self.dump else {
+            return;
+        };
+        if self.frames.is_multiple_of(*every) {
+            let path = path
+                .to_string_lossy()
+                .replace("{}", &self.frames.to_string());
+            if let Err(e) = self.save(path) {
+                warn!("{}", e);
+            }
         }
     }
 
     fn size(&self) -> u64 {
         self.width as u64 * self.height as u64
     }
+
+    /// the byte at `offset` in the frame buffer, 0 past its end
+    fn byte(&self, offset: usize) -> u8 {
+        self.buffer
+            .get(offset / 4)
+            .map_or(0, |pixel| (pixel >> (offset % 4 * 8)) as u8)
+    }
+
+    fn set_byte(&mut self, offset: usize, byte: u8) {
+        if let Some(pixel) = self.buffer.get_mut(offset / 4) {
+            let shift = offset % 4 * 8;
+            *pixel = (*pixel & !(0xff << shift)) | ((byte as u32) << shift);
+        }
+    }
 }
 
 impl IO for Screen {
     fn match_(&self, addr: u64) -> bool {
-        addr >= self.address && addr < self.address + self.size() * 4
+        (addr >= self.ctl && addr < self.ctl + 8)
+            || (addr >= self.address && addr < self.address + self.size() * 4)
     }
 
     fn name(&self) -> &'static str {
         "vga"
     }
 
+    /// The word at `addr`, of which loads keep the bytes they need.
     fn read(&mut self, addr: u64) -> Option<u32> {
-        let offset = if addr < self.address {
-            addr
-        } else {
-            addr - self.address - self.size()
-        };
-        let offset = offset as usize;
-        Some(self.buffer[offset])
+        if let Some(offset) = addr.checked_sub(self.ctl).filter(|offset| *offset < 8) {
+            let register = match offset & !3 {
+                SIZE => ((self.width as u32) << 16) | self.height as u32,
+                _ => 0,
+            };
+            return Some(register >> (offset % 4 * 8));
+        }
+        let offset = (addr - self.address) as usize;
+        Some((0..4).fold(0, |word, i| {
+            word | (self.byte(offset + i) as u32) << (i * 8)
+        }))
     }
 
     fn write(&mut self, addr: u64, value: u64) {
-        let offset = (addr - self.address) / 4;
-        let offset = offset as usize;
-        let value = value as u32;
-        self.buffer[offset] = value;
+        self.write_bytes(addr, 4, value);
+    }
+
+    fn write_bytes(&mut self, addr: u64, bytes: u8, value: u64) {
+        if let Some(offset) = addr.checked_sub(self.ctl).filter(|offset| *offset < 8) {
+            if offset == SYNC && value != 0 {
+                self.sync();
+            }
+            return;
+        }
+        let offset = (addr - self.address) as usize;
+        for i in 0..bytes as usize {
+            self.set_byte(offset + i, (value >> (i * 8)) as u8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control() {
+        let mut screen = Screen::new(0x100, 0x1000, 4, 3);
+        assert!(screen.match_(0x104) && screen.match_(0x1000 + 4 * 12 - 1));
+        assert!(!screen.match_(0x108) && !screen.match_(0x1000 + 4 * 12));
+        assert_eq!(screen.read(0x100), Some(4 << 16 | 3));
+
+        let dir = std::env::temp_dir().join(format!("remu-vga-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        screen.dump_frames(dir.join("frame{}.ppm"), 2);
+        screen.write(0x1004, 0xff0000);
+        screen.write(0x104, 1);
+        screen.write(0x104, 0);
+        assert_eq!(screen.frames(), 1);
+        assert!(!dir.join("frame1.ppm").exists());
+        screen.write(0x104, 1);
+        let image = std::fs::read(dir.join("frame2.ppm")).unwrap();
+        assert_eq!(&image[11..17], &[0, 0, 0, 255, 0, 0]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_frame_buffer() {
+        let mut screen = Screen::new(0x100, 0x1000, 4, 3);
+        screen.write(0x1004, 0x00123456);
+        assert_eq!(screen.pixels()[1], 0x00123456);
+        assert_eq!(screen.read(0x1004), Some(0x00123456));
+        // blue is the lowest byte, red the third
+        assert_eq!(screen.read(0x1004).map(|word| word as u8), Some(0x56));
+        assert_eq!(screen.read(0x1006).map(|word| word as u8), Some(0x12));
+
+        screen.write_bytes(0x1006, 1, 0xff);
+        screen.write_bytes(0x1004, 2, 0xabcd);
+        assert_eq!(screen.pixels()[1], 0x00ffabcd);
+        // a misaligned word spans two pixels
+        screen.write_bytes(0x100a, 4, 0x11223344);
+        assert_eq!(&screen.pixels()[2..4], &[0x33440000, 0x1122]);
+        assert_eq!(screen.read(0x100a), Some(0x11223344));
+        // the last byte reads 0 past the end
+        screen.write(0x1000 + 4 * 11, 0xffffffff);
+        assert_eq!(screen.read(0x1000 + 4 * 12 - 1), Some(0xff));
+
+        assert_eq!(screen.read(0x102), Some(4));
+        screen.write_bytes(0x100, 4, 0);
+        assert_eq!(screen.read(0x100), Some(4 << 16 | 3));
     }
 }
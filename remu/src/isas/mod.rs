@@ -1,9 +1,9 @@
 pub(crate) mod riscv;
 
 use crate::error::RError;
-pub use riscv::RV32CPU;
+pub use riscv::{PrivilegeMode, RV32CPU};
 
-pub trait ISA: MemoryModel + RegisterModel + Sized {
+pub trait ISA: MemoryModel + RegisterModel + TimeTravel + Sized {
     fn name(&self) -> String;
 
     /// decide whether 32bit or 64bit
@@ -38,16 +38,106 @@ pub trait ISA: MemoryModel + RegisterModel + Sized {
 
     fn disassemble(&mut self, addr: u32) -> Result<String, RError>;
 
+    /// Target of the pc-relative branch or jump at `addr`, if it is one.
+    fn jump_target(&mut self, _addr: u32) -> Option<u32> {
+        None
+    }
+
+    /// Whether the instruction at `addr` calls a function, that is jumps
+    /// and links a return address.
+    fn is_call(&mut self, _addr: u32) -> bool {
+        false
+    }
+
     fn execute(&mut self, inst_code: u32) -> Result<u32, RError>;
 
+    /// Privilege level of the hart, from 0 for user mode to 3 for machine
+    /// mode.
+    fn privilege(&self) -> u8 {
+        3
+    }
+
+    /// The trap raised since the last call, either taken by jumping to its
+    /// handler or stopping the machine before the faulting instruction.
+    fn take_trap(&mut self) -> Option<Trap> {
+        None
+    }
+
     fn priviledge_level_down(&mut self);
 
     fn priviledge_level_up(&mut self);
 }
 
+/// Moving the machine back to an earlier point of its execution.
+pub trait TimeTravel {
+    /// number of instructions retired so far
+    fn icount(&self) -> u64;
+
+    /// Take a checkpoint now and then every `interval` instructions, and
+    /// record device inputs so that execution from a checkpoint replays
+    /// exactly.
+    fn enable_history(&mut self, interval: u64);
+
+    /// Restore the latest checkpoint taken at or before `icount`, or the
+    /// earliest one if there is none, returning the instruction count it was
+    /// taken at.
+    fn restore_checkpoint(&mut self, icount: u64) -> Option<u64>;
+}
+
+/// A trap, its cause encoded as in the RISC-V `mcause` register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trap {
+    pub interrupt: bool,
+    pub cause: u32,
+    /// address of the instruction that raised it
+    pub pc: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A data access made by an instruction, used for watchpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemAccess {
+    pub addr: u32,
+    pub bytes: u8,
+    pub kind: AccessKind,
+}
+
+impl MemAccess {
+    pub fn new(addr: u32, bytes: u8, kind: AccessKind) -> Self {
+        MemAccess { addr, bytes, kind }
+    }
+
+    /// whether the access touches any byte of `[addr, addr + len)`
+    pub fn overlaps(&self, addr: u32, len: u32) -> bool {
+        let (start, end) = (self.addr as u64, self.addr as u64 + self.bytes as u64);
+        start < addr as u64 + len as u64 && (addr as u64) < end
+    }
+}
+
 pub trait MemoryModel {
     fn load_mem(&mut self, index: u32, bytes: u8) -> Option<u32>;
-    fn store_mem(&mut self, index: u32, bytes: u8, value: u32);
+    /// Returns false when nothing is mapped at `index`, an access fault.
+    fn store_mem(&mut self, index: u32, bytes: u8, value: u32) -> bool;
+    /// Read memory for inspection, with no side effect on devices.
+    fn peek_mem(&mut self, index: u32, bytes: u8) -> Option<u32> {
+        self.load_mem(index, bytes)
+    }
+    /// Write memory on behalf of a debugger, returning false instead of
+    /// writing to a device.
+    fn poke_mem(&mut self, index: u32, bytes: u8, value: u32) -> bool {
+        self.store_mem(index, bytes, value)
+    }
+    /// Start or stop keeping the memory accesses made by loads and stores.
+    fn trace_accesses(&mut self, _enable: bool) {}
+    /// Memory accesses since the last call, empty when not traced.
+    fn take_accesses(&mut self) -> Vec<MemAccess> {
+        Vec::new()
+    }
     fn store_mems(&mut self, index: u32, value: &[u32]) {
         for (i, item) in value.iter().enumerate() {
             self.store_mem(index + i as u32, 1, *item);
@@ -69,6 +159,11 @@ pub trait RegisterModel: Index<u32, Output = u32> + IndexMut<u32> {
 
     fn iter(&self) -> Box<dyn Iterator<Item = (String, u32)>>;
 
+    /// Names of the CSRs readable by name.
+    fn csr_names(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
     fn read_register_previlege(&self, index: u32) -> Option<u32>;
 
     fn write_register_previlege(&mut self, index: u32, value: u32);
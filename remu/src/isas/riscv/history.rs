@@ -0,0 +1,66 @@
+//! Periodic checkpoints of the machine, the base of reverse execution.
+//!
+//! A checkpoint keeps the registers at some instruction count and, for every
+//! memory page written afterwards, the page content before the first write.
+//! Restoring a checkpoint undoes the pages of every later checkpoint; the
+//! debugger then re-executes forward to the exact instruction, with device
+//! reads answered from the input log so the replay is deterministic.
+
+use super::mem::Journal;
+use super::reg::Regs;
+use super::PrivilegeMode;
+use std::collections::VecDeque;
+
+/// at most this many checkpoints are kept, older ones are dropped
+pub(super) const MAX_CHECKPOINTS: usize = 4096;
+
+pub(super) struct Checkpoint {
+    pub(super) icount: u64,
+    pub(super) regs: Regs,
+    pub(super) mode: PrivilegeMode,
+    /// pages written between this checkpoint and the next one
+    pub(super) undo: Journal,
+}
+
+pub(super) struct History {
+    pub(super) interval: u64,
+    pub(super) checkpoints: VecDeque<Checkpoint>,
+    /// the furthest instruction count ever executed, anything below it is
+    /// a re-execution
+    pub(super) horizon: u64,
+}
+
+impl History {
+    pub(super) fn new(interval: u64) -> Self {
+        History {
+            interval: interval.max(1),
+            checkpoints: VecDeque::new(),
+            horizon: 0,
+        }
+    }
+
+    /// Start a new checkpoint, `undo` being the journal of the one before.
+    /// Returns the instruction count of the oldest checkpoint when one was
+    /// dropped, history before it being gone.
+    pub(super) fn push(&mut self, checkpoint: Checkpoint, undo: Journal) -> Option<u64> {
+        if let Some(last) = self.checkpoints.back_mut() {
+            last.undo = undo;
+        }
+        let dropped = self.checkpoints.len() == MAX_CHECKPOINTS;
+        if dropped {
+            self.checkpoints.pop_front();
+        }
+        self.checkpoints.push_back(checkpoint);
+        dropped.then(|| self.checkpoints[0].icount)
+    }
+
+    /// index of the latest checkpoint taken at or before `icount`, or of
+    /// the earliest one if `icount` precedes the history
+    pub(super) fn find(&self, icount: u64) -> Option<usize> {
+        if self.checkpoints.is_empty() {
+            return None;
+        }
+        let index = self.checkpoints.partition_point(|c| c.icount <= icount);
+        Some(index.saturating_sub(1))
+    }
+}
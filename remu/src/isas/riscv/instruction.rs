@@ -15,7 +15,7 @@ type Fun7 = u32;
 type Opcode = u32;
 type Csr = u32;
 
-const ECALL: u32 = 0b0000_0000_0000_0000_0000_0000_0111_0011;
+pub(super) const ECALL: u32 = 0b0000_0000_0000_0000_0000_0000_0111_0011;
 const EBREAK: u32 = 0b0000_0000_0001_0000_0000_0000_0111_0011;
 const SRET: u32 = 0b0001_0000_0010_0000_0000_0000_0111_0011;
 const MRET: u32 = 0b0011_0000_0010_0000_0000_0000_0111_0011;
@@ -472,11 +472,14 @@ impl Inst for Instruction {
                 let rs1 = cpu[*rs1];
                 let rs2 = cpu[*rs2];
                 let addr = rs1.wrapping_add(*imm);
-                match funct3 {
+                let stored = match funct3 {
                     0b000 => cpu.store_mem(addr, 1, rs2), // sb
                     0b001 => cpu.store_mem(addr, 2, rs2), // sh
                     0b010 => cpu.store_mem(addr, 4, rs2), // sw
                     _ => panic!("Invalid funct3"),
+                };
+                if !stored {
+                    return Err(RError::InvalidStore(addr));
                 }
                 Ok(cpu.pc() + 4)
             }
@@ -1,11 +1,28 @@
-use crate::info;
-use crate::ioe::{SerialPort, Timer, IO};
+use std::collections::HashMap;
+
+use crate::error::RError;
+use crate::ioe::audio::WavSink;
+use crate::ioe::{Audio, Disk, Keyboard, Screen, SerialPort, Timer, IO};
 use crate::isas::MemoryModel;
-use crate::{add_device, settings::*};
+use crate::settings::Settings;
+use crate::{add_device, info, warn};
+
+pub(crate) const PAGE_SIZE: u32 = 0x1000;
+
+/// content of memory pages before their first write, by page number
+pub(crate) type Journal = HashMap<u32, Box<[u8]>>;
+
+/// A region of RAM.
+struct Ram {
+    base: u32,
+    bytes: Vec<u8>,
+}
 
 pub struct Mem {
-    mem: Vec<u8>,
+    rams: Vec<Ram>,
     pub devices: Vec<Box<dyn IO>>,
+    /// pages saved for reverse execution, only kept when history is enabled
+    pub(crate) journal: Option<Journal>,
 }
 
 impl Default for Mem {
@@ -16,19 +33,84 @@ impl Default for Mem {
 
 impl Mem {
     pub fn new() -> Self {
-        let mem = vec![0; 0x100000000];
+        Self::with_settings(&Settings::default()).unwrap()
+    }
+
+    /// Memory with the RAM regions and devices of `settings`.
+    pub fn with_settings(settings: &Settings) -> Result<Self, RError> {
+        settings.validate()?;
+        let rams = settings
+            .ram
+            .iter()
+            .map(|region| Ram {
+                base: region.base as u32,
+                bytes: vec![0; region.size as usize],
+            })
+            .collect();
         let mut devices: Vec<Box<dyn IO>> = Vec::new();
         // register devices
-        add_device!(ENABLE_SERIAL, SerialPort, devices);
-        add_device!(ENABLE_TIMER, Timer, devices);
-        // only enable vga and keyboard when sdl feature is enabled
-        #[cfg(feature = "sdl")]
-        {
-            use crate::ioe;
-            add_device!(ENABLE_KBD, ioe::Keyboard, devices);
-            add_device!(ENABLE_VGA, ioe::Screen, devices);
+        if let Some(base) = settings.devices.serial {
+            let serial = match &settings.serial_in {
+                Some(path) => SerialPort::with_input(base, path)?,
+                None => SerialPort::new(base, 4),
+            };
+            devices.push(Box::new(serial));
+            info!("SerialPort enabled at {:#x}", base);
+        }
+        add_device!(settings.devices.timer, Timer, devices);
+        add_device!(settings.devices.keyboard, Keyboard, devices);
+        if let Some(vga) = &settings.devices.vga {
+            let mut screen = Screen::new(vga.ctl, vga.base, vga.width, vga.height);
+            if let Some(path) = &vga.dump {
+                screen.dump_frames(path, vga.dump_every);
+            }
+            devices.push(Box::new(screen));
+            info!("Screen enabled at {:#x}", vga.base);
+        }
+        if let Some(audio) = &settings.devices.audio {
+            let mut device = Audio::new(audio.base, audio.sbuf, audio.sbuf_size);
+            if let Some(path) = &audio.output {
+                device.set_sink(WavSink::create(path)?);
+            }
+            devices.push(Box::new(device));
+            info!("Audio enabled at {:#x}", audio.base);
+        }
+        if let Some(disk) = &settings.devices.disk {
+            devices.push(Box::new(Disk::open(disk.base, &disk.image, disk.mode)?));
+            info!("Disk enabled at {:#x}", disk.base);
+        }
+        Ok(Mem {
+            rams,
+            devices,
+            journal: None,
+        })
+    }
+
+    /// the region and offset of the `bytes` bytes at `index`, if all are in
+    /// RAM
+    fn in_ram(&self, index: u32, bytes: u8) -> Option<(usize, usize)> {
+        self.rams.iter().enumerate().find_map(|(i, ram)| {
+            let offset = index.checked_sub(ram.base)? as usize;
+            (offset + bytes as usize <= ram.bytes.len()).then_some((i, offset))
+        })
+    }
+
+    /// the device mapped at `addr`, if any
+    pub(crate) fn device_at(&mut self, addr: u32) -> Option<&mut dyn IO> {
+        let device = self
+            .devices
+            .iter_mut()
+            .find(|device| device.match_(addr as u64))?;
+        Some(device.as_mut())
+    }
+
+    /// Write back the pages saved in `journal`.
+    pub(crate) fn undo(&mut self, journal: &Journal) {
+        for (page, content) in journal.iter() {
+            // regions are whole pages, a page is in one region
+            let (ram, start) = self.in_ram(page * PAGE_SIZE, 1).unwrap();
+            self.rams[ram].bytes[start..start + content.len()].copy_from_slice(content);
         }
-        Mem { mem, devices }
     }
 
     pub fn update_devices(&mut self) {
@@ -38,30 +120,58 @@ impl Mem {
     }
 }
 
+/// the low `bytes` bytes of `value`
+pub(crate) fn truncate(value: u32, bytes: u8) -> u32 {
+    match bytes {
+        1 => value as u8 as u32,
+        2 => value as u16 as u32,
+        _ => value,
+    }
+}
+
 impl MemoryModel for Mem {
     fn load_mem(&mut self, index: u32, bytes: u8) -> Option<u32> {
         for device in self.devices.iter_mut() {
             if device.match_(index as u64) {
-                return device.read(index as u64);
+                return device
+                    .read(index as u64)
+                    .map(|value| truncate(value, bytes));
             }
         }
+        let (ram, offset) = self.in_ram(index, bytes)?;
         let mut value: u32 = 0;
         for i in 0..bytes as usize {
             // little endian
-            value += (self.mem[index as usize + i] as u32) << (i * 8);
+            value += (self.rams[ram].bytes[offset + i] as u32) << (i * 8);
         }
         Some(value)
     }
 
-    fn store_mem(&mut self, index: u32, bytes: u8, value: u32) {
+    fn store_mem(&mut self, index: u32, bytes: u8, value: u32) -> bool {
         for device in self.devices.iter_mut() {
             if device.match_(index as u64) {
-                device.write(index as u64, value as u64);
-                return;
+                device.write_bytes(index as u64, bytes, truncate(value, bytes) as u64);
+                return true;
+            }
+        }
+        let Some((ram, offset)) = self.in_ram(index, bytes) else {
+            warn!("store to {:#x} is out of memory", index);
+            return false;
+        };
+        let ram = &mut self.rams[ram];
+        if let Some(journal) = self.journal.as_mut() {
+            // a misaligned store may touch two pages
+            let last = offset + bytes as usize - 1;
+            for page in [offset / PAGE_SIZE as usize, last / PAGE_SIZE as usize] {
+                let start = page * PAGE_SIZE as usize;
+                journal
+                    .entry(ram.base / PAGE_SIZE + page as u32)
+                    .or_insert_with(|| ram.bytes[start..start + PAGE_SIZE as usize].into());
             }
         }
         for i in 0..bytes as usize {
-            self.mem[index as usize + i] = (value >> (i * 8)) as u8;
+            ram.bytes[offset + i] = (value >> (i * 8)) as u8;
         }
+        true
     }
 }
@@ -1,3 +1,4 @@
+mod history;
 pub mod instruction;
 pub mod mem;
 pub mod reg;
@@ -6,17 +7,30 @@ use std::ops::Index;
 use std::ops::IndexMut;
 
 use crate::error::RError;
-use crate::isas::{Inst, MemoryModel, RegisterModel, ISA};
+use crate::ioe::replay::InputLog;
+use crate::isas::{AccessKind, Inst, MemAccess, MemoryModel, RegisterModel, TimeTravel, Trap, ISA};
+use crate::settings::{Isa, Settings};
 use crate::warn;
-use instruction::Instruction;
+use history::{Checkpoint, History};
+use instruction::{Instruction, ECALL};
 
 pub struct RV32CPU {
     regs: reg::Regs,
     pub mems: mem::Mem,
     mode: PrivilegeMode,
+    /// extensions executed, the others raising illegal instruction
+    isa: Isa,
+    /// number of instructions retired so far
+    icount: u64,
+    inputs: Option<InputLog>,
+    history: Option<History>,
+    /// memory accesses of instructions, only kept while watched
+    accesses: Option<Vec<MemAccess>>,
+    /// the last trap raised, until taken by a debugger
+    trap: Option<Trap>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PrivilegeMode {
     User = 0,
     Supervisor = 1,
@@ -30,6 +44,12 @@ impl Default for RV32CPU {
             regs: reg::Regs::new(),
             mems: mem::Mem::new(),
             mode: PrivilegeMode::Supervisor,
+            isa: Isa::default(),
+            icount: 0,
+            inputs: None,
+            history: None,
+            accesses: None,
+            trap: None,
         }
     }
 }
@@ -40,8 +60,67 @@ impl RV32CPU {
             regs,
             mems,
             mode: PrivilegeMode::Supervisor,
+            isa: Isa::default(),
+            icount: 0,
+            inputs: None,
+            history: None,
+            accesses: None,
+            trap: None,
         }
     }
+
+    /// A hart with the memory, devices, extensions and boot mode of
+    /// `settings`.
+    pub fn with_settings(settings: &Settings) -> Result<Self, RError> {
+        let mut cpu = Self::new(reg::Regs::new(), mem::Mem::with_settings(settings)?);
+        cpu.isa = settings.isa;
+        cpu.mode = settings.boot.mode;
+        Ok(cpu)
+    }
+
+    /// Route every device read through `log`, either recording or replaying it.
+    pub fn set_input_log(&mut self, log: InputLog) {
+        self.inputs = Some(log);
+    }
+
+    pub fn take_input_log(&mut self) -> Option<InputLog> {
+        self.inputs.take()
+    }
+
+    fn checkpoint(&mut self) {
+        let undo = self.mems.journal.replace(Default::default());
+        if let Some(history) = self.history.as_mut() {
+            let checkpoint = Checkpoint {
+                icount: self.icount,
+                regs: self.regs.clone(),
+                mode: self.mode,
+                undo: Default::default(),
+            };
+            let oldest = history.push(checkpoint, undo.unwrap_or_default());
+            // reads before the oldest checkpoint are never replayed again
+            if let (Some(oldest), Some(inputs)) = (oldest, self.inputs.as_mut()) {
+                inputs.forget(oldest);
+            }
+        }
+    }
+
+    /// virtual address for user mode
+    fn translate(&self, index: u32) -> u32 {
+        let size = rconfig::layout::USER_APP_SIZE as u32;
+        match self.mode {
+            PrivilegeMode::User => {
+                let id = self.read_register_by_name("mstatus").unwrap();
+                index + size * id
+            }
+            _ => index,
+        }
+    }
+
+    /// whether the instruction being executed was already executed once,
+    /// in which case it must not repeat output to devices
+    fn reexecuting(&self) -> bool {
+        matches!(&self.history, Some(history) if self.icount < history.horizon)
+    }
 }
 
 impl Index<u32> for RV32CPU {
@@ -60,29 +139,60 @@ impl IndexMut<u32> for RV32CPU {
 
 impl MemoryModel for RV32CPU {
     fn load_mem(&mut self, index: u32, bytes: u8) -> Option<u32> {
-        // virtual address for user mode
-        let size = rconfig::layout::USER_APP_SIZE as u32;
-        let index = match self.mode {
-            PrivilegeMode::User => {
-                let id = self.read_register_by_name("mstatus").unwrap();
-                index + size * id
+        if let Some(accesses) = self.accesses.as_mut() {
+            accesses.push(MemAccess::new(index, bytes, AccessKind::Read));
+        }
+        let index = self.translate(index);
+        if let Some(inputs) = self.inputs.as_mut() {
+            if let Some(device) = self.mems.device_at(index) {
+                return inputs
+                    .read(self.icount, device, index)
+                    .map(|value| mem::truncate(value, bytes));
             }
-            _ => index,
-        };
+        }
         self.mems.load_mem(index, bytes)
     }
 
-    fn store_mem(&mut self, index: u32, bytes: u8, value: u32) {
-        // virtual address for user mode
-        let size = rconfig::layout::USER_APP_SIZE as u32;
-        let index = match self.mode {
-            PrivilegeMode::User => {
-                let id = self.read_register_by_name("mstatus").unwrap();
-                index + size * id
-            }
-            _ => index,
-        };
-        self.mems.store_mem(index, bytes, value);
+    fn store_mem(&mut self, index: u32, bytes: u8, value: u32) -> bool {
+        if let Some(accesses) = self.accesses.as_mut() {
+            accesses.push(MemAccess::new(index, bytes, AccessKind::Write));
+        }
+        let index = self.translate(index);
+        // a store reaching a device again would repeat its output or its
+        // command, so it is skipped; the device state it leads to is then
+        // stale, which is why every device read is logged and answered from
+        // the input log instead of only the nondeterministic ones
+        if self.reexecuting() && self.mems.device_at(index).is_some() {
+            return true;
+        }
+        self.mems.store_mem(index, bytes, value)
+    }
+
+    fn peek_mem(&mut self, index: u32, bytes: u8) -> Option<u32> {
+        let index = self.translate(index);
+        if self.mems.device_at(index).is_some() {
+            return None;
+        }
+        self.mems.load_mem(index, bytes)
+    }
+
+    fn poke_mem(&mut self, index: u32, bytes: u8, value: u32) -> bool {
+        let index = self.translate(index);
+        if self.mems.device_at(index).is_some() {
+            return false;
+        }
+        self.mems.store_mem(index, bytes, value)
+    }
+
+    fn trace_accesses(&mut self, enable: bool) {
+        self.accesses = enable.then(Vec::new);
+    }
+
+    fn take_accesses(&mut self) -> Vec<MemAccess> {
+        self.accesses
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
     }
 }
 
@@ -107,6 +217,11 @@ impl RegisterModel for RV32CPU {
         self.regs.iter()
     }
 
+    #[inline]
+    fn csr_names(&self) -> Vec<&'static str> {
+        self.regs.csr_names()
+    }
+
     #[inline]
     fn read_register_previlege(&self, index: u32) -> Option<u32> {
         self.regs.read_register_previlege(index)
@@ -137,29 +252,102 @@ impl ISA for RV32CPU {
         32
     }
 
+    #[inline]
+    fn fetch_inst(&mut self, pc: u32) -> Result<u32, RError> {
+        // fetches are not data accesses, keep them out of the access trace
+        let addr = self.translate(pc);
+        let code = self.mems.load_mem(addr, 4);
+        if code.is_none() {
+            // instruction access fault
+            self.trap = Some(Trap {
+                interrupt: false,
+                cause: 1,
+                pc,
+            });
+        }
+        code.ok_or(RError::InvalidMem(addr))
+    }
+
     fn disassemble(&mut self, addr: u32) -> Result<String, RError> {
-        let inst_code = self.fetch_inst(addr)?;
+        // not a fetch, it must not raise a trap
+        let inst_code = self.peek_mem(addr, 4).ok_or(RError::InvalidMem(addr))?;
         let inst = Instruction::decode(inst_code)?;
         Ok(inst.to_string())
     }
 
+    fn jump_target(&mut self, addr: u32) -> Option<u32> {
+        match Instruction::decode(self.peek_mem(addr, 4)?).ok()? {
+            Instruction::BType(imm, ..) | Instruction::JType(imm, ..) => {
+                Some(addr.wrapping_add(imm))
+            }
+            _ => None,
+        }
+    }
+
+    fn is_call(&mut self, addr: u32) -> bool {
+        let Some(code) = self.peek_mem(addr, 4) else {
+            return false;
+        };
+        match Instruction::decode(code) {
+            Ok(Instruction::JType(_, rd, _)) => rd != 0,
+            // jalr
+            Ok(Instruction::IType(_, _, _, rd, 0b1100111)) => rd != 0,
+            _ => false,
+        }
+    }
+
     fn execute(&mut self, inst_code: u32) -> Result<u32, RError> {
-        match Instruction::decode(inst_code) {
+        let mode = self.mode as u32;
+        let result = match Instruction::decode(inst_code) {
+            // ecall, ebreak and mret are not part of zicsr
+            Ok(Instruction::CSRType(_, _, funct3, _, _)) if funct3 != 0 && !self.isa.zicsr => {
+                Err(RError::InvalidInstruction(format!(
+                    "{:#x}: csr instructions need zicsr",
+                    self.regs.pc()
+                )))
+            }
             Ok(inst) => inst.execute(self),
             Err(err) => {
                 warn!("invalid code at {:x}", self.regs.pc());
                 Err(err)
             }
+        };
+        let cause = match &result {
+            // environment call from the current mode
+            Ok(_) if inst_code == ECALL => Some(8 + mode),
+            Err(RError::InvalidCode(_)) | Err(RError::InvalidInstruction(_)) => Some(2),
+            Err(RError::InvalidMem(_)) => Some(5),
+            Err(RError::InvalidStore(_)) => Some(7),
+            _ => None,
+        };
+        if let Some(cause) = cause {
+            self.trap = Some(Trap {
+                interrupt: false,
+                cause,
+                pc: self.regs.pc(),
+            });
         }
+        result
+    }
+
+    fn privilege(&self) -> u8 {
+        self.mode as u8
+    }
+
+    fn take_trap(&mut self) -> Option<Trap> {
+        self.trap.take()
     }
 
     fn device_update(&mut self) -> Result<(), RError> {
-        static mut START_TIME: u32 = 1;
-        unsafe {
-            if START_TIME % 10000 == 0 {
-                self.mems.update_devices();
+        self.icount += 1;
+        if self.icount.is_multiple_of(10000) {
+            self.mems.update_devices();
+        }
+        if let Some(history) = self.history.as_mut() {
+            history.horizon = history.horizon.max(self.icount);
+            if self.icount.is_multiple_of(history.interval) {
+                self.checkpoint();
             }
-            START_TIME += 1;
         }
         Ok(())
     }
@@ -181,6 +369,48 @@ impl ISA for RV32CPU {
     }
 }
 
+impl TimeTravel for RV32CPU {
+    #[inline]
+    fn icount(&self) -> u64 {
+        self.icount
+    }
+
+    fn enable_history(&mut self, interval: u64) {
+        if self.inputs.is_none() {
+            self.inputs = Some(InputLog::new());
+        }
+        let mut history = History::new(interval);
+        history.horizon = self.icount;
+        self.history = Some(history);
+        self.mems.journal = None;
+        self.checkpoint();
+    }
+
+    fn restore_checkpoint(&mut self, icount: u64) -> Option<u64> {
+        let history = self.history.as_mut()?;
+        let index = history.find(icount)?;
+        // undo pages from the newest checkpoint back to the restored one
+        if let Some(journal) = self.mems.journal.take() {
+            self.mems.undo(&journal);
+        }
+        while history.checkpoints.len() > index + 1 {
+            let checkpoint = history.checkpoints.pop_back().unwrap();
+            self.mems.undo(&checkpoint.undo);
+        }
+        let checkpoint = history.checkpoints.back_mut().unwrap();
+        self.mems.undo(&checkpoint.undo);
+        checkpoint.undo.clear();
+        self.mems.journal = Some(Default::default());
+        self.regs = checkpoint.regs.clone();
+        self.mode = checkpoint.mode;
+        self.icount = checkpoint.icount;
+        if let Some(inputs) = self.inputs.as_mut() {
+            inputs.rewind(self.icount);
+        }
+        Some(self.icount)
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -192,4 +422,67 @@ mod tests {
         riscvisa[4] = 100;
         assert_eq!(riscvisa[4], 100);
     }
+
+    #[test]
+    fn test_restore_checkpoint() {
+        use super::instruction::Instruction;
+        use crate::isas::{Inst, MemoryModel, RegisterModel, TimeTravel, ISA};
+
+        let mut cpu = RV32CPU::default();
+        let program = [
+            Instruction::IType(1, (10, 0), 0b000, 10, 0b0010011).assemble(), // addi a0, a0, 1
+            Instruction::SType(0, (2, 10), 0b010, 0b0100011).assemble(),     // sw a0, 0(sp)
+            Instruction::JType(-8_i32 as u32, 0, 0b1101111).assemble(),      // j -8
+        ];
+        for (i, code) in program.iter().enumerate() {
+            cpu.store_mem(0x80000000 + 4 * i as u32, 4, *code);
+        }
+        cpu.update_pc(0x80000000);
+        cpu.write_register_by_name("sp", 0x80001000);
+        cpu.enable_history(4);
+
+        let mut trace = vec![];
+        for _ in 0..30 {
+            trace.push((cpu.pc(), cpu[10], cpu.load_mem(0x80001000, 4).unwrap()));
+            cpu.step().unwrap();
+        }
+        for target in [13, 4, 0, 29] {
+            let start = cpu.restore_checkpoint(target).unwrap();
+            assert!(start <= target);
+            while cpu.icount() < target {
+                cpu.step().unwrap();
+            }
+            let state = (cpu.pc(), cpu[10], cpu.load_mem(0x80001000, 4).unwrap());
+            assert_eq!(state, trace[target as usize]);
+        }
+    }
+
+    #[test]
+    fn test_history_bounds_input_log() {
+        use super::history::MAX_CHECKPOINTS;
+        use crate::ioe::TIMER_ADDR;
+        use crate::isas::{MemoryModel, RegisterModel, TimeTravel, ISA};
+
+        let mut cpu = RV32CPU::default();
+        // lw a0, 0(t0); j -4
+        cpu.store_mem(0x80000000, 4, 0x0002a503);
+        cpu.store_mem(0x80000004, 4, 0xffdff06f);
+        cpu.update_pc(0x80000000);
+        cpu.write_register_by_name("t0", TIMER_ADDR as u32);
+        cpu.enable_history(1);
+        let steps = 3 * MAX_CHECKPOINTS as u64;
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+        // a read every other instruction, only those of the kept history
+        let events = cpu.inputs.as_ref().unwrap().events().len();
+        assert!(events <= MAX_CHECKPOINTS / 2 + 1, "{} events", events);
+
+        // the oldest checkpoint still replays from the log
+        let start = cpu.restore_checkpoint(0).unwrap();
+        assert_eq!(start, steps - MAX_CHECKPOINTS as u64 + 1);
+        while cpu.icount() < steps {
+            cpu.step().unwrap();
+        }
+    }
 }
@@ -50,7 +50,7 @@ lazy_static! {
             (0x304, "mie"),
             (0x305, "mtvec"),
             (0x306, "mcounteren"),
-            (0x310, "mscratch"),
+            (0x310, "mstatush"),
             (0x340, "mscratch"),
             (0x341, "mepc"),
             (0x342, "mcause"),
@@ -100,7 +100,17 @@ impl Regs {
         }
     }
 
-    pub(super) fn index_to_name(index: u32) -> String {
+    /// names of the known CSRs, sorted by index
+    pub(crate) fn csr_names() -> Vec<(u32, &'static str)> {
+        let mut csrs = INDEX2CSR
+            .iter()
+            .map(|(index, name)| (*index as u32, *name))
+            .collect::<Vec<_>>();
+        csrs.sort();
+        csrs
+    }
+
+    pub(crate) fn index_to_name(index: u32) -> String {
         if index >= REG_NUM as u32 {
             panic!("Invalid register index: {}", index);
         }
@@ -165,6 +175,12 @@ impl RegisterModel for Regs {
         }
     }
 
+    fn csr_names(&self) -> Vec<&'static str> {
+        let mut names = CSR2INDEX.keys().copied().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
     fn read_register_previlege(&self, index: u32) -> Option<u32> {
         if index >= PRIVILEGE_REG_NUM as u32 {
             return None;
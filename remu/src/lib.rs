@@ -1,7 +1,11 @@
+pub mod config;
+pub mod dtb;
 pub mod error;
 pub mod exes;
 pub mod ioe;
 pub mod isas;
+pub mod machine;
 pub mod rdb;
 pub mod settings;
+pub mod trace;
 pub mod util;
@@ -0,0 +1,324 @@
+//! A whole machine to embed: a hart with its memory and devices built from
+//! `Settings`, the image it runs and what to do with its output.
+//!
+//! ```no_run
+//! use remu::machine::{Machine, Stop};
+//!
+//! let mut machine = Machine::builder()
+//!     .config("machine.toml")?
+//!     .on_serial(|byte| print!("{}", byte as char))
+//!     .build()?;
+//! machine.load("a.out")?;
+//! match machine.run_for(1_000_000) {
+//!     Stop::Exit(code) => println!("exited with {}", code),
+//!     stop => println!("stopped: {:?}", stop),
+//! }
+//! # Ok::<(), remu::error::RError>(())
+//! ```
+
+use crate::dtb;
+use crate::error::RError;
+use crate::exes::{check_segment, Exe, ELF};
+use crate::ioe::{SerialPort, IO};
+use crate::isas::{MemoryModel, RegisterModel, ISA, RV32CPU};
+use crate::settings::Settings;
+use crate::trace::{Trace, Tracer};
+use std::any::Any;
+use std::path::Path;
+
+/// Why `run` returned.
+#[derive(Debug)]
+pub enum Stop {
+    /// the program exited with this code
+    Exit(i8),
+    /// the instruction budget ran out
+    Budget,
+    /// the stop condition held
+    Condition,
+    Error(RError),
+}
+
+pub struct MachineBuilder {
+    settings: Settings,
+    devices: Vec<Box<dyn IO>>,
+    trace: Trace,
+    on_serial: Option<Box<dyn FnMut(u8)>>,
+    on_exit: Option<Box<dyn FnMut(i8)>>,
+}
+
+impl MachineBuilder {
+    pub fn settings(mut self, settings: Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Build the machine described in the file at `path`.
+    pub fn config(self, path: impl AsRef<Path>) -> Result<Self, RError> {
+        Ok(self.settings(Settings::load(path)?))
+    }
+
+    /// Attach a device of its own, in front of those of the settings.
+    pub fn device(mut self, device: impl IO) -> Self {
+        self.devices.push(Box::new(device));
+        self
+    }
+
+    /// Write the traces of `trace` to the log while running.
+    pub fn trace(mut self, trace: Trace) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Hand each byte the serial port writes to `f` instead of printing it.
+    pub fn on_serial(mut self, f: impl FnMut(u8) + 'static) -> Self {
+        self.on_serial = Some(Box::new(f));
+        self
+    }
+
+    /// Call `f` with the exit code of the program when it exits.
+    pub fn on_exit(mut self, f: impl FnMut(i8) + 'static) -> Self {
+        self.on_exit = Some(Box::new(f));
+        self
+    }
+
+    pub fn build(self) -> Result<Machine, RError> {
+        let mut cpu = RV32CPU::with_settings(&self.settings)?;
+        let devices = std::mem::take(&mut cpu.mems.devices);
+        cpu.mems.devices = self.devices;
+        cpu.mems.devices.extend(devices);
+        let mut machine = Machine {
+            cpu,
+            settings: self.settings,
+            elf: None,
+            trace: self.trace,
+            tracer: None,
+            on_exit: self.on_exit,
+        };
+        if let Some(f) = self.on_serial {
+            if let Some(serial) = machine.device::<SerialPort>() {
+                serial.set_output(f);
+            }
+        }
+        Ok(machine)
+    }
+}
+
+pub struct Machine {
+    cpu: RV32CPU,
+    settings: Settings,
+    /// the image loaded, if it is an ELF file
+    elf: Option<ELF>,
+    trace: Trace,
+    tracer: Option<Tracer>,
+    on_exit: Option<Box<dyn FnMut(i8)>>,
+}
+
+impl Machine {
+    pub fn builder() -> MachineBuilder {
+        MachineBuilder {
+            settings: Settings::default(),
+            devices: vec![],
+            trace: Trace::default(),
+            on_serial: None,
+            on_exit: None,
+        }
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn cpu(&self) -> &RV32CPU {
+        &self.cpu
+    }
+
+    /// The hart, for the debugger or for inspecting registers and memory.
+    pub fn cpu_mut(&mut self) -> &mut RV32CPU {
+        &mut self.cpu
+    }
+
+    pub fn elf(&self) -> Option<&ELF> {
+        self.elf.as_ref()
+    }
+
+    /// The first device of type `T`.
+    pub fn device<T: IO>(&mut self) -> Option<&mut T> {
+        self.cpu.mems.devices.iter_mut().find_map(|device| {
+            let device: &mut dyn Any = device.as_mut();
+            device.downcast_mut::<T>()
+        })
+    }
+
+    /// Load the image at `path`, an ELF file unless the settings give a
+    /// load address, then boot it.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<(), RError> {
+        let path = path.as_ref();
+        match self.settings.boot.load_addr {
+            Some(addr) => {
+                let bytes = std::fs::read(path)
+                    .map_err(|e| RError::IOError(format!("{}: {}", path.display(), e)))?;
+                self.load_raw(&bytes, addr)?;
+            }
+            None => {
+                let mut elf = ELF::parse_path(path.to_str().unwrap_or_default())?;
+                elf.load_binary(&mut self.cpu)?;
+                self.elf = Some(elf);
+            }
+        }
+        self.boot()
+    }
+
+    /// Copy `bytes` to `addr` and start there, without booting.
+    pub fn load_raw(&mut self, bytes: &[u8], addr: u32) -> Result<(), RError> {
+        check_segment(&mut self.cpu, addr, bytes.len() as u32)?;
+        for (i, byte) in bytes.iter().enumerate() {
+            self.cpu
+                .store_mem(addr.wrapping_add(i as u32), 1, *byte as u32);
+        }
+        self.cpu.update_pc(addr);
+        Ok(())
+    }
+
+    /// Pass the device tree and jump to the boot entry, as the settings ask.
+    pub fn boot(&mut self) -> Result<(), RError> {
+        if self.settings.boot.dtb {
+            let addr = dtb::boot(&mut self.cpu, &self.settings)?;
+            crate::info!("Device tree at {:#x}", addr);
+        }
+        if let Some(entry) = self.settings.boot.entry {
+            self.cpu.update_pc(entry);
+        }
+        self.tracer = self
+            .trace
+            .any()
+            .then(|| Tracer::new(self.trace, self.elf.as_ref()));
+        Ok(())
+    }
+
+    /// Execute one instruction.
+    pub fn step(&mut self) -> Result<(), RError> {
+        let result = match self.tracer.as_mut() {
+            Some(tracer) => tracer.step(&mut self.cpu),
+            None => self.cpu.step(),
+        };
+        if let (Err(RError::Ebreak(code)), Some(on_exit)) = (&result, self.on_exit.as_mut()) {
+            on_exit(*code);
+        }
+        result
+    }
+
+    /// Run until the program exits or fails.
+    pub fn run(&mut self) -> Stop {
+        self.run_until(None, |_| false)
+    }
+
+    /// Run at most `budget` instructions.
+    pub fn run_for(&mut self, budget: u64) -> Stop {
+        self.run_until(Some(budget), |_| false)
+    }
+
+    /// Run until `stop` holds before an instruction, or `budget`
+    /// instructions ran.
+    pub fn run_until(
+        &mut self,
+        budget: Option<u64>,
+        mut stop: impl FnMut(&mut RV32CPU) -> bool,
+    ) -> Stop {
+        let mut count = 0_u64;
+        loop {
+            if budget.is_some_and(|budget| count >= budget) {
+                return Stop::Budget;
+            }
+            if stop(&mut self.cpu) {
+                return Stop::Condition;
+            }
+            match self.step() {
+                Ok(()) => count += 1,
+                Err(RError::Ebreak(code)) => return Stop::Exit(code),
+                Err(e) => return Stop::Error(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ioe::{Keyboard, Screen};
+    use crate::settings::Devices;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// a program writing `H` to the serial port, then exiting with 7
+    const PROGRAM: [u32; 5] = [
+        0xa00002b7, // lui t0, 0xa0000
+        0x04800313, // li t1, 'H'
+        0x3e628c23, // sb t1, 0x3f8(t0)
+        0x00700513, // li a0, 7
+        0x00100073, // ebreak
+    ];
+
+    #[test]
+    fn test_machine() {
+        let output = Rc::new(RefCell::new(String::new()));
+        let exits = Rc::new(RefCell::new(vec![]));
+        let settings = Settings {
+            devices: Devices::parse("serial,keyboard").unwrap(),
+            ..Settings::default()
+        };
+        let mut machine = Machine::builder()
+            .settings(settings)
+            .on_serial({
+                let output = output.clone();
+                move |byte| output.borrow_mut().push(byte as char)
+            })
+            .on_exit({
+                let exits = exits.clone();
+                move |code| exits.borrow_mut().push(code)
+            })
+            .build()
+            .unwrap();
+        assert!(machine.device::<Keyboard>().is_some());
+        let program: Vec<u8> = PROGRAM.iter().flat_map(|code| code.to_le_bytes()).collect();
+        machine.load_raw(&program, 0x80000000).unwrap();
+        machine.boot().unwrap();
+        // the device tree is passed in a1
+        assert_ne!(machine.cpu().read_register_by_name("a1"), Some(0));
+
+        assert!(matches!(machine.run_for(2), Stop::Budget));
+        assert!(matches!(
+            machine.run_until(None, |cpu| cpu.pc() == 0x8000000c),
+            Stop::Condition
+        ));
+        assert_eq!(*output.borrow(), "H");
+        assert!(matches!(machine.run(), Stop::Exit(7)));
+        assert_eq!(*exits.borrow(), vec![7]);
+    }
+
+    #[test]
+    fn test_frame_buffer_access() {
+        // set the red byte of the first pixel, then read it back three ways
+        let program: [u32; 7] = [
+            0xa10002b7, // lui t0, 0xa1000
+            0xfff00313, // li t1, -1
+            0x00628123, // sb t1, 2(t0)
+            0x0022c503, // lbu a0, 2(t0)
+            0x00228583, // lb a1, 2(t0)
+            0x0002a603, // lw a2, 0(t0)
+            0x00100073, // ebreak
+        ];
+        let settings = Settings {
+            devices: Devices::parse("vga").unwrap(),
+            ..Settings::default()
+        };
+        let mut machine = Machine::builder().settings(settings).build().unwrap();
+        let program: Vec<u8> = program.iter().flat_map(|code| code.to_le_bytes()).collect();
+        machine.load_raw(&program, 0x80000000).unwrap();
+        assert!(matches!(machine.run(), Stop::Exit(-1)));
+        let cpu = machine.cpu();
+        assert_eq!(cpu.read_register_by_name("a0"), Some(0xff));
+        assert_eq!(cpu.read_register_by_name("a1"), Some(0xffffffff));
+        assert_eq!(cpu.read_register_by_name("a2"), Some(0x00ff0000));
+        assert_eq!(machine.device::<Screen>().unwrap().pixels()[0], 0x00ff0000);
+    }
+}
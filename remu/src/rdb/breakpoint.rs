@@ -1,98 +1,619 @@
-use super::eval::eval;
-use crate::isas::ISA;
+use super::catchpoint::{mode_name, Catch};
+use super::eval::{eval, location};
+use super::symbols::SymbolTable;
+use crate::error::RError;
+use crate::isas::{AccessKind, ISA};
 
+/// bytes watched by read and access watchpoints
+const WATCH_WIDTH: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum WatchKind {
+    /// the value of the expression changes
+    Write,
+    /// an instruction reads the location
+    Read,
+    /// an instruction reads or writes the location
+    Access,
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Exec {
+        addr: u32,
+        location: String,
+    },
+    Watch {
+        exp: String,
+        value: Option<u64>,
+    },
+    Access {
+        exp: String,
+        addr: u32,
+        read_only: bool,
+    },
+    Catch {
+        catch: Catch,
+        /// privilege level when last checked, for mode switches
+        mode: u8,
+    },
+}
+
+#[derive(Debug, Clone)]
 struct Breakpoint {
-    valid: bool,
-    exp: String,
-    original_value: u64,
+    number: u32,
+    kind: Kind,
+    enabled: bool,
+    /// stop only if this expression evaluates to non-zero
+    condition: Option<String>,
+    /// delete the breakpoint once it stops the program
+    temporary: bool,
+    /// debugger commands run when the breakpoint stops the program
+    commands: Vec<String>,
+    /// hits to let pass before stopping
+    ignore: u64,
+    hits: u64,
+}
+
+/// When the breakpoints are checked, which decides those that can stop
+/// the program.
+#[derive(Clone, Copy)]
+enum Check {
+    /// after an instruction is retired, all of them
+    Retired,
+    /// after a step that failed, the catchpoints
+    Fault,
+    /// before running, the breakpoints on the pc
+    Entry,
+}
+
+/// What stopped the program and the commands to run for it.
+#[derive(Debug, Default, PartialEq)]
+pub(super) struct Hit {
+    pub(super) report: String,
+    pub(super) commands: Vec<String>,
+}
+
+/// Split `spec if cond` into the spec and the condition.
+fn split_condition(spec: &str) -> (&str, Option<&str>) {
+    match spec.split_once(" if ") {
+        Some((spec, cond)) => (spec.trim(), Some(cond.trim())),
+        None => (spec.trim(), None),
+    }
 }
 
 impl Breakpoint {
-    fn new(exp: String, value: u64) -> Breakpoint {
-        Breakpoint {
-            valid: true,
-            exp,
-            original_value: value,
+    fn type_name(&self) -> &'static str {
+        match self.kind {
+            Kind::Exec { .. } if self.temporary => "temp breakpoint",
+            Kind::Exec { .. } => "breakpoint",
+            Kind::Watch { .. } => "watchpoint",
+            Kind::Access {
+                read_only: true, ..
+            } => "read watchpoint",
+            Kind::Access { .. } => "acc watchpoint",
+            Kind::Catch { .. } => "catchpoint",
         }
     }
 
-    fn disable(&mut self) {
-        self.valid = false;
+    fn what(&self) -> String {
+        match &self.kind {
+            Kind::Exec { addr, location } => format!("{:#010x} <{}>", addr, location),
+            Kind::Watch { exp, .. } | Kind::Access { exp, .. } => exp.clone(),
+            Kind::Catch { catch, .. } => catch.to_string(),
+        }
     }
 }
 
+/// Execution breakpoints, watchpoints and catchpoints, numbered from 1 as
+/// in gdb.
+#[derive(Clone)]
 pub(super) struct Breakpoints {
     breakpoints: Vec<Breakpoint>,
+    next: u32,
 }
 
 impl Breakpoints {
     pub(super) fn new() -> Breakpoints {
-        let breakpoint = Breakpoint {
-            valid: false,
-            exp: String::from("0"),
-            original_value: 0,
-        };
         Breakpoints {
-            breakpoints: vec![breakpoint],
+            breakpoints: vec![],
+            next: 1,
         }
     }
 
-    pub(super) fn make_breakpoint(&mut self, cpu: &mut impl ISA, exp: &str) -> Option<u8> {
-        match eval(cpu, exp) {
-            Some(value) => {
-                // check disabled breakpoint
-                for (i, bp) in self.breakpoints.iter_mut().enumerate() {
-                    if !bp.valid && bp.exp == exp {
-                        bp.valid = true;
-                        bp.original_value = value;
-                        return Some(i as u8);
-                    }
+    fn add(&mut self, kind: Kind, condition: Option<&str>, temporary: bool) -> u32 {
+        let number = self.next;
+        self.next += 1;
+        self.breakpoints.push(Breakpoint {
+            number,
+            kind,
+            enabled: true,
+            condition: condition.map(str::to_string),
+            temporary,
+            commands: vec![],
+            ignore: 0,
+            hits: 0,
+        });
+        number
+    }
+
+    /// Break when execution reaches `location`, a symbol, an address
+    /// expression, `*expr` or `file:line`, optionally followed by `if cond`. Returns the
+    /// breakpoint number and address.
+    pub(super) fn break_at(
+        &mut self,
+        cpu: &mut impl ISA,
+        symbols: &SymbolTable,
+        spec: &str,
+        temporary: bool,
+    ) -> Result<(u32, u32), RError> {
+        let (spec, condition) = split_condition(spec);
+        if let Some(condition) = condition {
+            eval(cpu, symbols, condition)?;
+        }
+        let addr = location(cpu, symbols, spec)?;
+        let location = spec.to_string();
+        let number = self.add(Kind::Exec { addr, location }, condition, temporary);
+        Ok((number, addr))
+    }
+
+    /// Watch `exp`, optionally followed by `if cond`. Read and access
+    /// watchpoints need a memory location such as `*0x80001000`.
+    pub(super) fn watch(
+        &mut self,
+        cpu: &mut impl ISA,
+        symbols: &SymbolTable,
+        spec: &str,
+        kind: WatchKind,
+    ) -> Result<u32, RError> {
+        let (exp, condition) = split_condition(spec);
+        if let Some(condition) = condition {
+            eval(cpu, symbols, condition)?;
+        }
+        let value = Some(eval(cpu, symbols, exp)?);
+        let exp = exp.to_string();
+        let kind = match kind {
+            WatchKind::Write => Kind::Watch { exp, value },
+            WatchKind::Read | WatchKind::Access => {
+                let addr = exp.strip_prefix('*').ok_or(RError::DebuggerError(format!(
+                    "Cannot watch reads of {}, use a location such as *address",
+                    exp
+                )))?;
+                let addr = eval(cpu, symbols, addr)? as u32;
+                let read_only = kind == WatchKind::Read;
+                Kind::Access {
+                    exp,
+                    addr,
+                    read_only,
                 }
+            }
+        };
+        Ok(self.add(kind, condition, false))
+    }
+
+    /// Stop on the trap or privilege change described by `catch`.
+    pub(super) fn catch(&mut self, cpu: &impl ISA, catch: Catch) -> u32 {
+        let mode = cpu.privilege();
+        self.add(Kind::Catch { catch, mode }, None, false)
+    }
+
+    fn find(&mut self, number: u32) -> Option<&mut Breakpoint> {
+        self.breakpoints.iter_mut().find(|bp| bp.number == number)
+    }
+
+    pub(super) fn delete_breakpoint(&mut self, number: u32) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|bp| bp.number != number);
+        self.breakpoints.len() != len
+    }
+
+    /// Enable or disable breakpoint `number`, or all of them.
+    pub(super) fn set_enabled(&mut self, number: Option<u32>, enabled: bool) -> bool {
+        match number {
+            Some(number) => match self.find(number) {
+                Some(bp) => {
+                    bp.enabled = enabled;
+                    true
+                }
+                None => false,
+            },
+            None => {
                 self.breakpoints
-                    .push(Breakpoint::new(exp.to_string(), value));
-                Some((self.breakpoints.len() - 1) as u8)
+                    .iter_mut()
+                    .for_each(|bp| bp.enabled = enabled);
+                true
             }
-            None => None,
         }
     }
 
-    pub(super) fn check_breakpoint(&self, cpu: &mut impl ISA) -> bool {
-        for bp in &self.breakpoints {
-            if bp.valid {
-                let value = eval(cpu, &bp.exp).unwrap();
-                if bp.original_value != value {
-                    println!("Breakpoint hit: {} = {}", bp.exp, value);
-                    return true;
-                }
+    /// number of the most recently set breakpoint
+    pub(super) fn last(&self) -> Option<u32> {
+        self.breakpoints.last().map(|bp| bp.number)
+    }
+
+    /// Set or remove the condition of breakpoint `number`.
+    pub(super) fn condition(
+        &mut self,
+        cpu: &mut impl ISA,
+        symbols: &SymbolTable,
+        number: u32,
+        condition: Option<String>,
+    ) -> Result<(), RError> {
+        if let Some(condition) = &condition {
+            eval(cpu, symbols, condition)?;
+        }
+        match self.find(number) {
+            Some(bp) => {
+                bp.condition = condition;
+                Ok(())
             }
+            None => Err(RError::DebuggerError(format!(
+                "No breakpoint number {}.",
+                number
+            ))),
         }
-        false
     }
 
-    pub(super) fn delete_breakpoint(&mut self, index: usize) {
-        if index >= self.breakpoints.len() {
-            return;
+    /// Replace the commands run when breakpoint `number` stops the program.
+    pub(super) fn set_commands(&mut self, number: u32, commands: Vec<String>) -> bool {
+        match self.find(number) {
+            Some(bp) => {
+                bp.commands = commands;
+                true
+            }
+            None => false,
         }
-        self.breakpoints[index].disable();
     }
 
-    pub(super) fn show(&self) {
-        for (i, bp) in self.breakpoints.iter().enumerate() {
-            if i == 0 {
+    pub(super) fn ignore(&mut self, number: u32, count: u64) -> bool {
+        match self.find(number) {
+            Some(bp) => {
+                bp.ignore = count;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Prepare for running: take the current value of watched expressions
+    /// and trace memory accesses if a read or access watchpoint is set.
+    pub(super) fn arm(&mut self, cpu: &mut impl ISA, symbols: &SymbolTable) {
+        let mut trace = false;
+        for bp in self.breakpoints.iter_mut().filter(|bp| bp.enabled) {
+            match &mut bp.kind {
+                Kind::Watch { exp, value } => *value = eval(cpu, symbols, exp).ok(),
+                Kind::Access { .. } => trace = true,
+                Kind::Catch { mode, .. } => *mode = cpu.privilege(),
+                Kind::Exec { .. } => (),
+            }
+        }
+        cpu.trace_accesses(trace);
+        cpu.take_trap();
+    }
+
+    /// Check the breakpoints after a step, returning what stopped the
+    /// program if any. Temporary breakpoints are deleted once they stop it.
+    pub(super) fn check(&mut self, cpu: &mut impl ISA, symbols: &SymbolTable) -> Option<Hit> {
+        self.stop(cpu, symbols, Check::Retired)
+    }
+
+    /// Check the catchpoints after a step that failed, the instruction
+    /// not being retired.
+    pub(super) fn check_fault(&mut self, cpu: &mut impl ISA, symbols: &SymbolTable) -> Option<Hit> {
+        self.stop(cpu, symbols, Check::Fault)
+    }
+
+    /// Check the breakpoints on the pc before running from it.
+    pub(super) fn check_entry(&mut self, cpu: &mut impl ISA, symbols: &SymbolTable) -> Option<Hit> {
+        self.stop(cpu, symbols, Check::Entry)
+    }
+
+    fn stop(&mut self, cpu: &mut impl ISA, symbols: &SymbolTable, check: Check) -> Option<Hit> {
+        let pc = cpu.pc();
+        let accesses = cpu.take_accesses();
+        let trap = cpu.take_trap();
+        let privilege = cpu.privilege();
+        let mut hit = Hit::default();
+        let mut reports = vec![];
+        let mut stopped = false;
+        let mut expired = vec![];
+        for bp in self.breakpoints.iter_mut().filter(|bp| bp.enabled) {
+            let checked = match check {
+                Check::Retired => true,
+                Check::Fault => matches!(bp.kind, Kind::Catch { .. }),
+                Check::Entry => matches!(bp.kind, Kind::Exec { .. }),
+            };
+            if !checked {
                 continue;
             }
-            if bp.valid {
-                println!("{}: {}", i, bp.exp);
+            let number = bp.number;
+            let report = match &mut bp.kind {
+                Kind::Exec { addr, location } => (*addr == pc)
+                    .then(|| format!("Breakpoint {}, {:#x} in {}", number, pc, location)),
+                Kind::Watch { exp, value } => {
+                    let current = eval(cpu, symbols, exp).ok();
+                    if current == *value {
+                        None
+                    } else {
+                        let report = format!(
+                            "Watchpoint {}: {}\nOld value = {}\nNew value = {}",
+                            number,
+                            exp,
+                            show_value(*value),
+                            show_value(current)
+                        );
+                        *value = current;
+                        Some(report)
+                    }
+                }
+                Kind::Access {
+                    exp,
+                    addr,
+                    read_only,
+                } => accesses
+                    .iter()
+                    .filter(|access| !*read_only || access.kind == AccessKind::Read)
+                    .find(|access| access.overlaps(*addr, WATCH_WIDTH))
+                    .map(|access| {
+                        let value = cpu.peek_mem(*addr, WATCH_WIDTH as u8);
+                        let label = if *read_only { "read" } else { "access" };
+                        format!(
+                            "Hardware {} watchpoint {}: {} ({:?})\nValue = {}",
+                            label,
+                            number,
+                            exp,
+                            access.kind,
+                            show_value(value.map(|v| v as u64))
+                        )
+                    }),
+                Kind::Catch {
+                    catch: Catch::ModeSwitch,
+                    mode,
+                } => {
+                    let previous = std::mem::replace(mode, privilege);
+                    (previous != privilege).then(|| {
+                        format!(
+                            "Catchpoint {} (mode switch from {} to {}), {:#x}",
+                            number,
+                            mode_name(previous),
+                            mode_name(privilege),
+                            pc
+                        )
+                    })
+                }
+                Kind::Catch { catch, .. } => trap
+                    .and_then(|trap| catch.catches(&trap, cpu))
+                    .map(|report| format!("Catchpoint {} ({}), {:#x}", number, report, pc)),
+            };
+            let Some(mut report) = report else {
+                continue;
+            };
+            if let Some(condition) = &bp.condition {
+                match eval(cpu, symbols, condition) {
+                    Ok(0) => continue,
+                    Ok(_) => (),
+                    Err(e) => report.push_str(&format!(
+                        "\nError in testing condition {}, {}",
+                        condition, e
+                    )),
+                }
+            }
+            bp.hits += 1;
+            if bp.ignore > 0 {
+                bp.ignore -= 1;
+                continue;
             }
+            stopped = true;
+            match bp.commands.first() {
+                Some(first) if first == "silent" => (),
+                _ => reports.push(report),
+            }
+            hit.commands
+                .extend(bp.commands.iter().filter(|c| *c != "silent").cloned());
+            if bp.temporary {
+                expired.push(bp.number);
+            }
+        }
+        self.breakpoints.retain(|bp| !expired.contains(&bp.number));
+        if !stopped {
+            return None;
         }
+        hit.report = reports.join("\n");
+        Some(hit)
     }
 
-    pub(super) fn exists(&self) -> bool {
-        for bp in &self.breakpoints {
-            if bp.valid {
-                return true;
+    pub(super) fn show(&self) {
+        if self.breakpoints.is_empty() {
+            println!("No breakpoints or watchpoints.");
+            return;
+        }
+        println!(
+            "{:<4} {:<16} {:<4} {:<6} What",
+            "Num", "Type", "Enb", "Hits"
+        );
+        for bp in self.breakpoints.iter() {
+            println!(
+                "{:<4} {:<16} {:<4} {:<6} {}",
+                bp.number,
+                bp.type_name(),
+                if bp.enabled { "y" } else { "n" },
+                bp.hits,
+                bp.what()
+            );
+            if let Some(condition) = &bp.condition {
+                println!("\tstop only if {}", condition);
+            }
+            if bp.hits > 0 {
+                println!("\tbreakpoint already hit {} time(s)", bp.hits);
+            }
+            if bp.ignore > 0 {
+                println!("\tWill ignore next {} crossings of breakpoint.", bp.ignore);
+            }
+            for command in bp.commands.iter() {
+                println!("\t    {}", command);
             }
         }
-        false
+    }
+}
+
+fn show_value(value: Option<u64>) -> String {
+    match value {
+        Some(value) => format!("{:#x}", value),
+        None => "<unavailable>".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isas::{MemoryModel, RegisterModel, RV32CPU};
+
+    #[test]
+    fn test_exec_breakpoint() {
+        let mut cpu = RV32CPU::default();
+        let mut bps = Breakpoints::new();
+        let symbols = SymbolTable::default();
+        assert_eq!(
+            bps.break_at(&mut cpu, &symbols, "0x80000100", false).ok(),
+            Some((1, 0x80000100))
+        );
+        bps.arm(&mut cpu, &symbols);
+        cpu.update_pc(0x80000100);
+        assert!(bps.check(&mut cpu, &symbols).is_some());
+
+        assert!(bps.ignore(1, 1));
+        assert!(bps.check(&mut cpu, &symbols).is_none());
+        assert!(bps.check(&mut cpu, &symbols).is_some());
+
+        assert!(bps.set_enabled(Some(1), false));
+        assert!(bps.check(&mut cpu, &symbols).is_none());
+        assert!(bps.delete_breakpoint(1));
+        assert!(!bps.delete_breakpoint(1));
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut cpu = RV32CPU::default();
+        let mut bps = Breakpoints::new();
+        let symbols = SymbolTable::default();
+        assert_eq!(
+            bps.watch(&mut cpu, &symbols, "$a0", WatchKind::Write).ok(),
+            Some(1)
+        );
+        assert!(bps
+            .watch(&mut cpu, &symbols, "$a0", WatchKind::Read)
+            .is_err());
+        assert_eq!(
+            bps.watch(&mut cpu, &symbols, "*0x1000", WatchKind::Read)
+                .ok(),
+            Some(2)
+        );
+        bps.arm(&mut cpu, &symbols);
+        assert!(bps.check(&mut cpu, &symbols).is_none());
+
+        cpu.write_register_by_name("a0", 1);
+        assert!(bps
+            .check(&mut cpu, &symbols)
+            .unwrap()
+            .report
+            .starts_with("Watchpoint 1"));
+        assert!(bps.check(&mut cpu, &symbols).is_none());
+
+        cpu.store_mem(0x1000, 4, 7);
+        assert!(bps.check(&mut cpu, &symbols).is_none());
+        cpu.load_mem(0x1002, 1);
+        assert!(bps
+            .check(&mut cpu, &symbols)
+            .unwrap()
+            .report
+            .starts_with("Hardware read watchpoint 2"));
+    }
+
+    #[test]
+    fn test_conditional_breakpoints() {
+        let mut cpu = RV32CPU::default();
+        let mut bps = Breakpoints::new();
+        let symbols = SymbolTable::default();
+        assert_eq!(
+            bps.break_at(&mut cpu, &symbols, "0x1000 if $a0 == 2", false)
+                .ok(),
+            Some((1, 0x1000))
+        );
+        assert!(bps
+            .break_at(&mut cpu, &symbols, "0x1000 if $a0 ==", false)
+            .is_err());
+        assert!(bps.set_commands(1, vec!["silent".to_string(), "p $a0".to_string()]));
+        cpu.update_pc(0x1000);
+        assert_eq!(bps.check(&mut cpu, &symbols), None);
+        cpu.write_register_by_name("a0", 2);
+        assert_eq!(
+            bps.check(&mut cpu, &symbols),
+            Some(Hit {
+                report: String::new(),
+                commands: vec!["p $a0".to_string()],
+            })
+        );
+
+        assert!(bps.condition(&mut cpu, &symbols, 1, None).is_ok());
+        assert!(bps.condition(&mut cpu, &symbols, 9, None).is_err());
+        assert!(bps.set_enabled(Some(1), false));
+        assert_eq!(
+            bps.break_at(&mut cpu, &symbols, "0x1000", true).ok(),
+            Some((2, 0x1000))
+        );
+        assert!(bps
+            .check(&mut cpu, &symbols)
+            .unwrap()
+            .report
+            .starts_with("Breakpoint 2"));
+        assert_eq!(bps.last(), Some(1));
+        assert_eq!(bps.check(&mut cpu, &symbols), None);
+    }
+
+    #[test]
+    fn test_catchpoints() {
+        use crate::isas::ISA;
+
+        let mut cpu = RV32CPU::default();
+        let mut bps = Breakpoints::new();
+        let symbols = SymbolTable::default();
+        // ecall; an invalid instruction
+        cpu.store_mem(0x1000, 4, 0x00000073);
+        cpu.store_mem(0x1004, 4, 0xffffffff);
+        cpu.write_register_by_name("mtvec", 0x2000);
+        cpu.write_register_by_name("a7", 64);
+        cpu.update_pc(0x1000);
+        assert_eq!(bps.catch(&cpu, Catch::Syscall(Some(93))), 1);
+        assert_eq!(bps.catch(&cpu, Catch::ModeSwitch), 2);
+        bps.arm(&mut cpu, &symbols);
+        cpu.step().unwrap();
+        let report = bps.check(&mut cpu, &symbols).unwrap().report;
+        assert_eq!(
+            report,
+            "Catchpoint 2 (mode switch from Supervisor to Machine), 0x2000"
+        );
+
+        assert_eq!(bps.catch(&cpu, Catch::Exception(Some(2))), 3);
+        bps.arm(&mut cpu, &symbols);
+        cpu.update_pc(0x1004);
+        assert!(cpu.step().is_err());
+        assert!(bps
+            .check_fault(&mut cpu, &symbols)
+            .unwrap()
+            .report
+            .starts_with("Catchpoint 3 (exception illegal instruction at 0x1004)"));
+
+        // sw zero, 0(t0) with nothing mapped at t0, a store access fault
+        cpu.store_mem(0x1008, 4, 0x0002a023);
+        cpu.write_register_by_name("t0", 0xfffffff0);
+        assert_eq!(bps.catch(&cpu, Catch::Exception(Some(7))), 4);
+        bps.arm(&mut cpu, &symbols);
+        cpu.update_pc(0x1008);
+        assert!(cpu.step().is_err());
+        assert!(bps
+            .check_fault(&mut cpu, &symbols)
+            .unwrap()
+            .report
+            .starts_with("Catchpoint 4 (exception store access fault at 0x1008)"));
     }
 }
@@ -0,0 +1,219 @@
+//! Catchpoints: stopping on traps and privilege changes rather than at an
+//! address. Causes are decoded as in the RISC-V `mcause` register.
+
+use std::fmt::Display;
+
+use crate::isas::{Trap, ISA};
+use rconfig::syscall;
+
+/// register holding the syscall number, `a7`
+const SYSCALL_REG: u32 = syscall::SYSCALL_REG_NUM;
+
+const SYSCALLS: [(u32, &str); 11] = [
+    (syscall::SYSCALL_EXIT, "exit"),
+    (syscall::SYSCALL_WRITE, "write"),
+    (syscall::SYSCALL_READ, "read"),
+    (syscall::SYSCALL_OPEN, "open"),
+    (syscall::SYSCALL_CLOSE, "close"),
+    (syscall::SYSCALL_FORK, "fork"),
+    (syscall::SYSCALL_EXEC, "exec"),
+    (syscall::SYSCALL_WAITPID, "waitpid"),
+    (syscall::SYSCALL_GETPID, "getpid"),
+    (syscall::SYSCALL_SLEEP, "sleep"),
+    (syscall::SYSCALL_SBARK, "sbrk"),
+];
+
+const EXCEPTIONS: [(u32, &str); 14] = [
+    (0, "instruction address misaligned"),
+    (1, "instruction access fault"),
+    (2, "illegal instruction"),
+    (3, "breakpoint"),
+    (4, "load address misaligned"),
+    (5, "load access fault"),
+    (6, "store address misaligned"),
+    (7, "store access fault"),
+    (8, "environment call from U-mode"),
+    (9, "environment call from S-mode"),
+    (11, "environment call from M-mode"),
+    (12, "instruction page fault"),
+    (13, "load page fault"),
+    (15, "store page fault"),
+];
+
+const INTERRUPTS: [(u32, &str); 6] = [
+    (1, "supervisor software interrupt"),
+    (3, "machine software interrupt"),
+    (5, "supervisor timer interrupt"),
+    (7, "machine timer interrupt"),
+    (9, "supervisor external interrupt"),
+    (11, "machine external interrupt"),
+];
+
+const MODES: [&str; 4] = ["User", "Supervisor", "Reserved", "Machine"];
+
+/// environment calls from user, supervisor and machine mode
+fn is_ecall(cause: u32) -> bool {
+    matches!(cause, 8 | 9 | 11)
+}
+
+fn syscall_name(number: u32) -> Option<&'static str> {
+    SYSCALLS
+        .iter()
+        .find(|(n, _)| *n == number)
+        .map(|(_, name)| *name)
+}
+
+/// Look up a number or a name in `table`, names matching regardless of
+/// case with `-` or `_` for spaces.
+fn parse_code(table: &[(u32, &str)], spec: &str) -> Option<u32> {
+    if let Ok(code) = spec.parse() {
+        return Some(code);
+    }
+    let spec = spec.replace(['-', '_'], " ");
+    table
+        .iter()
+        .find(|(_, name)| name.eq_ignore_ascii_case(&spec))
+        .map(|(code, _)| *code)
+}
+
+/// Decoded cause of `trap`.
+pub(super) fn describe(trap: &Trap) -> String {
+    let table: &[(u32, &str)] = match trap.interrupt {
+        true => &INTERRUPTS,
+        false => &EXCEPTIONS,
+    };
+    match table.iter().find(|(code, _)| *code == trap.cause) {
+        Some((_, name)) => name.to_string(),
+        None => format!("cause {}", trap.cause),
+    }
+}
+
+pub(super) fn mode_name(mode: u8) -> &'static str {
+    MODES.get(mode as usize).copied().unwrap_or("Unknown")
+}
+
+/// What a catchpoint stops on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Catch {
+    /// an environment call, of one syscall number or any
+    Syscall(Option<u32>),
+    /// an exception, of one cause or any
+    Exception(Option<u32>),
+    Interrupt,
+    /// a change of privilege level
+    ModeSwitch,
+}
+
+impl Catch {
+    /// Parse the arguments of `catch`: `syscall [number|name]`,
+    /// `exception [cause]`, `interrupt` or `mode-switch`.
+    pub(super) fn parse(spec: &str) -> Option<Catch> {
+        let (event, arg) = match spec.trim().split_once(char::is_whitespace) {
+            Some((event, arg)) => (event, Some(arg.trim())),
+            None => (spec.trim(), None),
+        };
+        match (event, arg) {
+            ("syscall", None) => Some(Catch::Syscall(None)),
+            ("syscall", Some(arg)) => parse_code(&SYSCALLS, arg).map(|n| Catch::Syscall(Some(n))),
+            ("exception", None) => Some(Catch::Exception(None)),
+            ("exception", Some(arg)) => {
+                parse_code(&EXCEPTIONS, arg).map(|cause| Catch::Exception(Some(cause)))
+            }
+            ("interrupt", None) => Some(Catch::Interrupt),
+            ("mode-switch", None) => Some(Catch::ModeSwitch),
+            _ => None,
+        }
+    }
+
+    /// Report of `trap` if it is caught, read just after it was taken.
+    pub(super) fn catches(&self, trap: &Trap, cpu: &impl ISA) -> Option<String> {
+        match *self {
+            Catch::Syscall(number) if !trap.interrupt && is_ecall(trap.cause) => {
+                let syscall = cpu[SYSCALL_REG];
+                if number.is_some_and(|number| number != syscall) {
+                    return None;
+                }
+                let name = syscall_name(syscall).unwrap_or("unknown");
+                Some(format!(
+                    "call to syscall {} ({}), {} at {:#x}",
+                    name,
+                    syscall,
+                    describe(trap),
+                    trap.pc
+                ))
+            }
+            Catch::Exception(cause) if !trap.interrupt => {
+                if cause.is_some_and(|cause| cause != trap.cause) {
+                    return None;
+                }
+                Some(format!("exception {} at {:#x}", describe(trap), trap.pc))
+            }
+            Catch::Interrupt if trap.interrupt => {
+                Some(format!("interrupt {} at {:#x}", describe(trap), trap.pc))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for Catch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Catch::Syscall(None) => write!(f, "syscall"),
+            Catch::Syscall(Some(number)) => match syscall_name(*number) {
+                Some(name) => write!(f, "syscall \"{}\"", name),
+                None => write!(f, "syscall {}", number),
+            },
+            Catch::Exception(None) => write!(f, "exception"),
+            Catch::Exception(Some(cause)) => {
+                let trap = Trap {
+                    interrupt: false,
+                    cause: *cause,
+                    pc: 0,
+                };
+                write!(f, "exception \"{}\"", describe(&trap))
+            }
+            Catch::Interrupt => write!(f, "interrupt"),
+            Catch::ModeSwitch => write!(f, "mode-switch"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isas::RV32CPU;
+
+    #[test]
+    fn test_catch() {
+        assert_eq!(Catch::parse("syscall"), Some(Catch::Syscall(None)));
+        assert_eq!(
+            Catch::parse("syscall write"),
+            Some(Catch::Syscall(Some(64)))
+        );
+        assert_eq!(Catch::parse("syscall 93"), Some(Catch::Syscall(Some(93))));
+        assert_eq!(Catch::parse("syscall nosuch"), None);
+        assert_eq!(
+            Catch::parse("exception illegal-instruction"),
+            Some(Catch::Exception(Some(2)))
+        );
+        assert_eq!(Catch::parse("mode-switch"), Some(Catch::ModeSwitch));
+        assert_eq!(Catch::parse("interrupt 3"), None);
+
+        let mut cpu = RV32CPU::default();
+        cpu[SYSCALL_REG] = 64;
+        let ecall = Trap {
+            interrupt: false,
+            cause: 8,
+            pc: 0x1000,
+        };
+        assert_eq!(
+            Catch::Syscall(Some(64)).catches(&ecall, &cpu).as_deref(),
+            Some("call to syscall write (64), environment call from U-mode at 0x1000")
+        );
+        assert_eq!(Catch::Syscall(Some(93)).catches(&ecall, &cpu), None);
+        assert!(Catch::Exception(None).catches(&ecall, &cpu).is_some());
+        assert_eq!(Catch::Exception(Some(2)).catches(&ecall, &cpu), None);
+        assert_eq!(Catch::Interrupt.catches(&ecall, &cpu), None);
+    }
+}
@@ -1,8 +1,24 @@
-use super::{breakpoint::Breakpoints, eval::eval};
+use super::breakpoint::{Breakpoints, Hit, WatchKind};
+use super::catchpoint::Catch;
+use super::eval::{eval, location};
+use super::examine::Examine;
+use super::frames::{backtrace, Frame};
+use super::interrupt::Interrupt;
+use super::prompt::Prompt;
+use super::symbols::SymbolTable;
+use super::tui::Tui;
 use crate::error::RError;
+use crate::exes::ELF;
 use crate::isas::ISA;
 use colored::Colorize;
-use std::io::Write;
+use std::collections::{HashSet, VecDeque};
+
+/// instructions between two checkpoints kept for reverse execution
+const HISTORY_INTERVAL: u64 = 10000;
+/// the largest range `disas` dumps, in bytes
+const MAX_DISAS: u32 = 0x10000;
+/// source lines shown on each side of pc by `show src`
+const SOURCE_CONTEXT: u32 = 5;
 
 #[derive(Debug, PartialEq)]
 enum DebuggerState {
@@ -17,17 +33,69 @@ enum DebuggerCommand {
     Run,
     Continue,
     Step(u64),
+    ReverseStep(u64),
+    ReverseContinue,
+    LastChange(String),
     Print(String),
+    Examine(Examine, String),
+    /// `display[/fmt] expr`, or list the displays with no expression
+    Display(Option<Examine>, String),
+    Undisplay(Option<u32>),
+    Set(u8, String, String), // width, lvalue, value
+    Jump(String),
+    Disassemble(Option<String>),
+    Backtrace(Option<usize>),
+    Frame(Option<usize>),
+    Up(usize),
+    Down(usize),
+    Finish,
+    Next(u64),
+    StepLine(u64),
+    NextLine(u64),
+    Until(String),
     Breakpoint(String),
-    Delete(u8), // delete breakpoint
-    Blank,      // blank line
+    TBreak(String),
+    Condition(u32, Option<String>),
+    Commands(Option<u32>),
+    Watch(WatchKind, String),
+    Catch(Catch),
+    Enable(Option<u32>),
+    Disable(Option<u32>),
+    Ignore(u32, u64),
+    Delete(u32), // delete breakpoint
+    Blank,       // blank line
     Show(String),
+    Layout(String),
     Help,
     Quit,
+    Source(String),
     Clear,
 }
 
 impl DebuggerCommand {
+    /// Whether an empty line runs the command again, as for stepping
+    /// and inspecting but not for commands that change the setup.
+    fn repeats(&self) -> bool {
+        matches!(
+            self,
+            DebuggerCommand::Continue
+                | DebuggerCommand::Step(_)
+                | DebuggerCommand::ReverseStep(_)
+                | DebuggerCommand::ReverseContinue
+                | DebuggerCommand::Print(_)
+                | DebuggerCommand::Examine(..)
+                | DebuggerCommand::Disassemble(_)
+                | DebuggerCommand::Backtrace(_)
+                | DebuggerCommand::Up(_)
+                | DebuggerCommand::Down(_)
+                | DebuggerCommand::Finish
+                | DebuggerCommand::Next(_)
+                | DebuggerCommand::StepLine(_)
+                | DebuggerCommand::NextLine(_)
+                | DebuggerCommand::Show(_)
+        )
+    }
+
     fn parse(input: &str) -> Option<DebuggerCommand> {
         let mut tokens = input.split_whitespace();
         let command = tokens.next();
@@ -47,43 +115,264 @@ impl DebuggerCommand {
                     None => Some(DebuggerCommand::Step(1)),
                 }
             }
+            Some("rs") | Some("reverse-step") => {
+                let count = tokens.next();
+                match count {
+                    Some(count) => count.parse::<u64>().ok().map(DebuggerCommand::ReverseStep),
+                    None => Some(DebuggerCommand::ReverseStep(1)),
+                }
+            }
+            Some("rc") | Some("reverse-continue") => Some(DebuggerCommand::ReverseContinue),
+            Some("last-change") => {
+                let exp = tokens.collect::<Vec<_>>().join(" ");
+                if exp.is_empty() {
+                    None
+                } else {
+                    Some(DebuggerCommand::LastChange(exp))
+                }
+            }
             Some("p") | Some("print") => {
-                let reg = tokens.next();
-                reg.map(|reg| DebuggerCommand::Print(reg.to_string()))
+                let exp = tokens.collect::<Vec<_>>().join(" ");
+                (!exp.is_empty()).then_some(DebuggerCommand::Print(exp))
+            }
+            Some(command) if command == "x" || command.starts_with("x/") => {
+                let examine = Examine::parse(command.strip_prefix("x/").unwrap_or(""))?;
+                let exp = tokens.collect::<Vec<_>>().join(" ");
+                (!exp.is_empty()).then_some(DebuggerCommand::Examine(examine, exp))
+            }
+            Some(command) if command == "display" || command.starts_with("display/") => {
+                let examine = match command.strip_prefix("display/") {
+                    Some(spec) => Some(Examine::parse(spec)?),
+                    None => None,
+                };
+                let exp = tokens.collect::<Vec<_>>().join(" ");
+                match examine {
+                    Some(_) if exp.is_empty() => None,
+                    _ => Some(DebuggerCommand::Display(examine, exp)),
+                }
+            }
+            Some("undisplay") => match tokens.next() {
+                Some(number) => number
+                    .parse()
+                    .ok()
+                    .map(|n| DebuggerCommand::Undisplay(Some(n))),
+                None => Some(DebuggerCommand::Undisplay(None)),
+            },
+            Some(command) if command == "set" || command.starts_with("set/") => {
+                let width = match command.strip_prefix("set/") {
+                    None | Some("w") => 4,
+                    Some("h") => 2,
+                    Some("b") => 1,
+                    Some(_) => return None,
+                };
+                let assignment = tokens.collect::<Vec<_>>().join(" ");
+                let (lvalue, value) = split_assignment(&assignment)?;
+                Some(DebuggerCommand::Set(width, lvalue, value))
+            }
+            Some("j") | Some("jump") => {
+                let location = tokens.collect::<Vec<_>>().join(" ");
+                (!location.is_empty()).then_some(DebuggerCommand::Jump(location))
+            }
+            Some("disas") | Some("disassemble") => {
+                let spec = tokens.collect::<Vec<_>>().join(" ");
+                Some(DebuggerCommand::Disassemble(
+                    (!spec.is_empty()).then_some(spec),
+                ))
+            }
+            Some("bt") | Some("backtrace") | Some("where") => match tokens.next() {
+                Some(count) => count
+                    .parse()
+                    .ok()
+                    .map(|n| DebuggerCommand::Backtrace(Some(n))),
+                None => Some(DebuggerCommand::Backtrace(None)),
+            },
+            Some("f") | Some("frame") => match tokens.next() {
+                Some(number) => number.parse().ok().map(|n| DebuggerCommand::Frame(Some(n))),
+                None => Some(DebuggerCommand::Frame(None)),
+            },
+            Some(command @ ("up" | "down")) => {
+                let count = match tokens.next() {
+                    Some(count) => count.parse().ok()?,
+                    None => 1,
+                };
+                match command {
+                    "up" => Some(DebuggerCommand::Up(count)),
+                    _ => Some(DebuggerCommand::Down(count)),
+                }
+            }
+            Some("fin") | Some("finish") => Some(DebuggerCommand::Finish),
+            Some("n") | Some("next") | Some("ni") | Some("nexti") => match tokens.next() {
+                Some(count) => count.parse().ok().map(DebuggerCommand::Next),
+                None => Some(DebuggerCommand::Next(1)),
+            },
+            Some(command @ ("sl" | "step-line" | "nl" | "next-line")) => {
+                let count = match tokens.next() {
+                    Some(count) => count.parse().ok()?,
+                    None => 1,
+                };
+                match command {
+                    "sl" | "step-line" => Some(DebuggerCommand::StepLine(count)),
+                    _ => Some(DebuggerCommand::NextLine(count)),
+                }
+            }
+            Some("u") | Some("until") | Some("advance") => {
+                let location = tokens.collect::<Vec<_>>().join(" ");
+                (!location.is_empty()).then_some(DebuggerCommand::Until(location))
+            }
+            Some("b") | Some("break") | Some("breakpoint") => {
+                let location = tokens.collect::<Vec<_>>().join(" ");
+                (!location.is_empty()).then_some(DebuggerCommand::Breakpoint(location))
+            }
+            Some("tb") | Some("tbreak") => {
+                let location = tokens.collect::<Vec<_>>().join(" ");
+                (!location.is_empty()).then_some(DebuggerCommand::TBreak(location))
+            }
+            Some("condition") => {
+                let number = tokens.next()?.parse().ok()?;
+                let condition = tokens.collect::<Vec<_>>().join(" ");
+                Some(DebuggerCommand::Condition(
+                    number,
+                    (!condition.is_empty()).then_some(condition),
+                ))
+            }
+            Some("commands") => match tokens.next() {
+                Some(number) => number
+                    .parse()
+                    .ok()
+                    .map(|n| DebuggerCommand::Commands(Some(n))),
+                None => Some(DebuggerCommand::Commands(None)),
+            },
+            Some(command @ ("watch" | "rwatch" | "awatch")) => {
+                let kind = match command {
+                    "watch" => WatchKind::Write,
+                    "rwatch" => WatchKind::Read,
+                    _ => WatchKind::Access,
+                };
+                let exp = tokens.collect::<Vec<_>>().join(" ");
+                (!exp.is_empty()).then_some(DebuggerCommand::Watch(kind, exp))
+            }
+            Some("catch") => {
+                Catch::parse(&tokens.collect::<Vec<_>>().join(" ")).map(DebuggerCommand::Catch)
             }
-            Some("b") | Some("breakpoint") => {
-                let addr = tokens.next();
-                addr.map(|addr| DebuggerCommand::Breakpoint(addr.to_string()))
+            Some("enable") => match tokens.next() {
+                Some(number) => number
+                    .parse()
+                    .ok()
+                    .map(|n| DebuggerCommand::Enable(Some(n))),
+                None => Some(DebuggerCommand::Enable(None)),
+            },
+            Some("disable") => match tokens.next() {
+                Some(number) => number
+                    .parse()
+                    .ok()
+                    .map(|n| DebuggerCommand::Disable(Some(n))),
+                None => Some(DebuggerCommand::Disable(None)),
+            },
+            Some("ignore") => {
+                let number = tokens.next()?.parse().ok()?;
+                let count = tokens.next()?.parse().ok()?;
+                Some(DebuggerCommand::Ignore(number, count))
             }
             Some("h") | Some("help") => Some(DebuggerCommand::Help),
             Some("q") | Some("quit") => Some(DebuggerCommand::Quit),
+            Some("source") => {
+                let path = tokens.collect::<Vec<_>>().join(" ");
+                (!path.is_empty()).then_some(DebuggerCommand::Source(path))
+            }
             Some("r") | Some("run") => Some(DebuggerCommand::Run),
             Some("d") | Some("delete") => {
                 let number = tokens.next();
                 match number {
                     Some(number) => {
-                        let count = number.parse::<u8>();
+                        let count = number.parse::<u32>();
                         match count {
                             Ok(count) => Some(DebuggerCommand::Delete(count)),
                             Err(_) => None,
                         }
                     }
-                    None => Some(DebuggerCommand::Step(1)),
+                    None => None,
                 }
             }
-            Some("show") | Some("layout") => {
+            Some("show") => {
                 let layout = tokens.next();
                 layout.map(|layout| DebuggerCommand::Show(layout.to_string()))
             }
+            Some("l") | Some("layout") => {
+                let layout = tokens.next();
+                layout.map(|layout| DebuggerCommand::Layout(layout.to_string()))
+            }
             None => Some(DebuggerCommand::Blank),
             _ => None,
         }
     }
 }
 
+/// Print an error of a command, in red.
+fn report(e: RError) {
+    match e {
+        RError::DebuggerError(message) => {
+            print!("{}", "Error".red());
+            println!(": {}", message);
+        }
+        e => println!("{}", e),
+    }
+}
+
+/// Split `lvalue = value` at the `=` that is not part of a comparison.
+fn split_assignment(assignment: &str) -> Option<(String, String)> {
+    let bytes = assignment.as_bytes();
+    let pos = (0..bytes.len()).find(|&i| {
+        bytes[i] == b'='
+            && bytes.get(i + 1) != Some(&b'=')
+            && (i == 0 || !b"=!<>".contains(&bytes[i - 1]))
+    })?;
+    let lvalue = assignment[..pos].trim();
+    let value = assignment[pos + 1..].trim();
+    if lvalue.is_empty() || value.is_empty() {
+        return None;
+    }
+    Some((lvalue.to_string(), value.to_string()))
+}
+
 pub struct Debugger {
     state: DebuggerState,
     bps: Breakpoints,
+    symbols: SymbolTable,
+    /// commands queued by breakpoints, run before reading more input
+    pending: VecDeque<String>,
+    /// stack frame selected by `frame`, `up` and `down`
+    frame: usize,
+    /// read commands only from sourced files, echoing them
+    batch: bool,
+    /// commands executed so far, numbering the echoes of batch mode
+    executed: u64,
+    /// exit code of the program once it has exited
+    exit_code: Option<i8>,
+    /// the split-pane layout, when enabled
+    tui: Option<Tui>,
+    /// expressions shown at every stop
+    displays: Vec<Display>,
+    next_display: u32,
+    /// instruction count and pc of the last stop
+    stop: Option<(u64, u32)>,
+    /// pc the program stopped at after running, which it resumes from
+    /// without stopping at its breakpoint again
+    stopped_at: Option<u32>,
+    /// registers at the last stop, and those changed since the stop before
+    registers: Vec<(String, u32)>,
+    changed: HashSet<String>,
+    prompt: Prompt,
+    /// the last command typed, run again by an empty line
+    repeat: Option<String>,
+}
+
+/// An expression shown at every stop, `x/fmt expr` when it has a format.
+struct Display {
+    number: u32,
+    format: Option<Examine>,
+    exp: String,
+    /// how it was given, `x/fmt expr` or `expr`
+    label: String,
 }
 
 impl Default for Debugger {
@@ -97,73 +386,551 @@ impl Debugger {
         Debugger {
             state: DebuggerState::Init,
             bps: Breakpoints::new(),
+            symbols: SymbolTable::default(),
+            pending: VecDeque::new(),
+            frame: 0,
+            batch: false,
+            executed: 0,
+            exit_code: None,
+            tui: None,
+            displays: vec![],
+            next_display: 1,
+            stop: None,
+            stopped_at: None,
+            registers: vec![],
+            changed: HashSet::new(),
+            prompt: Prompt::new(),
+            repeat: None,
         }
     }
 
+    /// Run without a terminal: commands come only from files given to
+    /// `source`, and each is echoed with its result for other programs to
+    /// parse. The session ends when they are exhausted.
+    pub fn set_batch(&mut self, batch: bool) {
+        self.batch = batch;
+        colored::control::set_override(!batch);
+    }
+
+    /// Queue the commands of the file at `path` to run next. Blank lines
+    /// and lines starting with `#` are skipped.
+    pub fn source(&mut self, path: &str) -> Result<(), RError> {
+        let script = std::fs::read_to_string(path)
+            .map_err(|e| RError::IOError(format!("{}: {}", path, e)))?;
+        let commands = script
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        for command in commands.rev() {
+            self.pending.push_front(command.to_string());
+        }
+        Ok(())
+    }
+
+    /// Exit code of the program, `None` if it has not exited.
+    pub fn exit_code(&self) -> Option<i8> {
+        self.exit_code
+    }
+
+    fn exited(&mut self, code: i8) {
+        self.state = DebuggerState::Exit;
+        self.exit_code = Some(code);
+        println!("{}", RError::Ebreak(code));
+    }
+
+    /// In batch mode, end the output of the last command with the state it
+    /// left the program in.
+    fn echo_end(&self, cpu: &impl ISA) {
+        if !self.batch {
+            return;
+        }
+        let state = match self.state {
+            DebuggerState::Init => "init",
+            DebuggerState::Running => "running",
+            DebuggerState::Paused => "paused",
+            DebuggerState::Exit => "exited",
+        };
+        println!("^done {} {} pc={:#x}", self.executed, state, cpu.pc());
+    }
+
+    /// Use the symbols of the debugged program for breakpoint locations.
+    pub fn load_symbols(&mut self, elf: &ELF) {
+        self.symbols = SymbolTable::new(elf);
+    }
+
     fn continue_(&mut self, cpu: &mut impl ISA) {
+        // a breakpoint on the pc stops the program before it runs, unless
+        // the program has just stopped there
+        if self.stopped_at != Some(cpu.pc()) {
+            self.frame = 0;
+            self.bps.arm(cpu, &self.symbols);
+            let hit = self.bps.check_entry(cpu, &self.symbols);
+            if self.stop_at(cpu, hit) {
+                self.stopped_at = Some(cpu.pc());
+                return;
+            }
+        }
+        self.run_until(cpu, |_| false);
+    }
+
+    /// Run until `done` holds after a step, a breakpoint stops the program
+    /// or it exits. Returns whether `done` was reached.
+    fn run_until<I: ISA>(&mut self, cpu: &mut I, mut done: impl FnMut(&I) -> bool) -> bool {
         self.state = DebuggerState::Running;
+        self.frame = 0;
+        self.bps.arm(cpu, &self.symbols);
+        let interrupt = Interrupt::catch();
         while self.state == DebuggerState::Running {
-            match self.step(cpu, 1) {
-                Ok(_) => self.state = DebuggerState::Running,
+            if interrupt.take() {
+                println!("Program received signal SIGINT at {:#x}.", cpu.pc());
+                self.state = DebuggerState::Paused;
+                break;
+            }
+            match cpu.step() {
+                Ok(_) => {
+                    if !self.check_breakpoint(cpu) && done(cpu) {
+                        self.state = DebuggerState::Paused;
+                        return true;
+                    }
+                }
                 Err(e) => {
                     self.state = DebuggerState::Paused;
                     match e {
-                        RError::Ebreak(_) => {
-                            println!("{}", e);
-                            self.state = DebuggerState::Exit;
-                            return;
+                        RError::Ebreak(code) => {
+                            self.exited(code);
+                            return false;
                         }
+                        _ if self.check_fault(cpu) => (),
                         _ => println!("continue: {}", e),
                     }
                 }
             }
-            self.check_breakpoint(cpu);
         }
+        false
     }
 
-    fn step(&mut self, cpu: &mut impl ISA, count: u64) -> Result<(), RError> {
+    /// Execute `count` instructions, stopping early at a breakpoint or on
+    /// Ctrl-C. Returns whether it stopped early.
+    fn step(&mut self, cpu: &mut impl ISA, count: u64) -> Result<bool, RError> {
+        self.frame = 0;
+        self.bps.arm(cpu, &self.symbols);
+        self.state = DebuggerState::Paused;
+        let interrupt = Interrupt::catch();
         for _ in 0..count {
-            let step_result = cpu.step();
-            match step_result {
-                Ok(_) => (),
-                _ => return step_result,
+            if let Err(e) = cpu.step() {
+                if self.check_fault(cpu) {
+                    return Ok(true);
+                }
+                return Err(e);
+            }
+            if self.check_breakpoint(cpu) {
+                return Ok(true);
+            }
+            if interrupt.take() {
+                println!("Program received signal SIGINT at {:#x}.", cpu.pc());
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Execute `count` instructions, running a called function up to its
+    /// return as a single one.
+    fn next(&mut self, cpu: &mut impl ISA, count: u64) -> Result<(), RError> {
+        for _ in 0..count {
+            let pc = cpu.pc();
+            if cpu.is_call(pc) {
+                let sp = cpu.read_register_by_name("sp").unwrap_or_default();
+                if !self.return_to(cpu, pc.wrapping_add(4), sp) {
+                    break;
+                }
+            } else if self.step(cpu, 1)? {
+                break;
             }
         }
-        self.state = DebuggerState::Paused;
         Ok(())
     }
 
+    /// Run a called function until it returns to `addr` with the stack of
+    /// the caller, `sp`. Returns false if stopped before.
+    fn return_to(&mut self, cpu: &mut impl ISA, addr: u32, sp: u32) -> bool {
+        self.run_until(cpu, |cpu| {
+            let current = cpu.read_register_by_name("sp").unwrap_or_default();
+            cpu.pc() == addr && current >= sp
+        })
+    }
+
+    /// Execute until the start of another source line, `count` times. A
+    /// call is run as a single step with `over_calls`, or when the called
+    /// function has no line information.
+    fn line_step(
+        &mut self,
+        cpu: &mut impl ISA,
+        count: u64,
+        over_calls: bool,
+    ) -> Result<(), RError> {
+        let interrupt = Interrupt::catch();
+        for _ in 0..count {
+            let start = match self.symbols.line(cpu.pc()) {
+                Some((file, line)) => (file.to_string(), line),
+                None => {
+                    return Err(RError::DebuggerError(format!(
+                        "no line information at {:#x}, use s or n",
+                        cpu.pc()
+                    )))
+                }
+            };
+            loop {
+                let pc = cpu.pc();
+                let sp = cpu.read_register_by_name("sp").unwrap_or_default();
+                let call = cpu.is_call(pc);
+                if call && over_calls {
+                    if !self.return_to(cpu, pc.wrapping_add(4), sp) {
+                        return Ok(());
+                    }
+                } else {
+                    if self.step(cpu, 1)? {
+                        return Ok(());
+                    }
+                    let source = self.symbols.line(cpu.pc()).is_some();
+                    if call && !source && !self.return_to(cpu, pc.wrapping_add(4), sp) {
+                        return Ok(());
+                    }
+                }
+                if interrupt.take() {
+                    println!("Program received signal SIGINT at {:#x}.", cpu.pc());
+                    return Ok(());
+                }
+                let pc = cpu.pc();
+                let moved = match self.symbols.line(pc) {
+                    Some((file, line)) => (file, line) != (start.0.as_str(), start.1),
+                    None => false,
+                };
+                if moved && self.symbols.is_stmt(pc) {
+                    break;
+                }
+            }
+        }
+        self.show_source(cpu);
+        Ok(())
+    }
+
+    /// Print the source line of pc, with its text when the file is found.
+    fn show_source(&self, cpu: &impl ISA) {
+        let Some((file, line)) = self.symbols.line(cpu.pc()) else {
+            return;
+        };
+        let text = std::fs::read_to_string(file).ok().and_then(|source| {
+            source
+                .lines()
+                .nth(line.saturating_sub(1) as usize)
+                .map(str::to_string)
+        });
+        match text {
+            Some(text) => println!("{}:{}\t{}", file, line, text.trim()),
+            None => println!("{}:{}", file, line),
+        }
+    }
+
+    /// Show the source lines around pc.
+    fn show_src(&self, cpu: &impl ISA) {
+        let Some((file, line)) = self.symbols.line(cpu.pc()) else {
+            println!("No line information at {:#x}.", cpu.pc());
+            return;
+        };
+        let Ok(source) = std::fs::read_to_string(file) else {
+            println!("{}:{} (source not found)", file, line);
+            return;
+        };
+        let first = line.saturating_sub(SOURCE_CONTEXT).max(1);
+        let lines = source.lines().enumerate().skip(first as usize - 1);
+        for (number, text) in lines.take(2 * SOURCE_CONTEXT as usize + 1) {
+            let number = number as u32 + 1;
+            let marker = if number == line { ">" } else { " " };
+            println!("{}{:>5}\t{}", marker, number, text);
+        }
+    }
+
+    /// Run until `location` or until the current function returns.
+    fn until(&mut self, cpu: &mut impl ISA, location: &str) -> Result<(), RError> {
+        let addr = self.location(cpu, location)?;
+        let caller = backtrace(cpu, &self.symbols).get(1).copied();
+        let reached = self.run_until(cpu, |cpu| {
+            let sp = cpu.read_register_by_name("sp").unwrap_or_default();
+            cpu.pc() == addr
+                || caller.is_some_and(|caller| cpu.pc() == caller.pc && sp >= caller.sp)
+        });
+        if reached {
+            self.show_frame(0, &Frame::current(cpu));
+        }
+        Ok(())
+    }
+
+    /// Restore the checkpoint before `icount` and re-execute up to it.
+    fn rewind(&mut self, cpu: &mut impl ISA, icount: u64) -> Result<(), RError> {
+        self.restore(cpu, icount)?;
+        while cpu.icount() < icount {
+            cpu.step()?;
+        }
+        Ok(())
+    }
+
+    fn restore(&mut self, cpu: &mut impl ISA, icount: u64) -> Result<u64, RError> {
+        let start = cpu
+            .restore_checkpoint(icount)
+            .ok_or(RError::DebuggerError("no execution history".to_string()))?;
+        self.state = DebuggerState::Paused;
+        self.frame = 0;
+        Ok(start)
+    }
+
+    fn reverse_step(&mut self, cpu: &mut impl ISA, count: u64) -> Result<(), RError> {
+        let target = cpu.icount().saturating_sub(count);
+        let start = self.restore(cpu, target)?;
+        if start > target {
+            println!("Reached the beginning of the recorded history.");
+            return Ok(());
+        }
+        self.rewind(cpu, target)
+    }
+
+    /// Run backward until the last breakpoint or watchpoint hit.
+    fn reverse_continue(&mut self, cpu: &mut impl ISA) -> Result<(), RError> {
+        let now = cpu.icount();
+        let mut end = now;
+        loop {
+            let start = self.restore(cpu, end.saturating_sub(1))?;
+            if start >= end {
+                println!("Reached the beginning of the recorded history.");
+                return Ok(());
+            }
+            // replay the segment, remembering the last change before `now`
+            let mut hit = None;
+            let mut bps = self.bps.clone();
+            bps.arm(cpu, &self.symbols);
+            while cpu.icount() < end {
+                cpu.step()?;
+                if let Some(stop) = bps.check(cpu, &self.symbols) {
+                    if cpu.icount() < now {
+                        hit = Some((cpu.icount(), stop.report));
+                    }
+                }
+            }
+            cpu.trace_accesses(false);
+            if let Some((icount, report)) = hit {
+                self.rewind(cpu, icount)?;
+                println!("{}", report);
+                return Ok(());
+            }
+            end = start;
+        }
+    }
+
+    /// Find the last instruction that changed the value of `exp`.
+    fn last_change(&mut self, cpu: &mut impl ISA, exp: &str) -> Result<(), RError> {
+        let now = cpu.icount();
+        eval(cpu, &self.symbols, exp)?;
+        let mut end = now;
+        loop {
+            let start = self.restore(cpu, end.saturating_sub(1))?;
+            if start >= end {
+                println!("{} has not changed in the recorded history", exp);
+                return self.rewind(cpu, now);
+            }
+            let mut change = None;
+            let mut value = eval(cpu, &self.symbols, exp).ok();
+            while cpu.icount() < end {
+                let pc = cpu.pc();
+                cpu.step()?;
+                let current = eval(cpu, &self.symbols, exp).ok();
+                if current != value {
+                    change = Some((cpu.icount() - 1, pc, value, current));
+                }
+                value = current;
+            }
+            if let Some((icount, pc, old, new)) = change {
+                let inst = cpu.disassemble(pc).unwrap_or("<???>".to_string());
+                println!(
+                    "{} changed from {:#x} to {:#x} by instruction #{} at {:#x}: {}",
+                    exp,
+                    old.unwrap_or_default(),
+                    new.unwrap_or_default(),
+                    icount,
+                    pc,
+                    inst
+                );
+                return self.rewind(cpu, now);
+            }
+            end = start;
+        }
+    }
+
     fn print(&self, cpu: &mut impl ISA, exp: String) {
-        match eval(cpu, &exp) {
-            Some(value) => println!("{:#x}", value),
-            None => println!("Invalid expression"),
+        match eval(cpu, &self.symbols, &exp) {
+            Ok(value) => println!("{:#x}", value),
+            Err(e) => report(e),
         }
     }
 
-    fn make_breakpoint(&mut self, cpu: &mut impl ISA, exp: String) {
-        match self.bps.make_breakpoint(cpu, &exp) {
-            Some(bp) => println!("Breakpoint {} at {}", bp, exp),
-            None => {
+    fn make_breakpoint(&mut self, cpu: &mut impl ISA, location: String, temporary: bool) {
+        match self.bps.break_at(cpu, &self.symbols, &location, temporary) {
+            Ok((number, addr)) if temporary => {
+                println!("Temporary breakpoint {} at {:#x}", number, addr)
+            }
+            Ok((number, addr)) => println!("Breakpoint {} at {:#x}", number, addr),
+            Err(e) => report(e),
+        }
+    }
+
+    fn make_watchpoint(&mut self, cpu: &mut impl ISA, kind: WatchKind, exp: String) {
+        match self.bps.watch(cpu, &self.symbols, &exp, kind) {
+            Ok(number) => println!("Watchpoint {}: {}", number, exp),
+            Err(e) => report(e),
+        }
+    }
+
+    /// Check the breakpoints after a step, pausing and returning true on a hit.
+    fn check_breakpoint(&mut self, cpu: &mut impl ISA) -> bool {
+        let hit = self.bps.check(cpu, &self.symbols);
+        self.stop_at(cpu, hit)
+    }
+
+    /// Check the catchpoints after a step that failed, pausing and
+    /// returning true if one caught the fault.
+    fn check_fault(&mut self, cpu: &mut impl ISA) -> bool {
+        let hit = self.bps.check_fault(cpu, &self.symbols);
+        self.stop_at(cpu, hit)
+    }
+
+    fn stop_at(&mut self, cpu: &impl ISA, hit: Option<Hit>) -> bool {
+        match hit {
+            Some(hit) => {
+                if !hit.report.is_empty() {
+                    println!("{}", hit.report);
+                    self.show_source(cpu);
+                }
+                // before the rest of a sourced file
+                for command in hit.commands.into_iter().rev() {
+                    self.pending.push_front(command);
+                }
+                self.state = DebuggerState::Paused;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn no_breakpoint(number: u32) {
+        print!("{}", "Error".red());
+        println!(": No breakpoint number {}", number);
+    }
+
+    fn show(&mut self, cpu: &mut impl ISA, layout: &str) {
+        match layout {
+            s if s.starts_with("asm") => self.show_asm(cpu),
+            s if s.starts_with("reg") => self.show_registers(cpu),
+            s if s.starts_with("mem") => self.show_memory(cpu),
+            s if s.starts_with("src") => self.show_src(cpu),
+            s if s.starts_with("break") => self.bps.show(),
+            _ => {
                 print!("{}", "Error".red());
-                println!(": Invalid expression");
+                println!(": '{}' is not a valid layout argument", layout);
+            }
+        }
+    }
+
+    /// One line on the state of the program for the tui layout.
+    fn status(&self, cpu: &impl ISA) -> String {
+        let state = match self.state {
+            DebuggerState::Init => "not started",
+            DebuggerState::Running => "running",
+            DebuggerState::Paused => "paused",
+            DebuggerState::Exit => "exited",
+        };
+        let pc = cpu.pc();
+        let mut status = format!("{} at {:#x} {}", state, pc, self.symbols.label(pc));
+        if let Some((file, line)) = self.symbols.line(pc) {
+            let file = file.rsplit('/').next().unwrap_or(file);
+            status.push_str(&format!(" {}:{}", file, line));
+        }
+        status
+    }
+
+    /// Note the registers changed since the last stop and show the
+    /// displays, once per stop.
+    fn stopped(&mut self, cpu: &mut impl ISA) {
+        let stop = (cpu.icount(), cpu.pc());
+        if self.stop == Some(stop) {
+            return;
+        }
+        if self.stop.is_some_and(|(icount, _)| icount != stop.0) {
+            self.stopped_at = Some(stop.1);
+        }
+        self.stop = Some(stop);
+        let registers = cpu.iter().collect::<Vec<_>>();
+        if !self.registers.is_empty() {
+            self.changed = registers
+                .iter()
+                .filter(|register| !self.registers.contains(register))
+                .map(|(name, _)| name.clone())
+                .collect();
+        }
+        self.registers = registers;
+        if self.state != DebuggerState::Init {
+            for display in self.displays.iter() {
+                self.show_display(cpu, display);
             }
         }
     }
 
-    fn check_breakpoint(&mut self, cpu: &mut impl ISA) {
-        if self.bps.exists() && self.bps.check_breakpoint(cpu) {
-            self.state = DebuggerState::Paused;
+    /// Add `exp` to the displays and show it, or show them all without one.
+    fn display(&mut self, cpu: &mut impl ISA, format: Option<Examine>, exp: String) {
+        if exp.is_empty() {
+            for display in self.displays.iter() {
+                self.show_display(cpu, display);
+            }
+            return;
+        }
+        if let Err(e) = eval(cpu, &self.symbols, &exp) {
+            return report(e);
         }
+        let label = match format {
+            Some(examine) => format!("x/{} {}", examine, exp),
+            None => exp.clone(),
+        };
+        let display = Display {
+            number: self.next_display,
+            format,
+            exp,
+            label,
+        };
+        self.next_display += 1;
+        self.show_display(cpu, &display);
+        self.displays.push(display);
     }
 
-    fn delete_breakpoint(&mut self, index: u8) {
-        self.bps.delete_breakpoint(index.into())
+    fn show_display(&self, cpu: &mut impl ISA, display: &Display) {
+        match display.format {
+            Some(examine) => {
+                println!("{}: {}", display.number, display.label);
+                self.examine(cpu, examine, &display.exp);
+            }
+            None => match eval(cpu, &self.symbols, &display.exp) {
+                Ok(value) => println!("{}: {} = {:#x}", display.number, display.label, value),
+                Err(_) => println!("{}: {} = <unavailable>", display.number, display.label),
+            },
+        }
     }
 
+    /// The registers, those changed since the stop before highlighted.
     fn show_registers(&self, cpu: &impl ISA) {
         let mut i = 0;
         for (name, value) in cpu.iter() {
-            print!("{:12} {:#10x}   ", name, value);
+            let entry = format!("{:12} {:#10x}   ", name, value);
+            match self.changed.contains(&name) {
+                true => print!("{}", entry.yellow().bold()),
+                false => print!("{}", entry),
+            }
             i += 1;
             if i % 4 == 0 {
                 println!();
@@ -181,7 +948,7 @@ impl Debugger {
         let _xlen = cpu.xlen();
         fn draw_line() {
             print!(" │");
-            for _ in 0..50 + 11 {
+            for _ in 0..50 + 32 {
                 print!("─");
             }
             println!("│");
@@ -193,37 +960,292 @@ impl Debugger {
             } else {
                 print!(" ");
             }
-            let inst = cpu.disassemble(addr);
-            match inst {
-                Ok(inst) => {
-                    print!("│{:#01$x} ", addr, 10);
-                    print!("{:>50}", inst);
+            print!("│{:#01$x} ", addr, 10);
+            print!("{:<20} ", self.symbols.label(addr));
+            print!("{:<50}", self.symbols.disassemble(cpu, addr));
+            println!("│");
+        }
+
+        draw_line();
+    }
+
+    /// The range `disas` dumps for `spec`, with the name of the function
+    /// it is.
+    fn disas_range(
+        &self,
+        cpu: &mut impl ISA,
+        spec: Option<&str>,
+    ) -> Result<((u32, u32), Option<String>), RError> {
+        let function = |addr: u32| {
+            let (symbol, _) = self.symbols.locate(addr)?;
+            let range = self.symbols.function(&symbol.name)?;
+            Some((range, Some(symbol.name.clone())))
+        };
+        let ((low, high), name) = match spec {
+            None => {
+                let pc = cpu.pc();
+                function(pc).unwrap_or(((pc.saturating_sub(0x10), pc.saturating_add(0x20)), None))
+            }
+            Some(spec) => match spec.split_once(',') {
+                Some((start, end)) => {
+                    let low = eval(cpu, &self.symbols, start)? as u32;
+                    let end = end.trim();
+                    let high = match end.strip_prefix('+') {
+                        Some(length) => {
+                            let length = eval(cpu, &self.symbols, length)? as u32;
+                            low.checked_add(length).ok_or_else(|| {
+                                RError::DebuggerError(format!(
+                                    "{:#x},+{:#x} is beyond the address space",
+                                    low, length
+                                ))
+                            })?
+                        }
+                        None => eval(cpu, &self.symbols, end)? as u32,
+                    };
+                    ((low, high), None)
                 }
-                Err(_) => {
-                    print!("│{:#01$x} ", addr, 10);
-                    print!("{:>50}", "<???>");
+                None => {
+                    let addr = eval(cpu, &self.symbols, spec)? as u32;
+                    function(addr).unwrap_or(((addr, addr.saturating_add(0x20)), None))
+                }
+            },
+        };
+        if high <= low {
+            return Err(RError::DebuggerError(format!(
+                "empty range [{:#x}, {:#x})",
+                low, high
+            )));
+        }
+        if high - low > MAX_DISAS {
+            return Err(RError::DebuggerError(format!(
+                "{:#x} bytes is too much to disassemble",
+                high - low
+            )));
+        }
+        Ok(((low, high), name))
+    }
+
+    /// Disassemble a function, `start,end` or `start,+length`, by default
+    /// the function around pc.
+    fn disas(&self, cpu: &mut impl ISA, spec: Option<&str>) -> Result<(), RError> {
+        let ((low, high), name) = self.disas_range(cpu, spec)?;
+        match name {
+            Some(name) => println!("Dump of assembler code for function {}:", name),
+            None => println!("Dump of assembler code from {:#x} to {:#x}:", low, high),
+        }
+        let pc = cpu.pc();
+        for addr in (low..high).step_by(4) {
+            let marker = if addr == pc { "=>" } else { "  " };
+            println!(
+                "{} {:#010x} {}:\t{}",
+                marker,
+                addr,
+                self.symbols.label(addr),
+                self.symbols.disassemble(cpu, addr)
+            );
+        }
+        println!("End of assembler dump.");
+        Ok(())
+    }
+
+    fn examine(&self, cpu: &mut impl ISA, examine: Examine, exp: &str) {
+        match eval(cpu, &self.symbols, exp) {
+            Ok(addr) => {
+                for line in examine.lines(cpu, addr as u32) {
+                    println!("{}", line);
                 }
             }
-            println!("│");
+            Err(e) => report(e),
         }
+    }
 
-        draw_line();
+    /// Assign `value` to a `$register` (CSRs included) or to the `width`
+    /// bytes at `*address`.
+    fn set(
+        &mut self,
+        cpu: &mut impl ISA,
+        width: u8,
+        lvalue: &str,
+        value: &str,
+    ) -> Result<(), RError> {
+        let value = eval(cpu, &self.symbols, value)? as u32;
+        if let Some(name) = lvalue.strip_prefix('$') {
+            if name == "pc" {
+                cpu.update_pc(value);
+            } else if cpu.read_register_by_name(name).is_some() {
+                cpu.write_register_by_name(name, value);
+            } else {
+                return Err(RError::DebuggerError(format!("no register {}", name)));
+            }
+        } else if let Some(addr) = lvalue.strip_prefix('*') {
+            let addr = eval(cpu, &self.symbols, addr)? as u32;
+            if !cpu.poke_mem(addr, width, value) {
+                return Err(RError::DebuggerError(format!(
+                    "cannot write device memory at {:#x}",
+                    addr
+                )));
+            }
+        } else {
+            return Err(RError::DebuggerError(format!(
+                "cannot assign to {}, use $register or *address",
+                lvalue
+            )));
+        }
+        Ok(())
+    }
+
+    /// Address of a symbol, an address expression, `*expr` or `file:line`.
+    fn location(&self, cpu: &mut impl ISA, spec: &str) -> Result<u32, RError> {
+        location(cpu, &self.symbols, spec)
+    }
+
+    /// Move pc to `location`, resolved like a breakpoint location, and
+    /// resume from there.
+    fn jump(&mut self, cpu: &mut impl ISA, location: &str) -> Result<(), RError> {
+        let addr = self.location(cpu, location)?;
+        cpu.update_pc(addr);
+        println!("Continuing at {:#x}.", addr);
+        self.continue_(cpu);
+        Ok(())
+    }
+
+    fn show_frame(&self, number: usize, frame: &Frame) {
+        let function = match self.symbols.locate(frame.call_site()) {
+            Some((symbol, _)) => symbol.name.as_str(),
+            None => "??",
+        };
+        match self.symbols.line(frame.call_site()) {
+            Some((file, line)) => println!(
+                "#{:<3}{:#010x} in {} () at {}:{}",
+                number, frame.pc, function, file, line
+            ),
+            None => println!("#{:<3}{:#010x} in {} ()", number, frame.pc, function),
+        }
     }
 
-    fn show_memory(&self, _cpu: &impl ISA) {
-        todo!("show_memory")
+    fn backtrace(&self, cpu: &mut impl ISA, count: Option<usize>) {
+        let frames = backtrace(cpu, &self.symbols);
+        let count = count.unwrap_or(frames.len());
+        for (number, frame) in frames.iter().enumerate().take(count) {
+            self.show_frame(number, frame);
+        }
+        if count < frames.len() {
+            println!("(More stack frames follow...)");
+        }
+    }
+
+    /// Select stack frame `number` and show where it is.
+    fn select_frame(&mut self, cpu: &mut impl ISA, number: usize) -> Result<(), RError> {
+        let frames = backtrace(cpu, &self.symbols);
+        let frame = frames
+            .get(number)
+            .ok_or(RError::DebuggerError(format!("no frame #{}", number)))?;
+        self.frame = number;
+        self.show_frame(number, frame);
+        println!("=> {}", self.symbols.disassemble(cpu, frame.pc));
+        Ok(())
+    }
+
+    /// Run until the function of the selected frame returns to its caller.
+    fn finish(&mut self, cpu: &mut impl ISA) -> Result<(), RError> {
+        let frames = backtrace(cpu, &self.symbols);
+        let caller = *frames.get(self.frame + 1).ok_or(RError::DebuggerError(
+            "\"finish\" not meaningful in the outermost frame".to_string(),
+        ))?;
+        print!("Run till exit from ");
+        self.show_frame(self.frame, &frames[self.frame]);
+        // a recursive call returns to the same pc with a deeper stack
+        let returned = self.run_until(cpu, |cpu| {
+            let sp = cpu.read_register_by_name("sp").unwrap_or_default();
+            cpu.pc() == caller.pc && sp >= caller.sp
+        });
+        if returned {
+            self.show_frame(0, &Frame::current(cpu));
+            let value = cpu.read_register_by_name("a0").unwrap_or_default();
+            println!("Value returned is $a0 = {:#x}", value);
+        }
+        Ok(())
+    }
+
+    /// the words on top of the stack
+    fn show_memory(&self, cpu: &mut impl ISA) {
+        let examine = Examine::parse("16xw").unwrap();
+        self.examine(cpu, examine, "$sp");
+    }
+
+    /// Next command from the breakpoint queue or the user, `None` at the
+    /// end of input.
+    fn read_input(&mut self, prompt: &str) -> Option<String> {
+        if let Some(command) = self.pending.pop_front() {
+            return Some(command);
+        }
+        if self.batch {
+            return None;
+        }
+        self.prompt.read(prompt)
+    }
+
+    /// Read the commands of breakpoint `number` up to `end`.
+    fn read_commands(&mut self, number: Option<u32>) {
+        let Some(number) = number.or(self.bps.last()) else {
+            println!("No breakpoints specified.");
+            return;
+        };
+        println!("Type commands for breakpoint {}, one per line.", number);
+        println!("End with a line saying just \"end\".");
+        let mut commands = vec![];
+        while let Some(command) = self.read_input(">") {
+            if command == "end" {
+                break;
+            }
+            commands.push(command);
+        }
+        if !self.bps.set_commands(number, commands) {
+            Self::no_breakpoint(number);
+        }
     }
 
     pub fn debug(&mut self, cpu: &mut impl ISA) {
-        println!("{}", crate::util::RDB_LOGO);
+        if !self.batch {
+            println!("{}", crate::util::RDB_LOGO);
+        }
+        cpu.enable_history(HISTORY_INTERVAL);
+        let registers = cpu.iter().map(|(name, _)| name);
+        let csrs = cpu.csr_names().into_iter().map(str::to_string);
+        self.prompt
+            .set_names(registers.chain(csrs).collect(), self.symbols.names());
         loop {
-            let mut input = String::new();
-            print!("{} ", "(rdb)".truecolor(169, 169, 169).bold());
-            std::io::stdout().flush().unwrap();
-            std::io::stdin()
-                .read_line(&mut input)
-                .expect("Failed to read line");
-            input = input.trim().to_string();
+            if self.executed > 0 {
+                self.echo_end(cpu);
+            }
+            self.stopped(cpu);
+            let status = self.status(cpu);
+            if let Some(tui) = self.tui.as_mut() {
+                if let Err(e) = tui.draw(cpu, &self.symbols, &status, &self.changed) {
+                    self.tui = None;
+                    println!("{}", e);
+                }
+            }
+            let typed = self.pending.is_empty();
+            let Some(mut input) = self.read_input("(rdb)") else {
+                self.state = DebuggerState::Exit;
+                self.tui = None;
+                return;
+            };
+            if typed {
+                match input.is_empty() {
+                    true => input = self.repeat.clone().unwrap_or_default(),
+                    false => {
+                        self.repeat = DebuggerCommand::parse(&input)
+                            .filter(DebuggerCommand::repeats)
+                            .map(|_| input.clone())
+                    }
+                }
+            }
+            self.executed += 1;
+            if self.batch {
+                println!("^command {} {}", self.executed, input);
+            }
             // parse user input
             match DebuggerCommand::parse(&input) {
                 Some(DebuggerCommand::Continue) => {
@@ -239,60 +1261,250 @@ impl Debugger {
                     } else {
                         match self.step(cpu, count) {
                             Ok(_) => (),
-                            Err(RError::Ebreak(_)) => {
-                                self.state = DebuggerState::Exit;
-                                println!("{}", RError::Ebreak(0));
-                            }
+                            Err(RError::Ebreak(code)) => self.exited(code),
                             Err(e) => println!("{}", e),
                         }
                     }
                 }
+                Some(DebuggerCommand::ReverseStep(count)) => {
+                    if let Err(e) = self.reverse_step(cpu, count) {
+                        println!("{}", e);
+                    }
+                }
+                Some(DebuggerCommand::ReverseContinue) => {
+                    if let Err(e) = self.reverse_continue(cpu) {
+                        println!("{}", e);
+                    }
+                }
+                Some(DebuggerCommand::LastChange(expression)) => {
+                    if let Err(e) = self.last_change(cpu, &expression) {
+                        println!("{}", e);
+                    }
+                }
                 Some(DebuggerCommand::Print(expression)) => self.print(cpu, expression),
-                Some(DebuggerCommand::Breakpoint(bp)) => self.make_breakpoint(cpu, bp),
+                Some(DebuggerCommand::Examine(examine, expression)) => {
+                    self.examine(cpu, examine, &expression)
+                }
+                Some(DebuggerCommand::Set(width, lvalue, value)) => {
+                    if let Err(e) = self.set(cpu, width, &lvalue, &value) {
+                        println!("{}", e);
+                    }
+                }
+                Some(DebuggerCommand::Jump(location)) => {
+                    if self.state == DebuggerState::Exit {
+                        println!("The program is exit.");
+                    } else if let Err(e) = self.jump(cpu, &location) {
+                        println!("{}", e);
+                    }
+                }
+                Some(DebuggerCommand::Backtrace(count)) => self.backtrace(cpu, count),
+                Some(DebuggerCommand::Frame(number)) => {
+                    let number = number.unwrap_or(self.frame);
+                    if let Err(e) = self.select_frame(cpu, number) {
+                        println!("{}", e);
+                    }
+                }
+                Some(DebuggerCommand::Up(count)) => {
+                    if let Err(e) = self.select_frame(cpu, self.frame + count) {
+                        println!("{}", e);
+                    }
+                }
+                Some(DebuggerCommand::Down(count)) => match self.frame.checked_sub(count) {
+                    Some(number) => {
+                        if let Err(e) = self.select_frame(cpu, number) {
+                            println!("{}", e);
+                        }
+                    }
+                    None => println!("Bottom (innermost) frame selected; you cannot go down."),
+                },
+                Some(DebuggerCommand::Next(count)) => {
+                    if self.state != DebuggerState::Paused {
+                        println!("The program is not paused.");
+                    } else {
+                        match self.next(cpu, count) {
+                            Ok(_) => (),
+                            Err(RError::Ebreak(code)) => self.exited(code),
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                }
+                Some(
+                    command @ (DebuggerCommand::StepLine(count) | DebuggerCommand::NextLine(count)),
+                ) => {
+                    let over_calls = matches!(command, DebuggerCommand::NextLine(_));
+                    if self.state == DebuggerState::Exit {
+                        println!("The program is exit.");
+                    } else {
+                        match self.line_step(cpu, count, over_calls) {
+                            Ok(_) => (),
+                            Err(RError::Ebreak(code)) => self.exited(code),
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                }
+                Some(DebuggerCommand::Until(location)) => {
+                    if self.state != DebuggerState::Paused {
+                        println!("The program is not paused.");
+                    } else if let Err(e) = self.until(cpu, &location) {
+                        println!("{}", e);
+                    }
+                }
+                Some(DebuggerCommand::Finish) => {
+                    if self.state != DebuggerState::Paused {
+                        println!("The program is not paused.");
+                    } else if let Err(e) = self.finish(cpu) {
+                        println!("{}", e);
+                    }
+                }
+                Some(DebuggerCommand::Disassemble(spec)) => {
+                    if let Err(e) = self.disas(cpu, spec.as_deref()) {
+                        println!("{}", e);
+                    }
+                }
+                Some(DebuggerCommand::Breakpoint(bp)) => self.make_breakpoint(cpu, bp, false),
+                Some(DebuggerCommand::TBreak(bp)) => self.make_breakpoint(cpu, bp, true),
+                Some(DebuggerCommand::Condition(number, condition)) => {
+                    if let Err(e) = self.bps.condition(cpu, &self.symbols, number, condition) {
+                        report(e);
+                    }
+                }
+                Some(DebuggerCommand::Commands(number)) => self.read_commands(number),
+                Some(DebuggerCommand::Watch(kind, exp)) => self.make_watchpoint(cpu, kind, exp),
+                Some(DebuggerCommand::Catch(catch)) => {
+                    let number = self.bps.catch(cpu, catch);
+                    println!("Catchpoint {} ({})", number, catch);
+                }
+                Some(DebuggerCommand::Enable(number)) => {
+                    if !self.bps.set_enabled(number, true) {
+                        Self::no_breakpoint(number.unwrap_or_default());
+                    }
+                }
+                Some(DebuggerCommand::Disable(number)) => {
+                    if !self.bps.set_enabled(number, false) {
+                        Self::no_breakpoint(number.unwrap_or_default());
+                    }
+                }
+                Some(DebuggerCommand::Ignore(number, count)) => {
+                    if self.bps.ignore(number, count) {
+                        println!(
+                            "Will ignore next {} crossings of breakpoint {}.",
+                            count, number
+                        );
+                    } else {
+                        Self::no_breakpoint(number);
+                    }
+                }
                 Some(DebuggerCommand::Quit) => {
+                    self.echo_end(cpu);
                     self.state = DebuggerState::Exit;
+                    self.tui = None;
                     return;
                 }
+                Some(DebuggerCommand::Source(path)) => {
+                    if let Err(e) = self.source(&path) {
+                        println!("{}", e);
+                    }
+                }
                 Some(DebuggerCommand::Run) => {
                     // TODO copy cpu state for reset
                     self.continue_(cpu);
                 }
                 Some(DebuggerCommand::Delete(number)) => {
-                    self.delete_breakpoint(number);
+                    if !self.bps.delete_breakpoint(number) {
+                        Self::no_breakpoint(number);
+                    }
                 }
                 Some(DebuggerCommand::Blank) => (),
-                Some(DebuggerCommand::Show(layout)) => match layout.as_str() {
-                    s if s.starts_with("asm") => {
-                        self.show_asm(cpu);
-                    }
-                    s if s.starts_with("reg") => {
-                        self.show_registers(cpu);
-                    }
-                    s if s.starts_with("mem") => {
-                        self.show_memory(cpu);
-                    }
-                    s if s.starts_with("break") => {
-                        self.bps.show();
+                Some(DebuggerCommand::Show(layout)) => self.show(cpu, &layout),
+                Some(DebuggerCommand::Display(format, exp)) => self.display(cpu, format, exp),
+                Some(DebuggerCommand::Undisplay(number)) => match number {
+                    Some(number) => {
+                        let len = self.displays.len();
+                        self.displays.retain(|display| display.number != number);
+                        if self.displays.len() == len {
+                            print!("{}", "Error".red());
+                            println!(": No display number {}", number);
+                        }
                     }
-                    _ => {
-                        print!("{}", "Error".red());
-                        println!(": '{}' is not a valid layout argument", layout);
+                    None => self.displays.clear(),
+                },
+                Some(DebuggerCommand::Layout(layout)) => match layout.as_str() {
+                    "tui" | "split" if self.batch => {
+                        println!("The tui layout is not available in batch mode.")
                     }
+                    "tui" | "split" => match Tui::enable() {
+                        Ok(tui) => self.tui = Some(tui),
+                        Err(e) => println!("{}", e),
+                    },
+                    "off" | "cli" => self.tui = None,
+                    _ => self.show(cpu, &layout),
                 },
-                Some(DebuggerCommand::Clear) => print!("\x1B[2J\x1B[1;1H"),
+                Some(DebuggerCommand::Clear) => {
+                    print!("\x1B[2J\x1B[1;1H");
+                    if let Some(tui) = self.tui.as_mut() {
+                        tui.invalidate();
+                    }
+                }
                 Some(DebuggerCommand::Help) => {
                     println!("Commands:");
                     println!("  c, continue\t\tContinue execution");
                     println!("  s, step [count]\tStep through [count] instructions");
+                    println!("  sl, step-line [count]\tStep [count] source lines");
+                    println!("  nl, next-line [count]\tStep [count] source lines over calls");
+                    println!("  rs, reverse-step [count]\tStep back [count] instructions");
+                    println!("  rc, reverse-continue\tRun backward until a breakpoint");
+                    println!("  last-change [expr]\tFind the instruction that last changed [expr]");
                     println!("  show [layout]\t\tShow the current [layout]");
                     println!("  p, print [expression]\tPrint the value of [expression]");
-                    println!("  b, breakpoint [expr]\tSet a breakpoint at [addr]");
+                    println!("  display[/fmt] [expr]\tPrint [expr] at every stop");
+                    println!("  undisplay [number]\tStop displaying [number], or all");
+                    println!("  x/NFU [addr]\t\tExamine N units of size U at [addr] in format F");
+                    println!(
+                        "  set[/bhw] [lvalue] = [expr]\tAssign [expr] to a $register or *address"
+                    );
+                    println!("  j, jump [location]\tResume at [location]");
+                    println!(
+                        "  disas [addr[,end|,+len]]\tDisassemble a function or an address range"
+                    );
+                    println!("  bt, backtrace [count]\tShow [count] frames of the call stack");
+                    println!(
+                        "  f, frame [number]\tSelect frame [number], or show the selected one"
+                    );
+                    println!("  up, down [count]\tSelect the frame [count] levels out or in");
+                    println!("  fin, finish\t\tRun until the selected frame returns");
+                    println!("  n, next, ni [count]\tStep [count] instructions over calls");
+                    println!(
+                        "  u, until [location]\tRun until [location] or the current frame returns"
+                    );
+                    println!(
+                        "  b, break [location] [if expr]\tBreak at a symbol, address, file:line or *[expr]"
+                    );
+                    println!(
+                        "  tb, tbreak [location]\tSet a breakpoint deleted after its first stop"
+                    );
+                    println!(
+                        "  condition [number] [expr]\tStop at [number] only if [expr] is true"
+                    );
+                    println!("  commands [number]\tCommands to run when [number] stops, up to end");
+                    println!("  watch [expr]\t\tStop when the value of [expr] changes");
+                    println!("  rwatch, awatch *[addr]\tStop when [addr] is read or accessed");
+                    println!("  catch syscall [number|name]\tStop at a syscall, or any ecall");
+                    println!("  catch exception [cause]\tStop when an exception is raised");
+                    println!(
+                        "  catch interrupt, mode-switch\tStop at an interrupt or privilege change"
+                    );
+                    println!("  enable, disable [number]\tEnable or disable breakpoint [number]");
+                    println!("  ignore [number] [count]\tPass breakpoint [number] [count] times");
                     println!("  d, delete [number]\tDelete breakpoint [number]");
                     println!("  r, run\t\tRun until breakpoint");
-                    println!("  l, layout [layout]\tSet the layout to [layout]");
+                    println!(
+                        "  l, layout [layout]\tSet the layout to tui or off, or show [layout]"
+                    );
                     println!("  h, help\t\tShow this help message");
+                    println!("  source [file]\t\tRun the commands in [file]");
                     println!("  q, quit\t\tQuit the debugger");
                     println!("  clear, cls\t\tClear the screen");
+                    println!("  (empty line)\t\tRepeat the last stepping or inspecting command");
                 }
                 None => {
                     print!("{}", "Error".red());
@@ -307,9 +1519,11 @@ impl Debugger {
 mod tests {
     #[test]
     fn test_command_parse() {
-        use super::DebuggerCommand;
+        use super::{Catch, DebuggerCommand, Examine, WatchKind};
         assert_eq!(DebuggerCommand::parse("c"), Some(DebuggerCommand::Continue));
         assert_eq!(DebuggerCommand::parse("s"), Some(DebuggerCommand::Step(1)));
+        assert!(DebuggerCommand::Step(1).repeats());
+        assert!(!DebuggerCommand::Breakpoint("main".to_string()).repeats());
         assert_eq!(
             DebuggerCommand::parse("s 10"),
             Some(DebuggerCommand::Step(10))
@@ -318,6 +1532,10 @@ mod tests {
             DebuggerCommand::parse("p x1"),
             Some(DebuggerCommand::Print("x1".to_string()))
         );
+        assert_eq!(
+            DebuggerCommand::parse("p *(u8*)$sp + 1"),
+            Some(DebuggerCommand::Print("*(u8*)$sp + 1".to_string()))
+        );
         assert_eq!(
             DebuggerCommand::parse("b 0x100"),
             Some(DebuggerCommand::Breakpoint("0x100".to_string()))
@@ -328,6 +1546,322 @@ mod tests {
             DebuggerCommand::parse("d 1"),
             Some(DebuggerCommand::Delete(1))
         );
+        assert_eq!(
+            DebuggerCommand::parse("rs 3"),
+            Some(DebuggerCommand::ReverseStep(3))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("rc"),
+            Some(DebuggerCommand::ReverseContinue)
+        );
+        assert_eq!(
+            DebuggerCommand::parse("last-change *($sp + 4)"),
+            Some(DebuggerCommand::LastChange("*($sp + 4)".to_string()))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("b *($ra + 4)"),
+            Some(DebuggerCommand::Breakpoint("*($ra + 4)".to_string()))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("rwatch *0x80001000"),
+            Some(DebuggerCommand::Watch(
+                WatchKind::Read,
+                "*0x80001000".to_string()
+            ))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("disable"),
+            Some(DebuggerCommand::Disable(None))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("ignore 2 5"),
+            Some(DebuggerCommand::Ignore(2, 5))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("b schedule if $a0 == 2"),
+            Some(DebuggerCommand::Breakpoint(
+                "schedule if $a0 == 2".to_string()
+            ))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("tbreak main"),
+            Some(DebuggerCommand::TBreak("main".to_string()))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("condition 1"),
+            Some(DebuggerCommand::Condition(1, None))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("condition 1 $a0 != 0"),
+            Some(DebuggerCommand::Condition(1, Some("$a0 != 0".to_string())))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("commands"),
+            Some(DebuggerCommand::Commands(None))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("x/4xb $sp + 8"),
+            Some(DebuggerCommand::Examine(
+                Examine::parse("4xb").unwrap(),
+                "$sp + 8".to_string()
+            ))
+        );
+        assert_eq!(DebuggerCommand::parse("x/4z $sp"), None);
+        assert_eq!(
+            DebuggerCommand::parse("set $a0 = $a0 == 1"),
+            Some(DebuggerCommand::Set(
+                4,
+                "$a0".to_string(),
+                "$a0 == 1".to_string()
+            ))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("set/b *($sp + 4)=0xff"),
+            Some(DebuggerCommand::Set(
+                1,
+                "*($sp + 4)".to_string(),
+                "0xff".to_string()
+            ))
+        );
+        assert_eq!(DebuggerCommand::parse("set $a0 == 1"), None);
+        assert_eq!(
+            DebuggerCommand::parse("jump *$ra"),
+            Some(DebuggerCommand::Jump("*$ra".to_string()))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("disas"),
+            Some(DebuggerCommand::Disassemble(None))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("disas main,+16"),
+            Some(DebuggerCommand::Disassemble(Some("main,+16".to_string())))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("bt"),
+            Some(DebuggerCommand::Backtrace(None))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("frame 2"),
+            Some(DebuggerCommand::Frame(Some(2)))
+        );
+        assert_eq!(DebuggerCommand::parse("up"), Some(DebuggerCommand::Up(1)));
+        assert_eq!(
+            DebuggerCommand::parse("down 2"),
+            Some(DebuggerCommand::Down(2))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("finish"),
+            Some(DebuggerCommand::Finish)
+        );
+        assert_eq!(DebuggerCommand::parse("ni"), Some(DebuggerCommand::Next(1)));
+        assert_eq!(
+            DebuggerCommand::parse("next 3"),
+            Some(DebuggerCommand::Next(3))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("until *0x80000010"),
+            Some(DebuggerCommand::Until("*0x80000010".to_string()))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("nl 2"),
+            Some(DebuggerCommand::NextLine(2))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("step-line"),
+            Some(DebuggerCommand::StepLine(1))
+        );
+        assert_eq!(DebuggerCommand::parse("sl x"), None);
+        assert_eq!(
+            DebuggerCommand::parse("catch syscall write"),
+            Some(DebuggerCommand::Catch(Catch::Syscall(Some(64))))
+        );
+        assert_eq!(DebuggerCommand::parse("catch fork"), None);
+        assert_eq!(
+            DebuggerCommand::parse("source tests/smoke.rdb"),
+            Some(DebuggerCommand::Source("tests/smoke.rdb".to_string()))
+        );
+        assert_eq!(DebuggerCommand::parse("source"), None);
+        assert_eq!(
+            DebuggerCommand::parse("display/i $pc"),
+            Some(DebuggerCommand::Display(
+                Examine::parse("i"),
+                "$pc".to_string()
+            ))
+        );
+        assert_eq!(
+            DebuggerCommand::parse("display"),
+            Some(DebuggerCommand::Display(None, String::new()))
+        );
+        assert_eq!(DebuggerCommand::parse("display/i"), None);
+        assert_eq!(
+            DebuggerCommand::parse("undisplay 2"),
+            Some(DebuggerCommand::Undisplay(Some(2)))
+        );
+        assert_eq!(DebuggerCommand::parse("d"), None);
         assert_eq!(DebuggerCommand::parse("invalid"), None);
     }
+
+    #[test]
+    fn test_set() {
+        use super::Debugger;
+        use crate::isas::{MemoryModel, RegisterModel, RV32CPU};
+
+        let mut debugger = Debugger::new();
+        let mut cpu = RV32CPU::default();
+        debugger.set(&mut cpu, 4, "$a0", "2 + 3").unwrap();
+        assert_eq!(cpu.read_register_by_name("a0"), Some(5));
+        debugger.set(&mut cpu, 4, "$mepc", "0x1000").unwrap();
+        assert_eq!(cpu.read_register_by_name("mepc"), Some(0x1000));
+        debugger.set(&mut cpu, 4, "$pc", "$mepc + 4").unwrap();
+        assert_eq!(cpu.pc(), 0x1004);
+        assert!(debugger.set(&mut cpu, 4, "$nope", "1").is_err());
+
+        debugger.set(&mut cpu, 4, "*0x2000", "0x11223344").unwrap();
+        debugger.set(&mut cpu, 1, "*0x2000", "0xff").unwrap();
+        assert_eq!(cpu.load_mem(0x2000, 4), Some(0x112233ff));
+        assert!(debugger.set(&mut cpu, 4, "*0xa00003f8", "1").is_err());
+        assert!(debugger.set(&mut cpu, 4, "0x2000", "1").is_err());
+    }
+
+    #[test]
+    fn test_disas_range() {
+        use super::Debugger;
+        use crate::isas::{RegisterModel, RV32CPU};
+
+        let debugger = Debugger::new();
+        let mut cpu = RV32CPU::default();
+        assert_eq!(
+            debugger.disas_range(&mut cpu, Some("0x1000,+8")).unwrap().0,
+            (0x1000, 0x1008)
+        );
+        // at the top of the address space
+        assert_eq!(
+            debugger
+                .disas_range(&mut cpu, Some("0xfffffff0"))
+                .unwrap()
+                .0,
+            (0xfffffff0, 0xffffffff)
+        );
+        cpu.update_pc(0xfffffffc);
+        assert_eq!(
+            debugger.disas_range(&mut cpu, None).unwrap().0,
+            (0xffffffec, 0xffffffff)
+        );
+        assert!(debugger.disas_range(&mut cpu, Some("$pc,+0x20")).is_err());
+        assert!(debugger
+            .disas_range(&mut cpu, Some("0x1000,0x1000"))
+            .is_err());
+        assert!(debugger
+            .disas_range(&mut cpu, Some("0x1000,0x800"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_next() {
+        use super::Debugger;
+        use crate::isas::{MemoryModel, RegisterModel, RV32CPU};
+
+        let mut debugger = Debugger::new();
+        let mut cpu = RV32CPU::default();
+        let code = [
+            0x008000ef, // jal ra, 8
+            0x00150513, // addi a0, a0, 1
+            0x00158593, // addi a1, a1, 1
+            0x00008067, // ret
+        ];
+        for (i, code) in code.iter().enumerate() {
+            cpu.store_mem(0x80000000 + 4 * i as u32, 4, *code);
+        }
+        cpu.update_pc(0x80000000);
+        cpu.write_register_by_name("sp", 0x80001000);
+        debugger.next(&mut cpu, 1).unwrap();
+        assert_eq!(cpu.pc(), 0x80000004);
+        assert_eq!(cpu.read_register_by_name("a1"), Some(1));
+        debugger.next(&mut cpu, 1).unwrap();
+        assert_eq!(cpu.pc(), 0x80000008);
+        assert_eq!(cpu.read_register_by_name("a0"), Some(1));
+    }
+
+    #[test]
+    fn test_changed_registers() {
+        use super::{Debugger, DebuggerState};
+        use crate::isas::{MemoryModel, RegisterModel, RV32CPU};
+
+        let mut debugger = Debugger::new();
+        let mut cpu = RV32CPU::default();
+        cpu.store_mem(0x80000000, 4, 0x00150513); // addi a0, a0, 1
+        cpu.update_pc(0x80000000);
+        debugger.state = DebuggerState::Paused;
+        debugger.display(&mut cpu, None, "$a0".to_string());
+        debugger.stopped(&mut cpu);
+        assert!(debugger.changed.is_empty());
+        debugger.step(&mut cpu, 1).unwrap();
+        debugger.stopped(&mut cpu);
+        let changed = debugger.changed.iter().cloned().collect::<Vec<_>>();
+        assert_eq!(changed.len(), 2);
+        assert!(changed.contains(&"a0".to_string()) && changed.contains(&"pc".to_string()));
+        assert_eq!(debugger.displays.len(), 1);
+    }
+
+    #[test]
+    fn test_batch() {
+        use super::Debugger;
+        use crate::isas::{MemoryModel, RegisterModel, RV32CPU};
+
+        let mut debugger = Debugger::new();
+        let mut cpu = RV32CPU::default();
+        let code = [
+            0x00500513, // li a0, 5
+            0x00000513, // li a0, 0
+            0x00100073, // ebreak
+        ];
+        for (i, code) in code.iter().enumerate() {
+            cpu.store_mem(0x80000000 + 4 * i as u32, 4, *code);
+        }
+        cpu.update_pc(0x80000000);
+        let path = std::env::temp_dir().join(format!("rdb-batch-{}.rdb", std::process::id()));
+        std::fs::write(&path, "# stop at the second li\nb *0x80000004\n\nr\nc\n").unwrap();
+        debugger.batch = true;
+        debugger.source(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(debugger.pending, ["b *0x80000004", "r", "c"]);
+        debugger.debug(&mut cpu);
+        assert_eq!(debugger.exit_code(), Some(0));
+        assert_eq!(debugger.executed, 3);
+    }
+
+    #[test]
+    fn test_entry_breakpoint() {
+        use super::Debugger;
+        use crate::isas::{MemoryModel, RegisterModel, TimeTravel, RV32CPU};
+
+        let program = || {
+            let mut cpu = RV32CPU::default();
+            let code = [0x00500513, 0x00000513, 0x00100073];
+            for (i, code) in code.iter().enumerate() {
+                cpu.store_mem(0x80000000 + 4 * i as u32, 4, *code);
+            }
+            cpu.update_pc(0x80000000);
+            cpu
+        };
+        let mut debugger = Debugger::new();
+        let mut cpu = program();
+        debugger.batch = true;
+        debugger
+            .pending
+            .extend(["b *0x80000000".to_string(), "r".to_string()]);
+        debugger.debug(&mut cpu);
+        assert_eq!(cpu.pc(), 0x80000000);
+        assert_eq!(cpu.icount(), 0);
+        assert_eq!(debugger.exit_code(), None);
+
+        // continuing from the breakpoint runs past it
+        let mut debugger = Debugger::new();
+        let mut cpu = program();
+        debugger.batch = true;
+        let commands = ["b *0x80000000", "r", "c"];
+        debugger.pending.extend(commands.map(str::to_string));
+        debugger.debug(&mut cpu);
+        assert_eq!(debugger.exit_code(), Some(0));
+    }
 }
@@ -1,292 +1,785 @@
+//! Expressions of the debugger, in C syntax.
+//!
+//! An expression is tokenized, parsed into a tree and evaluated against the
+//! machine. Values are integers of the guest xlen unless cast: `(i8)$a0`,
+//! `*(u16*)addr`. `$name` is a register or CSR, a bare name a symbol, or a
+//! register when no symbol has that name. Arithmetic wraps like the guest's.
+
+use super::symbols::SymbolTable;
+use crate::error::RError;
 use crate::isas::ISA;
 
-pub(super) fn eval(cpu: &mut impl ISA, exp: &str) -> Option<u64> {
-    let tokens = tokenize(exp)?;
-    eval_tokens(cpu, &tokens, 0, tokens.len() - 1)
+/// Evaluate `exp`, returning its value zero-extended.
+pub(super) fn eval(cpu: &mut impl ISA, symbols: &SymbolTable, exp: &str) -> Result<u64, RError> {
+    let expr = parse(exp)?;
+    let mut evaluator = Evaluator {
+        xlen: (cpu.xlen() / 8) as u8,
+        cpu,
+        symbols,
+    };
+    let value = evaluator.eval(&expr)?;
+    Ok(value.bits)
 }
 
-fn eval_tokens(cpu: &mut impl ISA, tokens: &[Token], start: usize, end: usize) -> Option<u64> {
-    if tokens[start] == Token::Lparen && tokens[end] == Token::Rparen {
-        return eval_tokens(cpu, tokens, start + 1, end - 1);
+/// Address of a location: `file:line`, `*expr` or an expression such as a
+/// symbol.
+pub(super) fn location(
+    cpu: &mut impl ISA,
+    symbols: &SymbolTable,
+    location: &str,
+) -> Result<u32, RError> {
+    let location = location.trim();
+    if let Some(exp) = location.strip_prefix('*') {
+        return eval(cpu, symbols, exp).map(|addr| addr as u32);
     }
-
-    if start == end {
-        match &tokens[start] {
-            Token::Number(n) => Some(*n),
-            Token::Register(r) => {
-                if r == "pc" {
-                    return Some(cpu.pc() as u64);
-                }
-                cpu.read_register_by_name(r).map(|v| v as u64)
-            }
-            _ => None,
-        }
-    } else {
-        let i = find_delimiter(tokens, start, end)?;
-        match tokens[i] {
-            Token::Operator(op) => {
-                if let Op::Star = op {
-                    let addr = eval_tokens(cpu, tokens, i + 1, end)?;
-                    return cpu.load_mem(addr as u32, 4).map(|v| v as u64);
-                }
-                let left = eval_tokens(cpu, tokens, start, i - 1)?;
-                let right = eval_tokens(cpu, tokens, i + 1, end)?;
-                match op {
-                    Op::Add => Some(left + right),
-                    Op::Sub => Some(left - right),
-                    Op::Mul => Some(left * right),
-                    Op::Div => Some(left / right),
-                    Op::And => Some((left != 0) as u64 & (right != 0) as u64),
-                    Op::BitAnd => Some(left & right),
-                    Op::Or => Some((left != 0) as u64 | (right != 0) as u64),
-                    Op::BitOr => Some(left | right),
-                    Op::Eq => Some((left == right) as u64),
-                    Op::Ne => Some((left != right) as u64),
-                    Op::Star => unreachable!(),
-                }
-            }
-            _ => todo!("eval_tokens: {:?}", tokens[i]),
+    if let Some((file, line)) = location.rsplit_once(':') {
+        if let Ok(line) = line.parse() {
+            return symbols
+                .line_address(file, line)
+                .ok_or(error(format!("No line {} in file \"{}\".", line, file)));
         }
     }
+    eval(cpu, symbols, location).map(|addr| addr as u32)
+}
+
+fn error(message: String) -> RError {
+    RError::DebuggerError(message)
 }
 
-fn tokenize(exp: &str) -> Option<Vec<Token>> {
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(u64),
+    /// `$name`
+    Register(String),
+    Name(String),
+    /// an operator or a parenthesis
+    Punct(&'static str),
+}
+
+/// operators, longest first so that `<<` is not read as `<`
+const PUNCTS: [&str; 27] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "&", "|",
+    "^", "~", "!", "(", ")", "?", ":", "[", "]", ",",
+];
+
+fn tokenize(exp: &str) -> Result<Vec<Token>, RError> {
     let mut tokens = vec![];
-    let mut chars = exp.chars().peekable();
-    while chars.peek().is_some() {
-        match chars.next() {
-            Some(c) if c.is_ascii_whitespace() => continue,
-            Some(c) if c.is_ascii_digit() => {
-                if c == '0' && chars.peek() == Some(&'x') {
-                    // hex
-                    chars.next();
-                    let mut num = 0;
-                    while let Some(c) = chars.peek() {
-                        if c.is_alphanumeric() {
-                            num = num * 16 + chars.next().unwrap().to_digit(16)? as u64;
-                        } else {
-                            break;
-                        }
-                    }
-                    tokens.push(Token::Number(num));
-                } else {
-                    // decmial
-                    let mut num = c.to_digit(10).unwrap() as u64;
-                    while let Some(c) = chars.peek() {
-                        if c.is_ascii_digit() {
-                            num = num * 10 + chars.next().unwrap().to_digit(10)? as u64;
-                            chars.next();
-                        } else {
-                            break;
-                        }
-                    }
-                    tokens.push(Token::Number(num));
-                }
+    let mut rest = exp.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() {
+            let end = word_end(rest);
+            tokens.push(Token::Number(parse_number(&rest[..end])?));
+            rest = &rest[end..];
+        } else if c == '$' {
+            let end = word_end(&rest[1..]) + 1;
+            if end == 1 {
+                return Err(error("Missing register name after '$'".to_string()));
             }
-            Some('$') => {
-                let mut reg = String::new();
-                while let Some(c) = chars.peek() {
-                    if c.is_alphanumeric() {
-                        reg.push(chars.next().unwrap());
-                    } else {
-                        break;
-                    }
-                }
-                tokens.push(Token::Register(reg));
-            }
-            Some('+') => tokens.push(Token::Operator(Op::Add)),
-            Some('-') => tokens.push(Token::Operator(Op::Sub)),
-            Some('*') => tokens.push(Token::Operator(Op::Mul)),
-            Some('/') => tokens.push(Token::Operator(Op::Div)),
-            Some('&') => match chars.peek() {
-                Some(c) if *c == '&' => {
-                    chars.next();
-                    tokens.push(Token::Operator(Op::And));
-                }
-                _ => tokens.push(Token::Operator(Op::BitAnd)),
-            },
-            Some('|') => match chars.peek() {
-                Some(c) if *c == '|' => {
-                    chars.next();
-                    tokens.push(Token::Operator(Op::Or));
-                }
-                _ => tokens.push(Token::Operator(Op::BitOr)),
-            },
-            Some('=') => match chars.peek() {
-                Some(c) if *c == '=' => {
-                    chars.next();
-                    tokens.push(Token::Operator(Op::Eq));
-                }
-                _ => return None,
-            },
-            Some('!') => match chars.peek() {
-                Some(c) if *c == '=' => {
-                    chars.next();
-                    tokens.push(Token::Operator(Op::Ne));
-                }
-                _ => return None,
-            },
-            Some('(') => tokens.push(Token::Lparen),
-            Some(')') => tokens.push(Token::Rparen),
-            _ => return None,
+            tokens.push(Token::Register(rest[1..end].to_string()));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+            let end = word_end(rest);
+            tokens.push(Token::Name(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            let Some(punct) = PUNCTS.iter().find(|punct| rest.starts_with(**punct)) else {
+                return Err(error(format!("Invalid character '{}' in expression", c)));
+            };
+            tokens.push(Token::Punct(punct));
+            rest = &rest[punct.len()..];
         }
+        rest = rest.trim_start();
     }
-    for i in 0..tokens.len() {
-        if let Token::Operator(Op::Mul) = tokens[i] {
-            if i == 0 {
-                tokens[i] = Token::Operator(Op::Star);
-            } else {
-                match tokens[i - 1] {
-                    Token::Lparen | Token::Operator(_) => tokens[i] = Token::Operator(Op::Star),
-                    _ => (),
-                }
-            }
+    Ok(tokens)
+}
+
+/// length of the number or name `rest` starts with
+fn word_end(rest: &str) -> usize {
+    rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+        .unwrap_or(rest.len())
+}
+
+/// A decimal, `0x` hexadecimal, `0b` binary or `0` octal literal.
+fn parse_number(text: &str) -> Result<u64, RError> {
+    let (digits, radix) = match text.get(..2) {
+        Some("0x") | Some("0X") => (&text[2..], 16),
+        Some("0b") | Some("0B") => (&text[2..], 2),
+        _ if text.len() > 1 && text.starts_with('0') => (&text[1..], 8),
+        _ => (text, 10),
+    };
+    u64::from_str_radix(digits, radix).map_err(|_| error(format!("Invalid number \"{}\"", text)))
+}
+
+/// An integer type, or a pointer to one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Type {
+    /// size in bytes, 0 for the guest xlen
+    size: u8,
+    signed: bool,
+    /// levels of indirection
+    pointer: u8,
+}
+
+impl Type {
+    const fn int(size: u8, signed: bool) -> Type {
+        Type {
+            size,
+            signed,
+            pointer: 0,
         }
     }
-    Some(tokens)
+
+    /// The type called `name`, in Rust, C or `<stdint.h>` spelling.
+    fn named(name: &str) -> Option<Type> {
+        let ty = match name {
+            "u8" | "uint8_t" | "unsigned char" => Type::int(1, false),
+            "i8" | "int8_t" | "char" | "signed char" => Type::int(1, true),
+            "u16" | "uint16_t" | "unsigned short" => Type::int(2, false),
+            "i16" | "int16_t" | "short" => Type::int(2, true),
+            "u32" | "uint32_t" | "unsigned" | "unsigned int" => Type::int(4, false),
+            "i32" | "int32_t" | "int" | "signed" => Type::int(4, true),
+            "u64" | "uint64_t" | "unsigned long long" => Type::int(8, false),
+            "i64" | "int64_t" | "long long" => Type::int(8, true),
+            "usize" | "unsigned long" | "size_t" | "uintptr_t" => Type::int(0, false),
+            "isize" | "long" | "intptr_t" => Type::int(0, true),
+            _ => return None,
+        };
+        Some(ty)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Op {
-    Add,
-    Sub,
+enum Unary {
+    Neg,
+    Not,
+    BitNot,
+    Deref,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Binary {
     Mul,
     Div,
-    And,
-    Or,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
     Eq,
     Ne,
     BitAnd,
+    BitXor,
     BitOr,
-    Star,
+    And,
+    Or,
+}
+
+impl Binary {
+    /// the operator of `punct` and its precedence, higher binding tighter
+    fn of(punct: &str) -> Option<(Binary, u8)> {
+        let op = match punct {
+            "*" => (Binary::Mul, 10),
+            "/" => (Binary::Div, 10),
+            "%" => (Binary::Rem, 10),
+            "+" => (Binary::Add, 9),
+            "-" => (Binary::Sub, 9),
+            "<<" => (Binary::Shl, 8),
+            ">>" => (Binary::Shr, 8),
+            "<" => (Binary::Lt, 7),
+            "<=" => (Binary::Le, 7),
+            ">" => (Binary::Gt, 7),
+            ">=" => (Binary::Ge, 7),
+            "==" => (Binary::Eq, 6),
+            "!=" => (Binary::Ne, 6),
+            "&" => (Binary::BitAnd, 5),
+            "^" => (Binary::BitXor, 4),
+            "|" => (Binary::BitOr, 3),
+            "&&" => (Binary::And, 2),
+            "||" => (Binary::Or, 1),
+            _ => return None,
+        };
+        Some(op)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-enum Token {
+enum Expr {
     Number(u64),
-    Operator(Op),
-    Lparen,
-    Rparen,
     Register(String),
+    /// a symbol, or a register without `$`
+    Name(String),
+    Unary(Unary, Box<Expr>),
+    Binary(Binary, Box<Expr>, Box<Expr>),
+    Cast(Type, Box<Expr>),
+    /// `cond ? then : else`
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
 }
 
-impl Op {
-    pub fn precedence(&self) -> u8 {
-        match self {
-            Op::And | Op::Or => 1,
-            Op::Eq | Op::Ne => 2,
-            Op::BitAnd => 3,
-            Op::BitOr => 4,
-            Op::Add | Op::Sub => 5,
-            Op::Mul | Op::Div => 6,
-            Op::Star => 7,
+fn parse(exp: &str) -> Result<Expr, RError> {
+    let tokens = tokenize(exp)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.conditional()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(error(format!(
+            "Unexpected {} in expression",
+            describe(token)
+        ))),
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(n) => format!("number {}", n),
+        Token::Register(name) => format!("register ${}", name),
+        Token::Name(name) => format!("name \"{}\"", name),
+        Token::Punct(punct) => format!("'{}'", punct),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, RError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or(error("Unexpected end of expression".to_string()))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if self.peek() == Some(&Token::Punct(punct_str(punct))) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), RError> {
+        match self.next()? {
+            Token::Punct(p) if p == punct => Ok(()),
+            token => Err(error(format!(
+                "Expected '{}' but found {}",
+                punct,
+                describe(&token)
+            ))),
+        }
+    }
+
+    fn conditional(&mut self) -> Result<Expr, RError> {
+        let cond = self.binary(1)?;
+        if !self.eat("?") {
+            return Ok(cond);
+        }
+        let then = self.conditional()?;
+        self.expect(":")?;
+        let otherwise = self.conditional()?;
+        Ok(Expr::Conditional(
+            Box::new(cond),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    /// binary operators binding at least as tight as `min`, left to right
+    fn binary(&mut self, min: u8) -> Result<Expr, RError> {
+        let mut left = self.unary()?;
+        while let Some(Token::Punct(punct)) = self.peek() {
+            let Some((op, precedence)) = Binary::of(punct) else {
+                break;
+            };
+            if precedence < min {
+                break;
+            }
+            self.pos += 1;
+            let right = self.binary(precedence + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, RError> {
+        let op = match self.peek() {
+            Some(Token::Punct("-")) => Some(Unary::Neg),
+            Some(Token::Punct("!")) => Some(Unary::Not),
+            Some(Token::Punct("~")) => Some(Unary::BitNot),
+            Some(Token::Punct("*")) => Some(Unary::Deref),
+            Some(Token::Punct("+")) => {
+                self.pos += 1;
+                return self.unary();
+            }
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.pos += 1;
+            return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+        }
+        if let Some(ty) = self.cast()? {
+            return Ok(Expr::Cast(ty, Box::new(self.unary()?)));
+        }
+        self.postfix()
+    }
+
+    /// `(type)` in front of an operand, type names being one or more words
+    /// followed by `*`s
+    fn cast(&mut self) -> Result<Option<Type>, RError> {
+        if self.peek() != Some(&Token::Punct("(")) {
+            return Ok(None);
+        }
+        let mut words = vec![];
+        let mut pos = self.pos + 1;
+        while let Some(Token::Name(word)) = self.tokens.get(pos) {
+            words.push(word.as_str());
+            pos += 1;
+        }
+        let Some(mut ty) = Type::named(&words.join(" ")) else {
+            return Ok(None);
+        };
+        self.pos = pos;
+        while self.eat("*") {
+            ty.pointer += 1;
+        }
+        self.expect(")")?;
+        Ok(Some(ty))
+    }
+
+    /// an operand followed by `[index]`s, `p[i]` being `*(p + i)`
+    fn postfix(&mut self) -> Result<Expr, RError> {
+        let mut expr = self.primary()?;
+        while self.eat("[") {
+            let index = self.conditional()?;
+            self.expect("]")?;
+            let sum = Expr::Binary(Binary::Add, Box::new(expr), Box::new(index));
+            expr = Expr::Unary(Unary::Deref, Box::new(sum));
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, RError> {
+        match self.next()? {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Register(name) => Ok(Expr::Register(name)),
+            Token::Name(name) => Ok(Expr::Name(name)),
+            Token::Punct("(") => {
+                let expr = self.conditional()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            token => Err(error(format!(
+                "Unexpected {} in expression",
+                describe(&token)
+            ))),
         }
     }
 }
 
-fn find_delimiter(exp: &[Token], start: usize, end: usize) -> Option<usize> {
-    if start >= end {
-        return None;
-    }
-    let mut i = start;
-    let mut dele = None;
-    let mut precedence = std::u8::MAX;
-    let mut depth = 0;
-    while i < end {
-        match exp[i] {
-            Token::Lparen => depth += 1,
-            Token::Rparen => {
-                depth -= 1;
-                if depth == 0 {
-                    return None;
-                }
+/// `punct` as one of the static strings tokens hold
+fn punct_str(punct: &str) -> &'static str {
+    PUNCTS.iter().find(|p| **p == punct).copied().unwrap_or("")
+}
+
+/// A typed value, its bits truncated to the size of its type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Value {
+    bits: u64,
+    ty: Type,
+}
+
+struct Evaluator<'a, I: ISA> {
+    cpu: &'a mut I,
+    symbols: &'a SymbolTable,
+    /// bytes of a guest register
+    xlen: u8,
+}
+
+impl<I: ISA> Evaluator<'_, I> {
+    /// size of values of type `ty`, in bytes
+    fn size(&self, ty: Type) -> u8 {
+        match (ty.pointer, ty.size) {
+            (0, 0) => self.xlen,
+            (0, size) => size,
+            _ => self.xlen,
+        }
+    }
+
+    /// size of what a pointer of type `ty` points to
+    fn element(&self, ty: Type) -> u8 {
+        let pointee = Type {
+            pointer: ty.pointer.saturating_sub(1),
+            ..ty
+        };
+        self.size(pointee)
+    }
+
+    fn word(&self) -> Type {
+        Type::int(self.xlen, false)
+    }
+
+    fn int(&self) -> Type {
+        Type::int(self.xlen, true)
+    }
+
+    fn value(&self, bits: u64, ty: Type) -> Value {
+        Value {
+            bits: bits & mask(self.size(ty)),
+            ty,
+        }
+    }
+
+    /// the value sign-extended to 64 bits if signed
+    fn extend(&self, value: Value) -> u64 {
+        let size = self.size(value.ty);
+        if value.ty.signed && value.ty.pointer == 0 && size < 8 {
+            let shift = 64 - 8 * size as u32;
+            (((value.bits << shift) as i64) >> shift) as u64
+        } else {
+            value.bits
+        }
+    }
+
+    fn convert(&self, value: Value, ty: Type) -> Value {
+        self.value(self.extend(value), ty)
+    }
+
+    /// Integers narrower than a register are promoted to signed registers,
+    /// as C promotes them to `int`.
+    fn promote(&self, value: Value) -> Value {
+        if value.ty.pointer == 0 && self.size(value.ty) < self.xlen {
+            return self.convert(value, self.int());
+        }
+        value
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Value, RError> {
+        match expr {
+            // an int if it fits in one, as in C
+            Expr::Number(n) => match *n < 1 << (8 * self.xlen - 1) {
+                true => Ok(self.value(*n, self.int())),
+                false => Ok(self.value(*n, self.word())),
+            },
+            Expr::Register(name) => self.register(name),
+            Expr::Name(name) => match self.symbols.lookup(name) {
+                Some(addr) => Ok(self.value(addr as u64, self.word())),
+                None => self
+                    .register(name)
+                    .map_err(|_| error(format!("No symbol \"{}\" in current context.", name))),
+            },
+            Expr::Cast(ty, operand) => {
+                let value = self.eval(operand)?;
+                Ok(self.convert(value, *ty))
             }
-            Token::Operator(op) => {
-                if depth == 0 && op.precedence() <= precedence {
-                    dele = Some(i);
-                    precedence = op.precedence();
-                }
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                self.unary(*op, value)
+            }
+            Expr::Binary(Binary::And, left, right) => {
+                let value = self.truth(left)? && self.truth(right)?;
+                Ok(self.value(value as u64, self.int()))
+            }
+            Expr::Binary(Binary::Or, left, right) => {
+                let value = self.truth(left)? || self.truth(right)?;
+                Ok(self.value(value as u64, self.int()))
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                self.binary(*op, left, right)
+            }
+            Expr::Conditional(cond, then, otherwise) => match self.truth(cond)? {
+                true => self.eval(then),
+                false => self.eval(otherwise),
+            },
+        }
+    }
+
+    fn truth(&mut self, expr: &Expr) -> Result<bool, RError> {
+        Ok(self.eval(expr)?.bits != 0)
+    }
+
+    fn register(&self, name: &str) -> Result<Value, RError> {
+        let value = match name {
+            "pc" => Some(self.cpu.pc()),
+            _ => self.cpu.read_register_by_name(name),
+        };
+        value
+            .map(|value| self.value(value as u64, self.word()))
+            .ok_or(error(format!("Invalid register ${}", name)))
+    }
+
+    fn read(&mut self, addr: u64, size: u8) -> Result<u64, RError> {
+        let unavailable = || error(format!("Cannot access memory at address {:#x}", addr));
+        if size == 8 {
+            let low = self.cpu.peek_mem(addr as u32, 4).ok_or_else(unavailable)?;
+            let high = self
+                .cpu
+                .peek_mem((addr as u32).wrapping_add(4), 4)
+                .ok_or_else(unavailable)?;
+            return Ok((high as u64) << 32 | low as u64);
+        }
+        self.cpu
+            .peek_mem(addr as u32, size)
+            .map(|value| value as u64)
+            .ok_or_else(unavailable)
+    }
+
+    fn unary(&mut self, op: Unary, value: Value) -> Result<Value, RError> {
+        match op {
+            Unary::Deref => {
+                // a plain integer is the address of a register-sized word
+                let ty = match value.ty.pointer {
+                    0 => self.word(),
+                    _ => Type {
+                        pointer: value.ty.pointer - 1,
+                        ..value.ty
+                    },
+                };
+                let bits = self.read(value.bits, self.size(ty))?;
+                Ok(self.value(bits, ty))
+            }
+            Unary::Not => Ok(self.value((value.bits == 0) as u64, self.int())),
+            Unary::Neg => {
+                let value = self.promote(value);
+                Ok(self.value(value.bits.wrapping_neg(), value.ty))
+            }
+            Unary::BitNot => {
+                let value = self.promote(value);
+                Ok(self.value(!value.bits, value.ty))
+            }
+        }
+    }
+
+    fn binary(&mut self, op: Binary, left: Value, right: Value) -> Result<Value, RError> {
+        let (left, right) = (self.promote(left), self.promote(right));
+        // pointer arithmetic counts elements
+        match (op, left.ty.pointer, right.ty.pointer) {
+            (Binary::Add | Binary::Sub, 1.., 0) => {
+                let offset = right.bits.wrapping_mul(self.element(left.ty) as u64);
+                let bits = match op {
+                    Binary::Add => left.bits.wrapping_add(offset),
+                    _ => left.bits.wrapping_sub(offset),
+                };
+                return Ok(self.value(bits, left.ty));
+            }
+            (Binary::Add, 0, 1..) => return self.binary(op, right, left),
+            (Binary::Sub, 1.., 1..) => {
+                let bytes = self.extend(self.value(left.bits.wrapping_sub(right.bits), self.int()));
+                let count = (bytes as i64) / self.element(left.ty).max(1) as i64;
+                return Ok(self.value(count as u64, self.int()));
             }
             _ => (),
         }
-        i += 1;
+        // the wider type, unsigned if either is at the same width
+        let ty = match self.size(left.ty).cmp(&self.size(right.ty)) {
+            std::cmp::Ordering::Greater => left.ty,
+            std::cmp::Ordering::Less => right.ty,
+            std::cmp::Ordering::Equal => Type::int(
+                self.size(left.ty),
+                left.ty.signed && right.ty.signed && left.ty.pointer == 0,
+            ),
+        };
+        let ty = Type { pointer: 0, ..ty };
+        let shift_ty = Type {
+            pointer: 0,
+            ..left.ty
+        };
+        let (a, b) = (
+            self.extend(self.convert(left, ty)),
+            self.extend(self.convert(right, ty)),
+        );
+        let signed = ty.signed;
+        let compare = |lt: bool| self.value(lt as u64, self.int());
+        let less = if signed {
+            (a as i64) < (b as i64)
+        } else {
+            a < b
+        };
+        let value = match op {
+            Binary::Mul => self.value(a.wrapping_mul(b), ty),
+            Binary::Div | Binary::Rem => {
+                if b == 0 || self.convert(right, ty).bits == 0 {
+                    return Err(error("Division by zero".to_string()));
+                }
+                let bits = match (op, signed) {
+                    (Binary::Div, true) => (a as i64).wrapping_div(b as i64) as u64,
+                    (Binary::Div, false) => a / b,
+                    (_, true) => (a as i64).wrapping_rem(b as i64) as u64,
+                    (_, false) => a % b,
+                };
+                self.value(bits, ty)
+            }
+            Binary::Add => self.value(a.wrapping_add(b), ty),
+            Binary::Sub => self.value(a.wrapping_sub(b), ty),
+            Binary::Shl | Binary::Shr => {
+                // the type of the left operand, an amount past its width
+                // shifting every bit out
+                let value = self.extend(left);
+                let width = 8 * self.size(shift_ty) as u64;
+                let amount = self.extend(right);
+                let bits = match op {
+                    Binary::Shl if amount >= width => 0,
+                    Binary::Shl => value << amount,
+                    _ if amount >= width && shift_ty.signed => ((value as i64) >> 63) as u64,
+                    _ if amount >= width => 0,
+                    _ if shift_ty.signed => ((value as i64) >> amount) as u64,
+                    _ => value >> amount,
+                };
+                self.value(bits, shift_ty)
+            }
+            Binary::Lt => compare(less),
+            Binary::Ge => compare(!less),
+            Binary::Gt => compare(!less && a != b),
+            Binary::Le => compare(less || a == b),
+            Binary::Eq => compare(a == b),
+            Binary::Ne => compare(a != b),
+            Binary::BitAnd => self.value(a & b, ty),
+            Binary::BitXor => self.value(a ^ b, ty),
+            Binary::BitOr => self.value(a | b, ty),
+            Binary::And | Binary::Or => unreachable!(),
+        };
+        Ok(value)
+    }
+}
+
+/// the low `size` bytes
+fn mask(size: u8) -> u64 {
+    match size {
+        8.. => u64::MAX,
+        size => (1 << (8 * size as u64)) - 1,
     }
-    dele
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::isas::{MemoryModel, RegisterModel, RV32CPU};
+
     #[test]
     fn test_tokenize() {
-        let exp = "1+2 * $x0";
-        let tokens = tokenize(exp).unwrap();
         assert_eq!(
-            tokens,
+            tokenize("1+23 * $x0").unwrap(),
             vec![
                 Token::Number(1),
-                Token::Operator(Op::Add),
-                Token::Number(2),
-                Token::Operator(Op::Mul),
+                Token::Punct("+"),
+                Token::Number(23),
+                Token::Punct("*"),
                 Token::Register("x0".to_string()),
             ]
         );
-
-        let exp = "1+2 * *$pc";
-        let tokens = tokenize(exp).unwrap();
         assert_eq!(
-            tokens,
+            tokenize("a<<0x1f").unwrap(),
             vec![
-                Token::Number(1),
-                Token::Operator(Op::Add),
-                Token::Number(2),
-                Token::Operator(Op::Mul),
-                Token::Operator(Op::Star),
-                Token::Register("pc".to_string()),
+                Token::Name("a".to_string()),
+                Token::Punct("<<"),
+                Token::Number(0x1f)
             ]
         );
+        assert_eq!(tokenize("1234").unwrap(), vec![Token::Number(1234)]);
+        assert_eq!(
+            tokenize("0b101 017").unwrap(),
+            vec![Token::Number(5), Token::Number(15)]
+        );
+        assert!(tokenize("0xfg").is_err());
+        assert!(tokenize("1 @ 2").is_err());
+    }
 
-        let exp = "0xfff";
-        let tokens = tokenize(exp).unwrap();
-        assert_eq!(tokens, vec![Token::Number(0xfff),]);
-
-        let exp = "((*0xfffff000) + 0x1000) && ($pc == 0x1000)";
-        let tokens = tokenize(exp).unwrap();
+    #[test]
+    fn test_parse() {
         assert_eq!(
-            tokens,
-            vec![
-                Token::Lparen,
-                Token::Lparen,
-                Token::Operator(Op::Star),
-                Token::Number(0xfffff000),
-                Token::Rparen,
-                Token::Operator(Op::Add),
-                Token::Number(0x1000),
-                Token::Rparen,
-                Token::Operator(Op::And),
-                Token::Lparen,
-                Token::Register("pc".to_string()),
-                Token::Operator(Op::Eq),
-                Token::Number(0x1000),
-                Token::Rparen,
-            ]
+            parse("-*(u8*)$sp").unwrap(),
+            Expr::Unary(
+                Unary::Neg,
+                Box::new(Expr::Unary(
+                    Unary::Deref,
+                    Box::new(Expr::Cast(
+                        Type {
+                            size: 1,
+                            signed: false,
+                            pointer: 1
+                        },
+                        Box::new(Expr::Register("sp".to_string()))
+                    ))
+                ))
+            )
         );
+        assert!(parse("(1 + 2").is_err());
+        assert!(parse("1 2").is_err());
+        assert!(parse("1 ? 2").is_err());
     }
 
     #[test]
     fn test_eval() {
-        use crate::isas::riscv::RV32CPU;
-        use crate::isas::RegisterModel;
-
         let mut cpu = RV32CPU::default();
+        let symbols = SymbolTable::default();
+        let eval = |cpu: &mut RV32CPU, exp: &str| eval(cpu, &symbols, exp);
 
         cpu.update_pc(0x1000);
-        let exp = "1+2 * $pc";
-        let value = eval(&mut cpu, exp).unwrap();
-        assert_eq!(value, 0x2001);
+        assert_eq!(eval(&mut cpu, "1+2 * $pc").unwrap(), 0x2001);
+        assert_eq!(eval(&mut cpu, "$pc == 0x1000").unwrap(), 1);
+        assert_eq!(eval(&mut cpu, "(1 + 2) * 3 % 4 << 2 | 1").unwrap(), 5);
+        assert_eq!(eval(&mut cpu, "1 < 2 && 2 >= 2 && !(3 <= 2)").unwrap(), 1);
+        assert_eq!(eval(&mut cpu, "1 ? 5 : 6").unwrap(), 5);
+
+        // wrapping at 32 bits, signed only when asked
+        assert_eq!(eval(&mut cpu, "0 - 1").unwrap(), 0xffffffff);
+        assert_eq!(eval(&mut cpu, "0xffffffff + 2").unwrap(), 1);
+        assert_eq!(eval(&mut cpu, "-1 > 0").unwrap(), 0);
+        assert_eq!(eval(&mut cpu, "(unsigned)-1 > 0").unwrap(), 1);
+        assert_eq!(eval(&mut cpu, "0xffffffff > 0").unwrap(), 1);
+        assert_eq!(eval(&mut cpu, "(i32)-8 / 2").unwrap(), 0xfffffffc);
+        assert_eq!(eval(&mut cpu, "(i32)0x80000000 >> 31").unwrap(), 0xffffffff);
+        assert_eq!(eval(&mut cpu, "0x80000000 >> 31").unwrap(), 1);
+        assert_eq!(eval(&mut cpu, "~0").unwrap(), 0xffffffff);
+        assert_eq!(eval(&mut cpu, "(u8)0x1234").unwrap(), 0x34);
+        assert_eq!(eval(&mut cpu, "(i8)0xff + 1").unwrap(), 0);
 
-        let exp = "$pc == 0x1000";
-        let value = eval(&mut cpu, exp).unwrap();
-        assert_eq!(value, 1);
+        // sized dereferences and pointer arithmetic
+        cpu.store_mem(0x2000, 4, 0x8899aabb);
+        cpu.write_register_by_name("a0", 0x2000);
+        assert_eq!(eval(&mut cpu, "*$a0").unwrap(), 0x8899aabb);
+        assert_eq!(eval(&mut cpu, "*(u8*)$a0").unwrap(), 0xbb);
+        assert_eq!(eval(&mut cpu, "*((u16*)$a0 + 1)").unwrap(), 0x8899);
+        assert_eq!(eval(&mut cpu, "((u8*)$a0)[3]").unwrap(), 0x88);
+        assert_eq!(eval(&mut cpu, "*(i16*)$a0").unwrap(), 0xaabb);
+        assert_eq!(eval(&mut cpu, "*(i16*)$a0 < 0").unwrap(), 1);
+
+        // registers and CSRs with or without `$`
+        cpu.write_register_by_name("mstatus", 3);
+        assert_eq!(eval(&mut cpu, "mstatus + a0").unwrap(), 0x2003);
+
+        let message = |result: Result<u64, RError>| match result {
+            Err(RError::DebuggerError(message)) => message,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(message(eval(&mut cpu, "1 / 0")), "Division by zero");
+        assert_eq!(message(eval(&mut cpu, "1 % (u8)0x100")), "Division by zero");
+        assert_eq!(
+            message(eval(&mut cpu, "main + 4")),
+            "No symbol \"main\" in current context."
+        );
+        assert_eq!(message(eval(&mut cpu, "$foo")), "Invalid register $foo");
+        assert_eq!(
+            message(eval(&mut cpu, "1 +")),
+            "Unexpected end of expression"
+        );
+        assert_eq!(
+            message(eval(&mut cpu, "*0xa00003f8")),
+            "Cannot access memory at address 0xa00003f8"
+        );
+        // the right operand of && is not evaluated once the left is false
+        assert_eq!(eval(&mut cpu, "0 && 1 / 0").unwrap(), 0);
     }
 }
@@ -0,0 +1,212 @@
+use crate::isas::ISA;
+
+/// longest string printed by `x/s`
+const MAX_STRING: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Format {
+    Hex,
+    Decimal,
+    Unsigned,
+    Char,
+    String,
+    Instruction,
+}
+
+/// A gdb-style `/NFU` specification: count, format and unit size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Examine {
+    pub(super) count: u32,
+    pub(super) format: Format,
+    /// unit size in bytes
+    pub(super) unit: u8,
+}
+
+impl Default for Examine {
+    fn default() -> Self {
+        Examine {
+            count: 1,
+            format: Format::Hex,
+            unit: 4,
+        }
+    }
+}
+
+impl std::fmt::Display for Examine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let format = match self.format {
+            Format::Hex => 'x',
+            Format::Decimal => 'd',
+            Format::Unsigned => 'u',
+            Format::Char => 'c',
+            Format::String => 's',
+            Format::Instruction => 'i',
+        };
+        let unit = match self.unit {
+            1 => 'b',
+            2 => 'h',
+            _ => 'w',
+        };
+        match self.format {
+            Format::String | Format::Instruction => write!(f, "{}{}", self.count, format),
+            _ => write!(f, "{}{}{}", self.count, format, unit),
+        }
+    }
+}
+
+impl Examine {
+    /// Parse the `NFU` part of `x/NFU`, each letter being optional.
+    pub(super) fn parse(spec: &str) -> Option<Examine> {
+        let mut examine = Examine::default();
+        let digits = spec.chars().take_while(|c| c.is_ascii_digit()).count();
+        if digits > 0 {
+            examine.count = spec[..digits].parse().ok()?;
+        }
+        for c in spec[digits..].chars() {
+            match c {
+                'x' => examine.format = Format::Hex,
+                'd' => examine.format = Format::Decimal,
+                'u' => examine.format = Format::Unsigned,
+                'c' => {
+                    examine.format = Format::Char;
+                    examine.unit = 1;
+                }
+                's' => examine.format = Format::String,
+                'i' => examine.format = Format::Instruction,
+                'b' => examine.unit = 1,
+                'h' => examine.unit = 2,
+                'w' => examine.unit = 4,
+                _ => return None,
+            }
+        }
+        Some(examine)
+    }
+
+    /// Units shown on one line.
+    fn per_line(&self) -> u32 {
+        match self.format {
+            Format::Char => 8,
+            Format::String | Format::Instruction => 1,
+            _ => 16 / self.unit as u32,
+        }
+    }
+
+    /// Show `count` units starting at `addr`. Memory is read through the
+    /// current address translation and device registers are never touched.
+    pub(super) fn lines(&self, cpu: &mut impl ISA, addr: u32) -> Vec<String> {
+        let mut lines = vec![];
+        let mut line = String::new();
+        let mut addr = addr;
+        for i in 0..self.count {
+            if i % self.per_line() == 0 {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                line = format!("{:#010x}:", addr);
+            }
+            let (item, size) = match self.format {
+                Format::String => read_string(cpu, addr),
+                Format::Instruction => (read_instruction(cpu, addr), 4),
+                _ => (self.read_unit(cpu, addr), self.unit as u32),
+            };
+            line.push('\t');
+            line.push_str(&item);
+            addr = addr.wrapping_add(size);
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn read_unit(&self, cpu: &mut impl ISA, addr: u32) -> String {
+        let Some(value) = cpu.peek_mem(addr, self.unit) else {
+            return "<unavailable>".to_string();
+        };
+        let bits = self.unit as u32 * 8;
+        match self.format {
+            Format::Hex => format!("{:#0width$x}", value, width = self.unit as usize * 2 + 2),
+            Format::Unsigned => value.to_string(),
+            Format::Decimal => {
+                let shift = 32 - bits;
+                (((value << shift) as i32) >> shift).to_string()
+            }
+            Format::Char => format!("{} {}", value as i8, escape(value as u8)),
+            Format::String | Format::Instruction => unreachable!(),
+        }
+    }
+}
+
+fn escape(byte: u8) -> String {
+    format!("'{}'", std::ascii::escape_default(byte))
+}
+
+/// the NUL-terminated string at `addr` and the bytes it occupies
+fn read_string(cpu: &mut impl ISA, addr: u32) -> (String, u32) {
+    let mut string = String::new();
+    for i in 0..MAX_STRING {
+        match cpu.peek_mem(addr.wrapping_add(i), 1) {
+            Some(0) => return (format!("\"{}\"", string), i + 1),
+            Some(byte) => string.extend(std::ascii::escape_default(byte as u8).map(char::from)),
+            None if i == 0 => return ("<unavailable>".to_string(), 1),
+            None => return (format!("\"{}\"...", string), i),
+        }
+    }
+    (format!("\"{}\"...", string), MAX_STRING)
+}
+
+fn read_instruction(cpu: &mut impl ISA, addr: u32) -> String {
+    if cpu.peek_mem(addr, 4).is_none() {
+        return "<unavailable>".to_string();
+    }
+    cpu.disassemble(addr).unwrap_or("<???>".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isas::{MemoryModel, RV32CPU};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Examine::parse(""), Some(Examine::default()));
+        assert_eq!(
+            Examine::parse("16xb"),
+            Some(Examine {
+                count: 16,
+                format: Format::Hex,
+                unit: 1,
+            })
+        );
+        assert_eq!(
+            Examine::parse("dh"),
+            Some(Examine {
+                count: 1,
+                format: Format::Decimal,
+                unit: 2,
+            })
+        );
+        assert_eq!(Examine::parse("4q"), None);
+        assert_eq!(Examine::parse("16xb").unwrap().to_string(), "16xb");
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut cpu = RV32CPU::default();
+        for (i, byte) in b"hi\0".iter().enumerate() {
+            cpu.store_mem(0x1000 + i as u32, 1, *byte as u32);
+        }
+        cpu.store_mem(0x1004, 4, 0xfffffffe);
+
+        let lines = Examine::parse("2xw").unwrap().lines(&mut cpu, 0x1000);
+        assert_eq!(lines, vec!["0x00001000:\t0x00006968\t0xfffffffe"]);
+        let lines = Examine::parse("dw").unwrap().lines(&mut cpu, 0x1004);
+        assert_eq!(lines, vec!["0x00001004:\t-2"]);
+        let lines = Examine::parse("s").unwrap().lines(&mut cpu, 0x1000);
+        assert_eq!(lines, vec!["0x00001000:\t\"hi\""]);
+        let lines = Examine::parse("2c").unwrap().lines(&mut cpu, 0x1000);
+        assert_eq!(lines, vec!["0x00001000:\t104 'h'\t105 'i'"]);
+        let lines = Examine::parse("5xb").unwrap().lines(&mut cpu, 0x1000);
+        assert_eq!(lines.len(), 1);
+    }
+}
@@ -0,0 +1,212 @@
+//! Stack unwinding for the RV32 guest.
+//!
+//! A frame is unwound with the frame pointer when its function has already
+//! set up `s0`, the return address and the caller's `s0` being saved just
+//! below it. Otherwise the function prologue is scanned for the stack
+//! adjustment and the slots of `ra` and `s0`, and a function that has not
+//! saved `ra` yet returns to the `ra` register.
+
+use super::symbols::SymbolTable;
+use crate::isas::ISA;
+
+/// frames shown at most by a backtrace
+const MAX_FRAMES: usize = 64;
+/// instructions of a function scanned for its prologue
+const MAX_PROLOGUE: u32 = 32;
+
+const SP: u32 = 2;
+const FP: u32 = 8;
+const RA: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Frame {
+    pub(super) pc: u32,
+    pub(super) sp: u32,
+    pub(super) fp: u32,
+    /// the `ra` register, only known in the innermost frame
+    ra: Option<u32>,
+}
+
+/// What the instructions of a prologue executed before pc did.
+#[derive(Debug, Default, PartialEq)]
+struct Prologue {
+    /// bytes the stack pointer moved down
+    frame_size: u32,
+    /// slot of `ra` and `s0`, as offsets below the caller's stack pointer
+    ra_slot: Option<u32>,
+    fp_slot: Option<u32>,
+    /// whether `s0` already points at the caller's stack pointer
+    fp_set: bool,
+}
+
+impl Prologue {
+    fn scan(cpu: &mut impl ISA, start: u32, pc: u32) -> Prologue {
+        let mut prologue = Prologue::default();
+        let end = pc.min(start.saturating_add(MAX_PROLOGUE * 4));
+        for addr in (start..end).step_by(4) {
+            let Some(code) = cpu.peek_mem(addr, 4) else {
+                break;
+            };
+            let rd = (code >> 7) & 0x1f;
+            let rs1 = (code >> 15) & 0x1f;
+            let rs2 = (code >> 20) & 0x1f;
+            match (code & 0x7f, (code >> 12) & 0x7) {
+                // addi
+                (0x13, 0) => {
+                    let imm = (code as i32) >> 20;
+                    if rd == SP && rs1 == SP {
+                        prologue.frame_size = prologue.frame_size.wrapping_sub(imm as u32);
+                    } else if rd == FP && rs1 == SP {
+                        prologue.fp_set = imm as u32 == prologue.frame_size;
+                    }
+                }
+                // sw
+                (0x23, 2) if rs1 == SP => {
+                    let imm = (((code as i32) >> 25) << 5) | ((code >> 7) & 0x1f) as i32;
+                    let slot = prologue.frame_size.wrapping_sub(imm as u32);
+                    match rs2 {
+                        RA => prologue.ra_slot = Some(slot),
+                        FP => prologue.fp_slot = Some(slot),
+                        _ => (),
+                    }
+                }
+                _ => (),
+            }
+        }
+        prologue
+    }
+}
+
+impl Frame {
+    /// the innermost frame, where the program is stopped
+    pub(super) fn current(cpu: &impl ISA) -> Frame {
+        let reg = |name| cpu.read_register_by_name(name).unwrap_or_default();
+        Frame {
+            pc: cpu.pc(),
+            sp: reg("sp"),
+            fp: reg("s0"),
+            ra: Some(reg("ra")),
+        }
+    }
+
+    /// address used to find the function of the frame: outer frames are
+    /// at a return address, just after their call
+    pub(super) fn call_site(&self) -> u32 {
+        match self.ra {
+            Some(_) => self.pc,
+            None => self.pc.wrapping_sub(4),
+        }
+    }
+
+    fn caller(&self, cpu: &mut impl ISA, symbols: &SymbolTable) -> Option<Frame> {
+        let prologue = symbols
+            .locate(self.call_site())
+            .filter(|(symbol, _)| symbol.is_func)
+            .map(|(symbol, _)| Prologue::scan(cpu, symbol.value as u32, self.pc));
+        let caller = match prologue {
+            Some(prologue) if !prologue.fp_set => {
+                let cfa = self.sp.wrapping_add(prologue.frame_size);
+                let pc = match prologue.ra_slot {
+                    Some(slot) => cpu.peek_mem(cfa.wrapping_sub(slot), 4)?,
+                    None => self.ra?,
+                };
+                let fp = match prologue.fp_slot {
+                    Some(slot) => cpu.peek_mem(cfa.wrapping_sub(slot), 4)?,
+                    None => self.fp,
+                };
+                Frame {
+                    pc,
+                    sp: cfa,
+                    fp,
+                    ra: None,
+                }
+            }
+            _ => {
+                if self.fp < self.sp || !self.fp.is_multiple_of(4) {
+                    return None;
+                }
+                Frame {
+                    pc: cpu.peek_mem(self.fp.wrapping_sub(4), 4)?,
+                    sp: self.fp,
+                    fp: cpu.peek_mem(self.fp.wrapping_sub(8), 4)?,
+                    ra: None,
+                }
+            }
+        };
+        // the stack only grows down, code is word aligned and a frame
+        // cannot be its own caller
+        if caller.pc == 0
+            || !caller.pc.is_multiple_of(4)
+            || caller.sp < self.sp
+            || (caller.pc, caller.sp) == (self.pc, self.sp)
+        {
+            return None;
+        }
+        Some(caller)
+    }
+}
+
+/// The frames from the innermost outwards.
+pub(super) fn backtrace(cpu: &mut impl ISA, symbols: &SymbolTable) -> Vec<Frame> {
+    let mut frames = vec![Frame::current(cpu)];
+    while frames.len() < MAX_FRAMES {
+        let frame = frames.last().unwrap();
+        match frame.caller(cpu, symbols) {
+            Some(caller) => frames.push(caller),
+            None => break,
+        }
+    }
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exes::SymbolEntry;
+    use crate::isas::{MemoryModel, RegisterModel, RV32CPU};
+
+    fn function(name: &str, value: u64, size: u64) -> SymbolEntry {
+        SymbolEntry {
+            name: name.to_string(),
+            value,
+            size,
+            is_func: true,
+        }
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let mut cpu = RV32CPU::default();
+        let symbols = SymbolTable::from_entries(vec![
+            function("main", 0x1000, 0x20),
+            function("leaf", 0x1020, 0x10),
+        ]);
+        // main: addi sp, sp, -16; sw ra, 12(sp); sw s0, 8(sp); addi s0, sp, 16; jal leaf
+        let main = [0xff010113, 0x00112623, 0x00812423, 0x01010413, 0x010000ef];
+        for (i, code) in main.iter().enumerate() {
+            cpu.store_mem(0x1000 + 4 * i as u32, 4, *code);
+        }
+        // main's frame, called from 0x2000 with sp = 0x8000 and s0 = 0x9000
+        cpu.store_mem(0x7ffc, 4, 0x2004);
+        cpu.store_mem(0x7ff8, 4, 0x9000);
+
+        // stopped at the entry of leaf, called by main
+        cpu.update_pc(0x1020);
+        cpu.write_register_by_name("sp", 0x7ff0);
+        cpu.write_register_by_name("s0", 0x8000);
+        cpu.write_register_by_name("ra", 0x1014);
+        let frames = backtrace(&mut cpu, &symbols);
+        let pcs = frames.iter().map(|frame| frame.pc).collect::<Vec<_>>();
+        assert_eq!(pcs, vec![0x1020, 0x1014, 0x2004]);
+        assert_eq!(frames[2].sp, 0x8000);
+
+        // stopped in main's prologue after saving ra
+        cpu.update_pc(0x1008);
+        cpu.write_register_by_name("s0", 0x9000);
+        let frames = backtrace(&mut cpu, &symbols);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].pc, 0x2004);
+        assert_eq!(frames[1].sp, 0x8000);
+        assert_eq!(frames[1].fp, 0x9000);
+    }
+}
@@ -0,0 +1,645 @@
+//! GDB remote serial protocol stub, so that `riscv64-unknown-elf-gdb` can
+//! attach to the emulator with `target remote`.
+//!
+//! Only the all-stop subset needed for a single hart is implemented:
+//! registers, memory, `s`/`c`, software and hardware breakpoints,
+//! watchpoints and the riscv32 target description.
+
+use crate::error::RError;
+use crate::isas::riscv::reg::Regs;
+use crate::isas::{AccessKind, ISA};
+use crate::{info, warn};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// gdb numbers the csrs after x0-x31, pc and f0-f31
+const CSR_REGNUM_BASE: u32 = 65;
+/// steps between two checks for a Ctrl-C from gdb
+const INTERRUPT_CHECK_INTERVAL: u64 = 4096;
+/// largest packet gdb may send, as told by qSupported
+const PACKET_SIZE: u32 = 0x4000;
+/// bytes of memory an m or M packet may cover, two hex digits each
+const MAX_MEM_LEN: u32 = PACKET_SIZE / 2;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// A byte stream gdb is connected through.
+pub trait Connection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+impl Connection for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+impl WatchKind {
+    fn matches(&self, kind: AccessKind) -> bool {
+        match self {
+            WatchKind::Write => kind == AccessKind::Write,
+            WatchKind::Read => kind == AccessKind::Read,
+            WatchKind::Access => true,
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self {
+            WatchKind::Write => "watch",
+            WatchKind::Read => "rwatch",
+            WatchKind::Access => "awatch",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Watchpoint {
+    addr: u32,
+    len: u32,
+    kind: WatchKind,
+}
+
+/// Why the target stopped, reported to gdb as a stop reply.
+#[derive(Debug, PartialEq)]
+enum Stop {
+    Signal(u8),
+    Watch(WatchKind, u32),
+    Exited(u8),
+}
+
+impl Stop {
+    fn reply(&self) -> String {
+        match self {
+            Stop::Signal(signal) => format!("S{:02x}", signal),
+            Stop::Watch(kind, addr) => format!("T{:02x}{}:{:x};", SIGTRAP, kind.reason(), addr),
+            Stop::Exited(code) => format!("W{:02x}", code),
+        }
+    }
+}
+
+pub struct GdbStub<C: Connection> {
+    conn: C,
+    breakpoints: Vec<u32>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+/// Wait for gdb on `addr`, a `host:port` TCP address or a unix socket path.
+pub fn listen(addr: &str) -> Result<GdbStub<Box<dyn Connection>>, RError> {
+    let io = |e: std::io::Error| RError::IOError(e.to_string());
+    info!("waiting for gdb on {}", addr);
+    let conn: Box<dyn Connection> = if addr.contains(':') {
+        let (stream, _) = TcpListener::bind(addr).map_err(io)?.accept().map_err(io)?;
+        stream.set_nodelay(true).map_err(io)?;
+        Box::new(stream)
+    } else {
+        let _ = std::fs::remove_file(addr);
+        let (stream, _) = UnixListener::bind(addr).map_err(io)?.accept().map_err(io)?;
+        Box::new(stream)
+    };
+    info!("gdb connected");
+    Ok(GdbStub::new(conn))
+}
+
+impl Connection for Box<dyn Connection> {
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        self.as_ref().set_nonblocking(nonblocking)
+    }
+}
+
+impl<C: Connection> GdbStub<C> {
+    pub fn new(conn: C) -> Self {
+        GdbStub {
+            conn,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+        }
+    }
+
+    /// Serve gdb until it detaches, kills the target or disconnects.
+    pub fn serve(&mut self, cpu: &mut impl ISA) -> Result<(), RError> {
+        while let Some(packet) = self.recv()? {
+            match self.handle(cpu, &packet)? {
+                Some(reply) => self.send(&reply)?,
+                None => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Answer one packet, `None` ending the session.
+    fn handle(&mut self, cpu: &mut impl ISA, packet: &str) -> Result<Option<String>, RError> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => Stop::Signal(SIGTRAP).reply(),
+            "g" => {
+                let mut reply = String::new();
+                for i in 0..32 {
+                    reply.push_str(&hex_u32(cpu[i]));
+                }
+                reply.push_str(&hex_u32(cpu.pc()));
+                reply
+            }
+            "G" => {
+                let values = parse_hex_bytes(args)
+                    .map(|bytes| bytes.chunks(4).map(le_u32).collect::<Vec<_>>());
+                match values {
+                    Some(values) if values.len() >= 33 => {
+                        for (i, value) in values.iter().take(32).enumerate().skip(1) {
+                            cpu[i as u32] = *value;
+                        }
+                        cpu.update_pc(values[32]);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "p" => match u32::from_str_radix(args, 16)
+                .ok()
+                .and_then(|n| read_reg(cpu, n))
+            {
+                Some(value) => hex_u32(value),
+                None => "E01".to_string(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(n, value)| {
+                    let n = u32::from_str_radix(n, 16).ok()?;
+                    Some((n, le_u32(&parse_hex_bytes(value)?)))
+                });
+                match parsed {
+                    Some((n, value)) if write_reg(cpu, n, value) => "OK".to_string(),
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => match parse_addr_len(args) {
+                Some((addr, len)) if len <= MAX_MEM_LEN => {
+                    let mut reply = String::new();
+                    for i in 0..len {
+                        match cpu.peek_mem(addr.wrapping_add(i), 1) {
+                            Some(byte) => reply.push_str(&format!("{:02x}", byte as u8)),
+                            None if i == 0 => return Ok(Some("E14".to_string())),
+                            None => break,
+                        }
+                    }
+                    reply
+                }
+                _ => "E01".to_string(),
+            },
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(range, data)| {
+                    Some((parse_addr_len(range)?, parse_hex_bytes(data)?))
+                });
+                match parsed {
+                    Some(((addr, len), data))
+                        if len <= MAX_MEM_LEN && data.len() == len as usize =>
+                    {
+                        // only RAM, and all of it or nothing, devices having
+                        // side effects
+                        let in_ram =
+                            (0..len).all(|i| cpu.peek_mem(addr.wrapping_add(i), 1).is_some());
+                        if !in_ram {
+                            return Ok(Some("E14".to_string()));
+                        }
+                        for (i, byte) in data.iter().enumerate() {
+                            cpu.poke_mem(addr.wrapping_add(i as u32), 1, *byte as u32);
+                        }
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "s" | "c" => {
+                if let Ok(addr) = u32::from_str_radix(args, 16) {
+                    cpu.update_pc(addr);
+                }
+                self.resume(cpu, command == "s")?.reply()
+            }
+            "Z" | "z" => self.set_point(args, command == "Z"),
+            "H" | "T" => "OK".to_string(),
+            "k" => return Ok(None),
+            "D" => {
+                self.send("OK")?;
+                return Ok(None);
+            }
+            "q" => self.query(packet),
+            _ => String::new(),
+        };
+        Ok(Some(reply))
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE)
+        } else if let Some(args) = packet.strip_prefix("qXfer:features:read:") {
+            let Some((annex, range)) = args.split_once(':') else {
+                return "E01".to_string();
+            };
+            let xml = match annex {
+                "target.xml" => target_xml(),
+                _ => return "E00".to_string(),
+            };
+            match parse_addr_len(range) {
+                Some((offset, len)) => {
+                    let offset = (offset as usize).min(xml.len());
+                    let end = (offset + len as usize).min(xml.len());
+                    let prefix = if end == xml.len() { 'l' } else { 'm' };
+                    format!("{}{}", prefix, &xml[offset..end])
+                }
+                None => "E01".to_string(),
+            }
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if packet == "qC" {
+            "QC1".to_string()
+        } else if packet == "qfThreadInfo" {
+            "m1".to_string()
+        } else if packet == "qsThreadInfo" {
+            "l".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    /// Handle `Z`/`z` packets, `type,addr,kind`.
+    fn set_point(&mut self, args: &str, insert: bool) -> String {
+        let fields = args.split(',').collect::<Vec<_>>();
+        if fields.len() < 3 {
+            return "E01".to_string();
+        }
+        let (Ok(addr), Ok(len)) = (
+            u32::from_str_radix(fields[1], 16),
+            u32::from_str_radix(fields[2], 16),
+        ) else {
+            return "E01".to_string();
+        };
+        let kind = match fields[0] {
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.push(addr);
+                } else if let Some(i) = self.breakpoints.iter().position(|bp| *bp == addr) {
+                    self.breakpoints.remove(i);
+                }
+                return "OK".to_string();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return String::new(),
+        };
+        let watchpoint = Watchpoint { addr, len, kind };
+        if insert {
+            self.watchpoints.push(watchpoint);
+        } else if let Some(i) = self.watchpoints.iter().position(|wp| *wp == watchpoint) {
+            self.watchpoints.remove(i);
+        }
+        "OK".to_string()
+    }
+
+    /// Execute one instruction, or run until something stops the target.
+    fn resume(&mut self, cpu: &mut impl ISA, single: bool) -> Result<Stop, RError> {
+        cpu.trace_accesses(!self.watchpoints.is_empty());
+        let mut steps: u64 = 0;
+        let stop = loop {
+            cpu.take_accesses();
+            match cpu.step() {
+                Ok(()) => (),
+                Err(RError::Ebreak(code)) => break Stop::Exited(code as u8),
+                Err(RError::InvalidMem(_))
+                | Err(RError::InvalidStore(_))
+                | Err(RError::AddressMisaligned(_)) => break Stop::Signal(SIGSEGV),
+                Err(RError::InvalidCode(_)) | Err(RError::InvalidInstruction(_)) => {
+                    break Stop::Signal(SIGILL)
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    break Stop::Signal(SIGTRAP);
+                }
+            }
+            if let Some(stop) = self.watch_hit(cpu) {
+                break stop;
+            }
+            if single || self.breakpoints.contains(&cpu.pc()) {
+                break Stop::Signal(SIGTRAP);
+            }
+            steps += 1;
+            if steps.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && self.interrupted()? {
+                break Stop::Signal(SIGINT);
+            }
+        };
+        cpu.trace_accesses(false);
+        Ok(stop)
+    }
+
+    fn watch_hit(&self, cpu: &mut impl ISA) -> Option<Stop> {
+        if self.watchpoints.is_empty() {
+            return None;
+        }
+        for access in cpu.take_accesses() {
+            for wp in self.watchpoints.iter() {
+                if wp.kind.matches(access.kind) && access.overlaps(wp.addr, wp.len) {
+                    return Some(Stop::Watch(wp.kind, wp.addr));
+                }
+            }
+        }
+        None
+    }
+
+    /// Whether gdb sent a Ctrl-C while the target was running.
+    fn interrupted(&mut self) -> Result<bool, RError> {
+        let io = |e: std::io::Error| RError::IOError(e.to_string());
+        self.conn.set_nonblocking(true).map_err(io)?;
+        let mut byte = [0u8; 1];
+        let result = self.conn.read(&mut byte);
+        self.conn.set_nonblocking(false).map_err(io)?;
+        match result {
+            Ok(1) => Ok(byte[0] == 0x03),
+            Ok(_) => Err(RError::IOError("gdb disconnected".to_string())),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(io(e)),
+        }
+    }
+
+    /// Read the next packet, acknowledging it. `None` if gdb disconnected.
+    fn recv(&mut self) -> Result<Option<String>, RError> {
+        let mut packet = Vec::new();
+        let mut in_packet = false;
+        loop {
+            let Some(byte) = self.read_byte()? else {
+                return Ok(None);
+            };
+            match byte {
+                b'$' => {
+                    in_packet = true;
+                    packet.clear();
+                }
+                b'#' if in_packet => {
+                    let (Some(high), Some(low)) = (self.read_byte()?, self.read_byte()?) else {
+                        return Ok(None);
+                    };
+                    let expected = std::str::from_utf8(&[high, low])
+                        .ok()
+                        .and_then(|s| u8::from_str_radix(s, 16).ok());
+                    if expected == Some(checksum(&packet)) {
+                        self.write_all(b"+")?;
+                        return Ok(Some(String::from_utf8_lossy(&packet).into_owned()));
+                    }
+                    self.write_all(b"-")?;
+                    in_packet = false;
+                }
+                _ if in_packet => packet.push(byte),
+                // acks and stray interrupts between packets
+                _ => (),
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> Result<(), RError> {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        loop {
+            self.write_all(packet.as_bytes())?;
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>, RError> {
+        let mut byte = [0u8; 1];
+        match self.conn.read(&mut byte) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(e) => Err(RError::IOError(e.to_string())),
+        }
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), RError> {
+        self.conn
+            .write_all(bytes)
+            .and_then(|_| self.conn.flush())
+            .map_err(|e| RError::IOError(e.to_string()))
+    }
+}
+
+fn read_reg(cpu: &impl ISA, n: u32) -> Option<u32> {
+    match n {
+        0..=31 => Some(cpu[n]),
+        32 => Some(cpu.pc()),
+        n if n >= CSR_REGNUM_BASE => cpu.read_register_previlege(n - CSR_REGNUM_BASE),
+        _ => None,
+    }
+}
+
+fn write_reg(cpu: &mut impl ISA, n: u32, value: u32) -> bool {
+    match n {
+        0 => (),
+        1..=31 => cpu[n] = value,
+        32 => cpu.update_pc(value),
+        n if n >= CSR_REGNUM_BASE && n - CSR_REGNUM_BASE < 0x1000 => {
+            cpu.write_register_previlege(n - CSR_REGNUM_BASE, value)
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// riscv32 target description, x0-x31 and pc followed by the known csrs
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\">\
+         <architecture>riscv:rv32</architecture>\
+         <feature name=\"org.gnu.gdb.riscv.cpu\">",
+    );
+    for i in 0..32 {
+        let (name, kind) = match i {
+            0 => ("zero".to_string(), "int"),
+            1 => ("ra".to_string(), "code_ptr"),
+            2 => ("sp".to_string(), "data_ptr"),
+            3 => ("gp".to_string(), "data_ptr"),
+            4 => ("tp".to_string(), "data_ptr"),
+            _ => (Regs::index_to_name(i), "int"),
+        };
+        xml.push_str(&format!(
+            "<reg name=\"{}\" bitsize=\"32\" type=\"{}\" regnum=\"{}\"/>",
+            name, kind, i
+        ));
+    }
+    xml.push_str("<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"32\"/>");
+    xml.push_str("</feature><feature name=\"org.gnu.gdb.riscv.csr\">");
+    for (index, name) in Regs::csr_names() {
+        xml.push_str(&format!(
+            "<reg name=\"{}\" bitsize=\"32\" regnum=\"{}\"/>",
+            name,
+            CSR_REGNUM_BASE + index
+        ));
+    }
+    xml.push_str("</feature></target>");
+    xml
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// 32-bit value in target (little endian) byte order
+fn hex_u32(value: u32) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .take(4)
+        .enumerate()
+        .fold(0, |value, (i, byte)| value | (*byte as u32) << (i * 8))
+}
+
+fn parse_hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// `addr,length` in hex
+fn parse_addr_len(args: &str) -> Option<(u32, u32)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((
+        u32::from_str_radix(addr, 16).ok()?,
+        u32::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isas::{MemoryModel, RegisterModel, RV32CPU};
+    use std::collections::VecDeque;
+
+    /// gdb side of the connection, replaying scripted input
+    #[derive(Default)]
+    struct Script {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.input.pop_front() {
+                Some(byte) => {
+                    buf[0] = byte;
+                    Ok(1)
+                }
+                None => Err(ErrorKind::WouldBlock.into()),
+            }
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Script {
+        fn set_nonblocking(&self, _nonblocking: bool) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_packets() {
+        let mut cpu = RV32CPU::default();
+        let mut stub = GdbStub::new(Script::default());
+        let mut ask = |cpu: &mut RV32CPU, packet: &str| stub.handle(cpu, packet).unwrap().unwrap();
+
+        cpu.write_register_by_name("a0", 0x12345678);
+        assert_eq!(ask(&mut cpu, "pa"), "78563412");
+        assert_eq!(ask(&mut cpu, "Pb=efbeadde"), "OK");
+        assert_eq!(cpu.read_register_by_name("a1"), Some(0xdeadbeef));
+        assert_eq!(ask(&mut cpu, "M80000000,4:13050500"), "OK");
+        assert_eq!(cpu.load_mem(0x80000000, 4), Some(0x00050513));
+        assert_eq!(ask(&mut cpu, "m80000000,2"), "1305");
+        assert_eq!(ask(&mut cpu, "ma0000048,4"), "E14");
+        // devices and addresses past RAM are not written
+        assert_eq!(ask(&mut cpu, "Ma00003f8,1:41"), "E14");
+        assert_eq!(ask(&mut cpu, "M9ffffffe,4:01020304"), "E14");
+        assert_eq!(ask(&mut cpu, "m9ffffffe,2"), "0000");
+        // at most half a packet of memory
+        assert_eq!(ask(&mut cpu, "m80000000,2000").len(), 0x4000);
+        assert_eq!(ask(&mut cpu, "m0,ffffffff"), "E01");
+        assert_eq!(ask(&mut cpu, "m80000000,2001"), "E01");
+        let data = "00".repeat(0x2001);
+        assert_eq!(ask(&mut cpu, &format!("M80000000,2001:{}", data)), "E01");
+        assert!(ask(&mut cpu, "qXfer:features:read:target.xml:0,40").starts_with("m<?xml"));
+    }
+
+    #[test]
+    fn test_breakpoint_and_watchpoint() {
+        use crate::isas::riscv::instruction::Instruction;
+        use crate::isas::Inst;
+
+        let mut cpu = RV32CPU::default();
+        let program = [
+            Instruction::IType(1, (10, 0), 0b000, 10, 0b0010011).assemble(), // addi a0, a0, 1
+            Instruction::SType(0, (2, 10), 0b010, 0b0100011).assemble(),     // sw a0, 0(sp)
+            Instruction::JType(-8_i32 as u32, 0, 0b1101111).assemble(),      // j -8
+        ];
+        for (i, code) in program.iter().enumerate() {
+            cpu.store_mem(0x80000000 + 4 * i as u32, 4, *code);
+        }
+        cpu.update_pc(0x80000000);
+        cpu.write_register_by_name("sp", 0x80001000);
+
+        let mut stub = GdbStub::new(Script::default());
+        assert_eq!(
+            stub.handle(&mut cpu, "Z0,80000008,4").unwrap().unwrap(),
+            "OK"
+        );
+        assert_eq!(stub.handle(&mut cpu, "c").unwrap().unwrap(), "S05");
+        assert_eq!(cpu.pc(), 0x80000008);
+        assert_eq!(
+            stub.handle(&mut cpu, "z0,80000008,4").unwrap().unwrap(),
+            "OK"
+        );
+        assert_eq!(
+            stub.handle(&mut cpu, "Z2,80001000,4").unwrap().unwrap(),
+            "OK"
+        );
+        assert_eq!(
+            stub.handle(&mut cpu, "c").unwrap().unwrap(),
+            "T05watch:80001000;"
+        );
+        assert_eq!(cpu.read_register_by_name("a0"), Some(2));
+    }
+}
@@ -0,0 +1,42 @@
+//! Ctrl-C while the debugged program runs pauses it instead of killing
+//! the debugger.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigint(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// Catches SIGINT until dropped, when the previous handler is restored, so
+/// that guards can nest.
+pub(super) struct Interrupt {
+    previous: libc::sighandler_t,
+}
+
+impl Interrupt {
+    pub(super) fn catch() -> Interrupt {
+        let handler = on_sigint as *const () as libc::sighandler_t;
+        // SAFETY: the handler only stores to an atomic
+        let previous = unsafe { libc::signal(libc::SIGINT, handler) };
+        if previous != handler {
+            INTERRUPTED.store(false, Ordering::Relaxed);
+        }
+        Interrupt { previous }
+    }
+
+    /// whether Ctrl-C was pressed since the last call
+    pub(super) fn take(&self) -> bool {
+        INTERRUPTED.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl Drop for Interrupt {
+    fn drop(&mut self) {
+        // SAFETY: restoring the handler replaced by `catch`
+        unsafe {
+            libc::signal(libc::SIGINT, self.previous);
+        }
+    }
+}
@@ -1,5 +1,13 @@
 mod breakpoint;
+mod catchpoint;
 mod debugger;
 mod eval;
+mod examine;
+mod frames;
+pub mod gdbstub;
+mod interrupt;
+mod prompt;
+mod symbols;
+mod tui;
 
 pub use debugger::Debugger;
@@ -0,0 +1,293 @@
+//! The `(rdb)` prompt: line editing, history kept across sessions in
+//! `$RDB_HISTORY` or `~/.rdb_history`, and completion of commands,
+//! registers, CSRs and symbols. Input that is not a terminal is read
+//! line by line.
+
+use colored::Colorize;
+use rustyline::completion::{Completer, FilenameCompleter};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::Validator;
+use rustyline::{CompletionType, Config, Context, Editor, Helper};
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+
+/// entries kept in the history file
+const HISTORY_SIZE: usize = 1000;
+
+/// command names, in their long forms
+const COMMANDS: [&str; 43] = [
+    "advance",
+    "awatch",
+    "backtrace",
+    "break",
+    "catch",
+    "clear",
+    "commands",
+    "condition",
+    "continue",
+    "delete",
+    "disable",
+    "disassemble",
+    "display",
+    "down",
+    "enable",
+    "finish",
+    "frame",
+    "help",
+    "ignore",
+    "jump",
+    "last-change",
+    "layout",
+    "next",
+    "next-line",
+    "nexti",
+    "print",
+    "quit",
+    "reverse-continue",
+    "reverse-step",
+    "run",
+    "rwatch",
+    "set",
+    "show",
+    "source",
+    "step",
+    "step-line",
+    "tbreak",
+    "undisplay",
+    "until",
+    "up",
+    "watch",
+    "where",
+    "x",
+];
+
+/// arguments of commands taking a fixed set of words
+const ARGUMENTS: [(&str, &[&str]); 3] = [
+    (
+        "catch",
+        &["syscall", "exception", "interrupt", "mode-switch"],
+    ),
+    ("show", &["asm", "reg", "mem", "src", "break"]),
+    (
+        "layout",
+        &["tui", "split", "off", "asm", "reg", "mem", "src"],
+    ),
+];
+
+/// Names to complete besides the commands.
+#[derive(Default)]
+struct Completion {
+    registers: Vec<String>,
+    symbols: Vec<String>,
+    files: FilenameCompleter,
+}
+
+impl Completion {
+    /// Start of the word before `pos` and its candidates.
+    fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let start = line[..pos]
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || "_.$-".contains(c)))
+            .map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        let before = line[..start].split_whitespace().collect::<Vec<_>>();
+        let names: Vec<&str> = match before.as_slice() {
+            [] => COMMANDS.to_vec(),
+            [command] if ARGUMENTS.iter().any(|(name, _)| name == command) => {
+                let (_, arguments) = ARGUMENTS.iter().find(|(name, _)| name == command).unwrap();
+                arguments.to_vec()
+            }
+            _ if word.starts_with('$') => {
+                let names = self
+                    .registers
+                    .iter()
+                    .filter(|name| name.starts_with(&word[1..]));
+                let mut names = names.map(|name| format!("${}", name)).collect::<Vec<_>>();
+                names.sort();
+                return (start, finish(names));
+            }
+            _ => self
+                .symbols
+                .iter()
+                .chain(self.registers.iter())
+                .map(String::as_str)
+                .collect(),
+        };
+        let mut names = names
+            .into_iter()
+            .filter(|name| name.starts_with(word))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        (start, finish(names))
+    }
+}
+
+/// A space after the only candidate, to go on with the next word.
+fn finish(mut names: Vec<String>) -> Vec<String> {
+    if let [name] = names.as_mut_slice() {
+        name.push(' ');
+    }
+    names
+}
+
+impl Completer for Completion {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        if line.trim_start().starts_with("source ") {
+            let (start, paths) = self.files.complete_path(line, pos)?;
+            return Ok((
+                start,
+                paths.into_iter().map(|path| path.replacement).collect(),
+            ));
+        }
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+pub(super) struct Prompt {
+    /// the line editor, only for terminals
+    editor: Option<Editor<Completion, FileHistory>>,
+    history: Option<PathBuf>,
+}
+
+fn history_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("RDB_HISTORY") {
+        return Some(PathBuf::from(path));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rdb_history"))
+}
+
+impl Prompt {
+    pub(super) fn new() -> Prompt {
+        let editor = std::io::stdin()
+            .is_terminal()
+            .then(|| {
+                let config = Config::builder()
+                    .max_history_size(HISTORY_SIZE)
+                    .ok()?
+                    .auto_add_history(false)
+                    .completion_type(CompletionType::List)
+                    .build();
+                Editor::with_config(config).ok()
+            })
+            .flatten();
+        let mut prompt = Prompt {
+            editor,
+            history: history_path(),
+        };
+        if let Some(editor) = prompt.editor.as_mut() {
+            editor.set_helper(Some(Completion::default()));
+            if let Some(path) = prompt.history.as_ref() {
+                // there is no history on the first run
+                let _ = editor.load_history(path);
+            }
+        }
+        prompt
+    }
+
+    /// Complete these register and symbol names as well as commands.
+    pub(super) fn set_names(&mut self, registers: Vec<String>, symbols: Vec<String>) {
+        if let Some(completion) = self.editor.as_mut().and_then(Editor::helper_mut) {
+            completion.registers = registers;
+            completion.symbols = symbols;
+        }
+    }
+
+    /// A line of input, `None` at the end of it. Ctrl-C abandons the line
+    /// being edited.
+    pub(super) fn read(&mut self, prompt: &str) -> Option<String> {
+        let prompt = format!("{} ", prompt.truecolor(169, 169, 169).bold());
+        let Some(editor) = self.editor.as_mut() else {
+            print!("{}", prompt);
+            std::io::stdout().flush().unwrap();
+            let mut input = String::new();
+            return match std::io::stdin().read_line(&mut input) {
+                Ok(0) | Err(_) => None,
+                Ok(_) => Some(input.trim().to_string()),
+            };
+        };
+        loop {
+            match editor.readline(&prompt) {
+                Ok(line) => {
+                    let line = line.trim().to_string();
+                    if !line.is_empty() && editor.add_history_entry(&line).unwrap_or(false) {
+                        if let Some(path) = self.history.as_ref() {
+                            let _ = editor.append_history(path);
+                        }
+                    }
+                    return Some(line);
+                }
+                Err(ReadlineError::Interrupted) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completion() {
+        let completion = Completion {
+            registers: vec!["a0".to_string(), "a1".to_string(), "mstatus".to_string()],
+            symbols: vec!["main".to_string(), "malloc".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            completion.candidates("disp", 4),
+            (0, vec!["display ".to_string()])
+        );
+        assert_eq!(
+            completion.candidates("  rev", 5),
+            (
+                2,
+                vec!["reverse-continue".to_string(), "reverse-step".to_string()]
+            )
+        );
+        assert_eq!(
+            completion.candidates("p $a", 4),
+            (2, vec!["$a0".to_string(), "$a1".to_string()])
+        );
+        assert_eq!(
+            completion.candidates("b ma", 4),
+            (2, vec!["main".to_string(), "malloc".to_string()])
+        );
+        assert_eq!(
+            completion.candidates("p *(u8*)m", 9),
+            (
+                8,
+                vec![
+                    "main".to_string(),
+                    "malloc".to_string(),
+                    "mstatus".to_string()
+                ]
+            )
+        );
+        assert_eq!(
+            completion.candidates("catch sy", 8),
+            (6, vec!["syscall ".to_string()])
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use crate::exes::{LineTable, SymbolEntry, ELF};
+use crate::isas::ISA;
+use crate::warn;
+
+/// Symbols of the debugged program, sorted by address, and its source lines.
+#[derive(Debug, Default)]
+pub(super) struct SymbolTable {
+    symbols: Vec<SymbolEntry>,
+    lines: LineTable,
+}
+
+impl SymbolTable {
+    pub(super) fn new(elf: &ELF) -> Self {
+        let mut symbols = elf.symbol_entries();
+        // mapping symbols such as `$x` only mark the start of code
+        symbols.retain(|symbol| !symbol.name.starts_with('$'));
+        symbols.sort_by_key(|symbol| symbol.value);
+        let lines = LineTable::from_elf(elf).unwrap_or_else(|e| {
+            warn!("no source lines: {}", e);
+            LineTable::default()
+        });
+        SymbolTable { symbols, lines }
+    }
+
+    /// Source file and line of the instruction at `addr`.
+    pub(super) fn line(&self, addr: u32) -> Option<(&str, u32)> {
+        self.lines.locate(addr as u64)
+    }
+
+    /// Whether `addr` starts a statement of a source line.
+    pub(super) fn is_stmt(&self, addr: u32) -> bool {
+        self.lines.is_stmt(addr as u64)
+    }
+
+    /// Address of `file:line`, the first one if the line has several.
+    pub(super) fn line_address(&self, file: &str, line: u32) -> Option<u32> {
+        self.lines
+            .addresses(file, line)
+            .first()
+            .map(|addr| *addr as u32)
+    }
+
+    /// Names of the symbols, for completion.
+    pub(super) fn names(&self) -> Vec<String> {
+        self.symbols
+            .iter()
+            .map(|symbol| symbol.name.clone())
+            .collect()
+    }
+
+    pub(super) fn lookup(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .map(|symbol| symbol.value as u32)
+    }
+
+    /// Start and end address of function `name`.
+    pub(super) fn function(&self, name: &str) -> Option<(u32, u32)> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.name == name && symbol.is_func && symbol.size > 0)
+            .map(|symbol| (symbol.value as u32, (symbol.value + symbol.size) as u32))
+    }
+
+    /// The symbol covering `addr`, or the closest sizeless symbol before
+    /// it, with the offset of `addr` into it.
+    pub(super) fn locate(&self, addr: u32) -> Option<(&SymbolEntry, u32)> {
+        let addr = addr as u64;
+        let end = self.symbols.partition_point(|symbol| symbol.value <= addr);
+        self.symbols[..end]
+            .iter()
+            .rev()
+            .find(|symbol| symbol.size == 0 || addr < symbol.value + symbol.size)
+            .map(|symbol| (symbol, (addr - symbol.value) as u32))
+    }
+
+    /// `<name+offset>` label of `addr`, empty if no symbol covers it.
+    pub(super) fn label(&self, addr: u32) -> String {
+        match self.locate(addr) {
+            Some((symbol, 0)) => format!("<{}>", symbol.name),
+            Some((symbol, offset)) => format!("<{}+{}>", symbol.name, offset),
+            None => String::new(),
+        }
+    }
+
+    /// `addr` disassembled, with the symbol of a branch or jump target
+    pub(super) fn disassemble(&self, cpu: &mut impl ISA, addr: u32) -> String {
+        let Ok(inst) = cpu.disassemble(addr) else {
+            return "<???>".to_string();
+        };
+        match cpu.jump_target(addr) {
+            Some(target) => format!("{}  # {:#x} {}", inst, target, self.label(target)),
+            None => inst,
+        }
+    }
+}
+
+#[cfg(test)]
+impl SymbolTable {
+    pub(super) fn from_entries(mut symbols: Vec<SymbolEntry>) -> Self {
+        symbols.sort_by_key(|symbol| symbol.value);
+        SymbolTable {
+            symbols,
+            lines: LineTable::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(name: &str, value: u64, size: u64, is_func: bool) -> SymbolEntry {
+        SymbolEntry {
+            name: name.to_string(),
+            value,
+            size,
+            is_func,
+        }
+    }
+
+    #[test]
+    fn test_locate() {
+        let symbols = SymbolTable::from_entries(vec![
+            symbol("main", 0x1010, 0x20, true),
+            symbol("_start", 0x1000, 0x10, true),
+            symbol("buf", 0x2000, 0x100, false),
+            symbol("_end", 0x3000, 0, false),
+        ]);
+        assert_eq!(symbols.lookup("main"), Some(0x1010));
+        assert_eq!(symbols.function("main"), Some((0x1010, 0x1030)));
+        assert_eq!(symbols.function("buf"), None);
+        assert_eq!(symbols.label(0x1000), "<_start>");
+        assert_eq!(symbols.label(0x1014), "<main+4>");
+        assert_eq!(symbols.label(0x1030), "");
+        assert_eq!(symbols.label(0x20ff), "<buf+255>");
+        assert_eq!(symbols.label(0x3004), "<_end+4>");
+        assert_eq!(symbols.label(0x0ffc), "");
+    }
+
+    #[test]
+    fn test_lines() {
+        use crate::exes::Exe;
+        let elf = ELF::parse_path("tests/a_32.out").unwrap();
+        let symbols = SymbolTable::new(&elf);
+        assert_eq!(symbols.line_address("div.S", 74), Some(0x13574));
+        assert!(symbols.line(0x13574).unwrap().0.ends_with("div.S"));
+        assert!(symbols.is_stmt(0x13574));
+        assert_eq!(symbols.label(0x10194), "<main+4>");
+    }
+}
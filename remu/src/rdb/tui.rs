@@ -0,0 +1,261 @@
+//! Split-pane terminal layout. Disassembly, registers, the stack and the
+//! serial output are drawn at the top of the screen before each prompt,
+//! while commands and their output scroll in the region left below them.
+
+use super::symbols::SymbolTable;
+use crate::error::RError;
+use crate::ioe::{capture_serial, take_serial};
+use crate::isas::ISA;
+use colored::Colorize;
+use console::{measure_text_width, truncate_str, Term};
+use std::collections::HashSet;
+use std::io::Write;
+
+/// smallest terminal the layout fits in
+const MIN_ROWS: usize = 24;
+const MIN_COLS: usize = 80;
+/// rows left at the bottom for commands and their output
+const COMMAND_ROWS: usize = 8;
+/// serial output kept for its pane, in bytes
+const SERIAL_KEEP: usize = 0x4000;
+
+pub(super) struct Tui {
+    term: Term,
+    serial: String,
+    /// terminal size the scroll region was set for
+    size: (usize, usize),
+}
+
+/// `text` cut or padded to `width` columns, ignoring color codes.
+fn fit(text: &str, width: usize) -> String {
+    let len = measure_text_width(text);
+    match len > width {
+        true => truncate_str(text, width, "…").into_owned(),
+        false => format!("{}{}", text, " ".repeat(width - len)),
+    }
+}
+
+/// registers in as many columns as fit, `changed` ones highlighted
+fn registers(
+    cpu: &impl ISA,
+    changed: &HashSet<String>,
+    height: usize,
+    width: usize,
+) -> Vec<String> {
+    let columns = (width / 17).max(1);
+    let mut lines = vec![String::new(); height];
+    for (i, (name, value)) in cpu.iter().enumerate() {
+        let (row, column) = (i % height, i / height);
+        if column >= columns {
+            break;
+        }
+        let entry = format!(" {:>4} {:#010x} ", name, value);
+        match changed.contains(&name) {
+            true => lines[row].push_str(&entry.yellow().bold().to_string()),
+            false => lines[row].push_str(&entry),
+        }
+    }
+    lines
+}
+
+impl Tui {
+    /// Take over the terminal, capturing the serial output for its pane.
+    pub(super) fn enable() -> Result<Tui, RError> {
+        let term = Term::stdout();
+        if !term.is_term() {
+            return Err(RError::DebuggerError(
+                "the tui layout needs a terminal".to_string(),
+            ));
+        }
+        let (rows, cols) = term.size();
+        if (rows as usize) < MIN_ROWS || (cols as usize) < MIN_COLS {
+            return Err(RError::DebuggerError(format!(
+                "the tui layout needs at least {}x{} characters",
+                MIN_COLS, MIN_ROWS
+            )));
+        }
+        capture_serial(true);
+        Ok(Tui {
+            term,
+            serial: String::new(),
+            size: (0, 0),
+        })
+    }
+
+    /// Lay the screen out again on the next redraw.
+    pub(super) fn invalidate(&mut self) {
+        self.size = (0, 0);
+    }
+
+    /// Redraw the panes, `status` describing the state of the program and
+    /// `changed` the registers to highlight.
+    pub(super) fn draw(
+        &mut self,
+        cpu: &mut impl ISA,
+        symbols: &SymbolTable,
+        status: &str,
+        changed: &HashSet<String>,
+    ) -> Result<(), RError> {
+        let (rows, cols) = self.term.size();
+        let (rows, cols) = (rows as usize, cols as usize);
+        if rows < MIN_ROWS || cols < MIN_COLS {
+            return Ok(());
+        }
+        let panes = rows - COMMAND_ROWS - 3;
+        let top = panes * 3 / 5;
+        let bottom = panes - top;
+        let left = cols * 3 / 5;
+        let right = cols - left - 1;
+
+        let mut out = String::new();
+        if self.size != (rows, cols) {
+            // commands scroll below the panes, from the row after the status
+            out.push_str(&format!(
+                "\x1b[{};{}r\x1b[2J\x1b[{};1H",
+                rows - COMMAND_ROWS + 1,
+                rows,
+                rows
+            ));
+            self.size = (rows, cols);
+        }
+        let asm = self.asm(cpu, symbols, top, left);
+        let registers = registers(cpu, changed, top, right);
+        let stack = self.stack(cpu, symbols, bottom);
+        let serial = self.serial(bottom);
+
+        let mut lines = vec![format!(
+            "{}┬{}",
+            fit(&format!("{:─<1$}", "─ asm ", left), left),
+            fit(&format!("{:─<1$}", "─ registers ", right), right)
+        )];
+        for i in 0..top {
+            let asm = asm.get(i).map(String::as_str).unwrap_or("");
+            let register = registers.get(i).map(String::as_str).unwrap_or("");
+            lines.push(format!("{}│{}", fit(asm, left), fit(register, right)));
+        }
+        lines.push(format!(
+            "{}┼{}",
+            fit(&format!("{:─<1$}", "─ stack ", left), left),
+            fit(&format!("{:─<1$}", "─ serial ", right), right)
+        ));
+        for i in 0..bottom {
+            let stack = stack.get(i).map(String::as_str).unwrap_or("");
+            let serial = serial.get(i).map(String::as_str).unwrap_or("");
+            lines.push(format!("{}│{}", fit(stack, left), fit(serial, right)));
+        }
+        lines.push(fit(
+            &format!("{:─<1$}", format!("─ {} ", status), cols),
+            cols,
+        ));
+
+        // draw over the panes and put the cursor back in the command region
+        out.push_str("\x1b7");
+        for (row, line) in lines.iter().enumerate() {
+            out.push_str(&format!("\x1b[{};1H\x1b[2K{}", row + 1, line));
+        }
+        out.push_str("\x1b8");
+        self.term
+            .write_all(out.as_bytes())
+            .and_then(|_| self.term.flush())
+            .map_err(|e| RError::IOError(e.to_string()))
+    }
+
+    /// instructions around pc, pc in the upper third
+    fn asm(
+        &self,
+        cpu: &mut impl ISA,
+        symbols: &SymbolTable,
+        height: usize,
+        width: usize,
+    ) -> Vec<String> {
+        let pc = cpu.pc();
+        let start = pc.saturating_sub(4 * (height / 3) as u32);
+        (0..height as u32)
+            .map(|i| {
+                let addr = start.wrapping_add(4 * i);
+                let line = format!(
+                    "{}{:#010x} {:<20} {}",
+                    if addr == pc { ">" } else { " " },
+                    addr,
+                    symbols.label(addr),
+                    symbols.disassemble(cpu, addr)
+                );
+                match addr == pc {
+                    true => fit(&line, width).green().bold().to_string(),
+                    false => line,
+                }
+            })
+            .collect()
+    }
+
+    /// words from the stack pointer up, with the symbol they point into
+    fn stack(&self, cpu: &mut impl ISA, symbols: &SymbolTable, height: usize) -> Vec<String> {
+        let sp = cpu.read_register_by_name("sp").unwrap_or_default();
+        (0..height as u32)
+            .map(|i| {
+                let addr = sp.wrapping_add(4 * i);
+                let marker = if i == 0 { "sp>" } else { "   " };
+                match cpu.peek_mem(addr, 4) {
+                    Some(value) => format!(
+                        "{} {:#010x}: {:#010x} {}",
+                        marker,
+                        addr,
+                        value,
+                        symbols.label(value)
+                    ),
+                    None => format!("{} {:#010x}: <unavailable>", marker, addr),
+                }
+            })
+            .collect()
+    }
+
+    /// the last lines written to the serial port
+    fn serial(&mut self, height: usize) -> Vec<String> {
+        self.serial.push_str(&take_serial());
+        if self.serial.len() > SERIAL_KEEP {
+            let mut cut = self.serial.len() - SERIAL_KEEP;
+            while !self.serial.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.serial.drain(..cut);
+        }
+        let lines = self
+            .serial
+            .split('\n')
+            .map(|line| line.rsplit('\r').next().unwrap_or("").to_string())
+            .collect::<Vec<_>>();
+        lines[lines.len().saturating_sub(height)..].to_vec()
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        capture_serial(false);
+        // whole screen scrolling again
+        let _ = self.term.write_all(b"\x1b[r\x1b[2J\x1b[1;1H");
+        let _ = self.term.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isas::RV32CPU;
+
+    #[test]
+    fn test_panes() {
+        let mut tui = Tui {
+            term: Term::stdout(),
+            serial: "boot\r\nhello\nwor".to_string(),
+            size: (0, 0),
+        };
+        assert_eq!(tui.serial(2), vec!["hello", "wor"]);
+
+        let cpu = RV32CPU::default();
+        let changed = HashSet::from(["a0".to_string()]);
+        let lines = registers(&cpu, &changed, 17, 40);
+        assert_eq!(lines.len(), 17);
+        assert!(lines[10].contains("a0"));
+        assert!(lines[15].contains("pc"));
+    }
+}
@@ -1,3 +1,9 @@
+use crate::config::{self, Table};
+use crate::error::RError;
+use crate::ioe;
+use crate::isas::PrivilegeMode;
+use std::path::{Path, PathBuf};
+
 pub const ENABLE_DEBUG: bool = true;
 
 pub const ENABLE_SERIAL: bool = true;
@@ -8,12 +14,598 @@ pub const ENABLE_DISK: bool = false;
 pub const ENABLE_FB: bool = false;
 pub const ENABLE_TIMER: bool = true;
 
+/// bytes of RAM, from address 0 up to the devices
+pub const MEM_SIZE: u64 = ioe::DEVICE_BASE;
+
+pub const VGA_WIDTH: u32 = 400;
+pub const VGA_HEIGHT: u32 = 300;
+
+/// the end of the 32-bit address space
+const ADDRESS_END: u64 = 1 << 32;
+
 #[macro_export]
 macro_rules! add_device {
-    ($flag:ident, $device:ty, $devices:ident) => {
-        if $flag {
-            $devices.push(Box::<$device>::default());
-            info!("{} enabled", stringify!($device));
+    ($base:expr, $device:ty, $devices:ident) => {
+        if let Some(base) = $base {
+            $devices.push(Box::new(<$device>::new(base)));
+            info!("{} enabled at {:#x}", stringify!($device), base);
         }
     };
 }
+
+/// A range of addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub base: u64,
+    pub size: u64,
+}
+
+impl Region {
+    pub fn end(&self) -> u64 {
+        self.base + self.size
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vga {
+    /// address of the control registers
+    pub ctl: u64,
+    /// address of the frame buffer
+    pub base: u64,
+    pub width: u32,
+    pub height: u32,
+    /// file each synced frame is saved to, PPM or PNG by its extension, a
+    /// `{}` in it standing for the number of the frame
+    pub dump: Option<PathBuf>,
+    /// save one frame out of this many
+    pub dump_every: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Audio {
+    /// address of the registers
+    pub base: u64,
+    /// address of the stream buffer
+    pub sbuf: u64,
+    pub sbuf_size: u32,
+    /// WAV file the samples played are written to
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disk {
+    pub base: u64,
+    /// file holding the sectors of the disk
+    pub image: PathBuf,
+    pub mode: ioe::DiskMode,
+}
+
+/// Devices a machine is built with, by base address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Devices {
+    pub serial: Option<u64>,
+    pub timer: Option<u64>,
+    pub keyboard: Option<u64>,
+    pub vga: Option<Vga>,
+    pub audio: Option<Audio>,
+    /// the disk, which needs an image and is never there by default
+    pub disk: Option<Disk>,
+}
+
+const VGA: Vga = Vga {
+    ctl: ioe::VGACTL_ADDR,
+    base: ioe::VGA_ADDR,
+    width: VGA_WIDTH,
+    height: VGA_HEIGHT,
+    dump: None,
+    dump_every: 1,
+};
+
+const AUDIO: Audio = Audio {
+    base: ioe::AUDIO_ADDR,
+    sbuf: ioe::AUDIO_SBUF_ADDR,
+    sbuf_size: 0x10000,
+    output: None,
+};
+
+impl Default for Devices {
+    fn default() -> Self {
+        Devices {
+            serial: ENABLE_SERIAL.then_some(ioe::SERIAL_PORT),
+            timer: ENABLE_TIMER.then_some(ioe::TIMER_ADDR),
+            keyboard: ENABLE_KBD.then_some(ioe::KBD_ADDR),
+            vga: ENABLE_VGA.then_some(VGA),
+            audio: ENABLE_AUDIO.then_some(AUDIO),
+            disk: None,
+        }
+    }
+}
+
+impl Devices {
+    fn none() -> Devices {
+        Devices {
+            serial: None,
+            timer: None,
+            keyboard: None,
+            vga: None,
+            audio: None,
+            disk: None,
+        }
+    }
+
+    /// Parse a comma separated list of device names, or `none`, each device
+    /// being at its default address.
+    pub fn parse(list: &str) -> Result<Devices, RError> {
+        let mut devices = Devices::none();
+        for name in list.split(',').map(str::trim) {
+            match name {
+                "serial" => devices.serial = Some(ioe::SERIAL_PORT),
+                "timer" => devices.timer = Some(ioe::TIMER_ADDR),
+                "keyboard" | "kbd" => devices.keyboard = Some(ioe::KBD_ADDR),
+                "vga" => devices.vga = Some(VGA),
+                "audio" => devices.audio = Some(AUDIO),
+                "disk" => {
+                    return Err(RError::Other(
+                        "the disk needs an image, given with --disk or a [disk] table".to_string(),
+                    ))
+                }
+                "none" | "" => (),
+                name => {
+                    return Err(RError::Other(format!(
+                        "unknown device {}, expected serial, timer, keyboard, vga or audio",
+                        name
+                    )))
+                }
+            }
+        }
+        Ok(devices)
+    }
+
+    /// The addresses each device answers, by device name.
+    pub fn regions(&self) -> Vec<(&'static str, Region)> {
+        let region = |base, size| Region { base, size };
+        let mut regions = vec![];
+        if let Some(base) = self.serial {
+            regions.push(("serial", region(base, 4)));
+        }
+        if let Some(base) = self.timer {
+            regions.push(("timer", region(base, 8)));
+        }
+        if let Some(base) = self.keyboard {
+            regions.push(("keyboard", region(base, 8)));
+        }
+        if let Some(vga) = &self.vga {
+            let size = vga.width as u64 * vga.height as u64 * 4;
+            regions.push(("vga", region(vga.base, size)));
+            regions.push(("vgactl", region(vga.ctl, 8)));
+        }
+        if let Some(audio) = &self.audio {
+            regions.push(("audio", region(audio.base, ioe::audio::REGISTERS)));
+            regions.push(("sbuf", region(audio.sbuf, audio.sbuf_size as u64)));
+        }
+        if let Some(disk) = &self.disk {
+            let size = ioe::disk::WINDOW + ioe::disk::WINDOW_SIZE as u64;
+            regions.push(("disk", region(disk.base, size)));
+        }
+        regions
+    }
+}
+
+/// Extensions of the hart to the RV32I base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Isa {
+    pub zicsr: bool,
+}
+
+impl Default for Isa {
+    fn default() -> Self {
+        Isa { zicsr: true }
+    }
+}
+
+impl Isa {
+    /// Parse an ISA string such as `rv32i_zicsr`, failing on extensions
+    /// remu does not implement.
+    pub fn parse(isa: &str) -> Result<Isa, RError> {
+        let lower = isa.to_ascii_lowercase();
+        let rest = lower
+            .strip_prefix("rv32i")
+            .ok_or_else(|| RError::Other(format!("ISA {} is not based on rv32i", isa)))?;
+        let unsupported =
+            |name: &str| RError::Other(format!("extension {} of {} is not supported", name, isa));
+        let mut names = rest.split('_');
+        // single letter extensions come first, the others after underscores
+        if let Some(letter) = names.next().unwrap().chars().next() {
+            return Err(unsupported(&letter.to_string()));
+        }
+        let mut extensions = Isa { zicsr: false };
+        for name in names {
+            match name {
+                "zicsr" => extensions.zicsr = true,
+                "" => (),
+                name => return Err(unsupported(name)),
+            }
+        }
+        Ok(extensions)
+    }
+}
+
+impl std::fmt::Display for Isa {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rv32i")?;
+        if self.zicsr {
+            write!(f, "_zicsr")?;
+        }
+        Ok(())
+    }
+}
+
+/// How the hart starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Boot {
+    pub mode: PrivilegeMode,
+    /// pc to start at instead of the entry of the image
+    pub entry: Option<u32>,
+    /// where a raw image is loaded, images being ELF files without it
+    pub load_addr: Option<u32>,
+    /// whether to pass the software a device tree
+    pub dtb: bool,
+}
+
+impl Default for Boot {
+    fn default() -> Self {
+        Boot {
+            mode: PrivilegeMode::Supervisor,
+            entry: None,
+            load_addr: None,
+            dtb: true,
+        }
+    }
+}
+
+/// Settings of a machine that may change from one run to the next.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub ram: Vec<Region>,
+    pub devices: Devices,
+    /// file read by the serial port instead of the terminal
+    pub serial_in: Option<PathBuf>,
+    pub isa: Isa,
+    pub boot: Boot,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            ram: vec![Region {
+                base: 0,
+                size: MEM_SIZE,
+            }],
+            devices: Devices::default(),
+            serial_in: None,
+            isa: Isa::default(),
+            boot: Boot::default(),
+        }
+    }
+}
+
+fn address(table: &Table, key: &str) -> Result<Option<u32>, String> {
+    match table.integer(key)? {
+        Some(addr) => match u32::try_from(addr) {
+            Ok(addr) => Ok(Some(addr)),
+            Err(_) => Err(table.error(format!("{} {:#x} is not a 32-bit address", key, addr))),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The base of the device of `table`, unless it is disabled.
+fn device_base(table: &Table, keys: &[&str], default: u64) -> Result<Option<u64>, String> {
+    table.check_keys(keys)?;
+    if table.boolean("enabled")? == Some(false) {
+        return Ok(None);
+    }
+    Ok(Some(table.integer("base")?.unwrap_or(default)))
+}
+
+impl Settings {
+    /// Read the machine description at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Settings, RError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| RError::IOError(format!("{}: {}", path.display(), e)))?;
+        Settings::parse(&text).map_err(|e| match e {
+            RError::Other(message) => RError::Other(format!("{}: {}", path.display(), message)),
+            e => e,
+        })
+    }
+
+    /// Parse a machine description. Devices without a table are left out,
+    /// those with one are at their default address unless given a `base`.
+    pub fn parse(text: &str) -> Result<Settings, RError> {
+        let settings = Settings::from_tables(&config::parse(text).map_err(RError::Other)?)
+            .map_err(RError::Other)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    fn from_tables(tables: &[Table]) -> Result<Settings, String> {
+        let mut settings = Settings {
+            ram: vec![],
+            devices: Devices::none(),
+            ..Settings::default()
+        };
+        for table in tables {
+            match table.name.as_str() {
+                "" => {
+                    table.check_keys(&["isa"])?;
+                    if let Some(isa) = table.string("isa")? {
+                        settings.isa = Isa::parse(isa).map_err(|e| e.to_string())?;
+                    }
+                }
+                "ram" => {
+                    table.check_keys(&["base", "size"])?;
+                    let size = table.size("size")?;
+                    settings.ram.push(Region {
+                        base: table.integer("base")?.unwrap_or(0),
+                        size: size.ok_or_else(|| table.error("needs a size".to_string()))?,
+                    });
+                }
+                "serial" => {
+                    let keys = ["enabled", "base", "input"];
+                    settings.devices.serial = device_base(table, &keys, ioe::SERIAL_PORT)?;
+                    settings.serial_in = table.string("input")?.map(PathBuf::from);
+                }
+                "timer" => {
+                    let keys = ["enabled", "base"];
+                    settings.devices.timer = device_base(table, &keys, ioe::TIMER_ADDR)?;
+                }
+                "keyboard" => {
+                    let keys = ["enabled", "base"];
+                    settings.devices.keyboard = device_base(table, &keys, ioe::KBD_ADDR)?;
+                }
+                "vga" => {
+                    let keys = [
+                        "enabled",
+                        "ctl",
+                        "base",
+                        "width",
+                        "height",
+                        "dump",
+                        "dump_every",
+                    ];
+                    let dimension = |key, default| match table.integer(key)? {
+                        Some(value) => u32::try_from(value)
+                            .map_err(|_| table.error(format!("{} is too large", key))),
+                        None => Ok(default),
+                    };
+                    settings.devices.vga = match device_base(table, &keys, VGA.base)? {
+                        Some(base) => Some(Vga {
+                            ctl: table.integer("ctl")?.unwrap_or(VGA.ctl),
+                            base,
+                            width: dimension("width", VGA.width)?,
+                            height: dimension("height", VGA.height)?,
+                            dump: table.string("dump")?.map(PathBuf::from),
+                            dump_every: match table.integer("dump_every")? {
+                                Some(0) => {
+                                    return Err(table.error("dump_every must not be 0".to_string()))
+                                }
+                                every => every.unwrap_or(1),
+                            },
+                        }),
+                        None => None,
+                    };
+                }
+                "audio" => {
+                    let keys = ["enabled", "base", "sbuf", "sbuf_size", "output"];
+                    settings.devices.audio = match device_base(table, &keys, AUDIO.base)? {
+                        Some(base) => Some(Audio {
+                            base,
+                            sbuf: table.integer("sbuf")?.unwrap_or(AUDIO.sbuf),
+                            sbuf_size: match table.size("sbuf_size")? {
+                                Some(size) => u32::try_from(size).map_err(|_| {
+                                    table.error("sbuf_size is too large".to_string())
+                                })?,
+                                None => AUDIO.sbuf_size,
+                            },
+                            output: table.string("output")?.map(PathBuf::from),
+                        }),
+                        None => None,
+                    };
+                }
+                "disk" => {
+                    let keys = ["enabled", "base", "image", "mode"];
+                    settings.devices.disk = match device_base(table, &keys, ioe::DISK_ADDR)? {
+                        Some(base) => Some(Disk {
+                            base,
+                            image: table
+                                .string("image")?
+                                .map(PathBuf::from)
+                                .ok_or_else(|| table.error("needs an image".to_string()))?,
+                            mode: match table.string("mode")? {
+                                Some(mode) => mode
+                                    .parse()
+                                    .map_err(|e: RError| table.error(e.to_string()))?,
+                                None => ioe::DiskMode::default(),
+                            },
+                        }),
+                        None => None,
+                    };
+                }
+                "boot" => {
+                    table.check_keys(&["mode", "entry", "load_addr", "dtb"])?;
+                    settings.boot.mode = match table.string("mode")? {
+                        Some("machine") => PrivilegeMode::Machine,
+                        Some("supervisor") | None => PrivilegeMode::Supervisor,
+                        Some("user") => PrivilegeMode::User,
+                        Some(mode) => {
+                            return Err(table.error(format!(
+                                "unknown mode {}, expected machine, supervisor or user",
+                                mode
+                            )))
+                        }
+                    };
+                    settings.boot.entry = address(table, "entry")?;
+                    settings.boot.load_addr = address(table, "load_addr")?;
+                    settings.boot.dtb = table.boolean("dtb")?.unwrap_or(true);
+                }
+                name => return Err(format!("line {}: unknown table [{}]", table.line, name)),
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Check RAM regions and devices fit in the address space without
+    /// overlapping.
+    pub fn validate(&self) -> Result<(), RError> {
+        let error = |message: String| Err(RError::Other(message));
+        if self.ram.is_empty() {
+            return error("no RAM region".to_string());
+        }
+        let mut regions: Vec<_> = self.ram.iter().map(|region| ("ram", *region)).collect();
+        regions.extend(self.devices.regions());
+        for (name, region) in regions.iter() {
+            if region.size == 0 {
+                return error(format!("{} at {:#x} is empty", name, region.base));
+            }
+            if region.end() > ADDRESS_END {
+                return error(format!(
+                    "{} [{:#x}, {:#x}) is beyond the 32-bit address space",
+                    name,
+                    region.base,
+                    region.end()
+                ));
+            }
+        }
+        // reverse execution saves RAM by page
+        let page = crate::isas::riscv::mem::PAGE_SIZE as u64;
+        for region in self.ram.iter() {
+            if region.base % page != 0 || region.size % page != 0 {
+                return error(format!(
+                    "ram [{:#x}, {:#x}) is not aligned to pages of {:#x} bytes",
+                    region.base,
+                    region.end(),
+                    page
+                ));
+            }
+        }
+        regions.sort_by_key(|(_, region)| region.base);
+        for pair in regions.windows(2) {
+            let ((name, a), (other, b)) = (pair[0], pair[1]);
+            if b.base < a.end() {
+                return error(format!(
+                    "{} [{:#x}, {:#x}) overlaps {} [{:#x}, {:#x})",
+                    name,
+                    a.base,
+                    a.end(),
+                    other,
+                    b.base,
+                    b.end()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_devices() {
+        let devices = Devices::parse("serial, kbd").unwrap();
+        assert!(devices.serial.is_some() && devices.keyboard.is_some());
+        assert!(devices.timer.is_none() && devices.vga.is_none());
+        assert_eq!(Devices::parse("none").unwrap(), Devices::parse("").unwrap());
+        assert!(Devices::parse("vga,audio").unwrap().audio.is_some());
+        assert!(Devices::parse("serial,disk").is_err());
+    }
+
+    #[test]
+    fn test_isa() {
+        assert_eq!(Isa::parse("rv32i").unwrap(), Isa { zicsr: false });
+        assert_eq!(Isa::parse("RV32I_Zicsr").unwrap(), Isa { zicsr: true });
+        assert_eq!(Isa::default().to_string(), "rv32i_zicsr");
+        assert!(Isa::parse("rv32im").is_err());
+        assert!(Isa::parse("rv32i_zifencei").is_err());
+        assert!(Isa::parse("rv64i").is_err());
+    }
+
+    #[test]
+    fn test_settings() {
+        assert!(Settings::default().validate().is_ok());
+        let default = Settings::parse(include_str!("../machines/default.toml")).unwrap();
+        assert_eq!(default, Settings::default());
+
+        let settings = Settings::parse(
+            "isa = \"rv32i_zicsr\"\n\
+             [[ram]]\n\
+             base = 0x80000000\n\
+             size = \"128M\"\n\
+             [serial]\n\
+             base = 0x10000000\n\
+             [vga]\n\
+             width = 640\n\
+             height = 480\n\
+             [audio]\n\
+             sbuf_size = \"4K\"\n\
+             output = \"tone.wav\"\n\
+             [disk]\n\
+             image = \"disk.img\"\n\
+             mode = \"cow\"\n\
+             [boot]\n\
+             mode = \"machine\"\n\
+             entry = 0x80000000\n",
+        )
+        .unwrap();
+        assert_eq!(
+            settings.ram,
+            vec![Region {
+                base: 0x80000000,
+                size: 128 << 20
+            }]
+        );
+        assert_eq!(settings.devices.serial, Some(0x10000000));
+        assert_eq!(settings.devices.timer, None);
+        assert_eq!(settings.devices.vga.as_ref().unwrap().width, 640);
+        let audio = settings.devices.audio.as_ref().unwrap();
+        assert_eq!((audio.base, audio.sbuf_size), (ioe::AUDIO_ADDR, 4096));
+        assert_eq!(audio.output, Some("tone.wav".into()));
+        let disk = settings.devices.disk.as_ref().unwrap();
+        assert_eq!(
+            (disk.base, disk.mode),
+            (ioe::DISK_ADDR, ioe::DiskMode::CopyOnWrite)
+        );
+        assert_eq!(settings.boot.mode, PrivilegeMode::Machine);
+        assert_eq!(settings.boot.entry, Some(0x80000000));
+
+        let message = |text| match Settings::parse(text) {
+            Err(RError::Other(message)) => message,
+            result => panic!("unexpected {:?}", result),
+        };
+        assert_eq!(
+            message("[[ram]]\nsize = 0x2000\n[[ram]]\nbase = 0x1000\nsize = 0x1000"),
+            "ram [0x0, 0x2000) overlaps ram [0x1000, 0x2000)"
+        );
+        assert_eq!(
+            message("[[ram]]\nsize = 0xa0001000\n[keyboard]"),
+            "ram [0x0, 0xa0001000) overlaps keyboard [0xa0000060, 0xa0000068)"
+        );
+        assert_eq!(message("[serial]"), "no RAM region");
+        assert_eq!(
+            message("[[ram]]\nsize = 0x1000\n[uart]"),
+            "line 3: unknown table [uart]"
+        );
+        assert_eq!(
+            message("[[ram]]\nsize = 0x1000\nbase = 0x800"),
+            "ram [0x800, 0x1800) is not aligned to pages of 0x1000 bytes"
+        );
+        assert_eq!(
+            message("[[ram]]\nsize = 0x1000\n[disk]\nmode = \"ro\""),
+            "line 3: [disk] needs an image"
+        );
+        assert!(Settings::parse("[[ram]]\nsize = 8G").is_err());
+        assert!(Settings::parse("[[ram]]\nsize = 1\n[serial]\nbaud = 9600").is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Traces of a running program, written to the log: executed instructions
+//! (itrace), memory accesses (mtrace) and function calls and returns
+//! (ftrace).
+
+use crate::error::RError;
+use crate::exes::{SymbolEntry, ELF};
+use crate::isas::{AccessKind, ISA};
+use colored::ColoredString;
+
+/// Which traces to write.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Trace {
+    pub itrace: bool,
+    pub mtrace: bool,
+    pub ftrace: bool,
+}
+
+impl Trace {
+    /// Parse a comma separated list such as `itrace,ftrace`.
+    pub fn parse(list: &str) -> Result<Trace, RError> {
+        let mut trace = Trace::default();
+        for name in list.split(',').map(str::trim) {
+            match name {
+                "itrace" => trace.itrace = true,
+                "mtrace" => trace.mtrace = true,
+                "ftrace" => trace.ftrace = true,
+                name => {
+                    return Err(RError::Other(format!(
+                        "unknown trace {}, expected itrace, mtrace or ftrace",
+                        name
+                    )))
+                }
+            }
+        }
+        Ok(trace)
+    }
+
+    pub fn any(&self) -> bool {
+        self.itrace || self.mtrace || self.ftrace
+    }
+}
+
+pub struct Tracer {
+    trace: Trace,
+    /// functions of the program, sorted by address
+    functions: Vec<SymbolEntry>,
+    /// return addresses of the calls being traced
+    calls: Vec<u32>,
+}
+
+fn log(message: std::fmt::Arguments) {
+    crate::util::log(&ColoredString::default(), message);
+}
+
+impl Tracer {
+    /// A tracer naming functions after the symbols of `elf`.
+    pub fn new(trace: Trace, elf: Option<&ELF>) -> Tracer {
+        let mut functions = elf.map(ELF::symbol_entries).unwrap_or_default();
+        functions.retain(|symbol| symbol.is_func);
+        functions.sort_by_key(|symbol| symbol.value);
+        Tracer {
+            trace,
+            functions,
+            calls: vec![],
+        }
+    }
+
+    /// the function `addr` is in, or the address itself
+    fn function(&self, addr: u32) -> String {
+        let addr = addr as u64;
+        let index = self
+            .functions
+            .partition_point(|symbol| symbol.value <= addr);
+        match index.checked_sub(1).map(|i| &self.functions[i]) {
+            Some(symbol) if symbol.size == 0 || addr < symbol.value + symbol.size => {
+                symbol.name.clone()
+            }
+            _ => format!("{:#x}", addr),
+        }
+    }
+
+    /// Step `cpu`, tracing the instruction.
+    pub fn step(&mut self, cpu: &mut impl ISA) -> Result<(), RError> {
+        let pc = cpu.pc();
+        if self.trace.itrace {
+            let code = cpu.peek_mem(pc, 4).unwrap_or_default();
+            let inst = cpu.disassemble(pc).unwrap_or("<???>".to_string());
+            log(format_args!("{:#010x}: {:08x}  {}", pc, code, inst));
+        }
+        let call = self.trace.ftrace && cpu.is_call(pc);
+        cpu.trace_accesses(self.trace.mtrace);
+        let result = cpu.step();
+        for access in cpu.take_accesses() {
+            let kind = match access.kind {
+                AccessKind::Read => "read ",
+                AccessKind::Write => "write",
+            };
+            log(format_args!(
+                "{:#010x}: {} {:#010x} [{}]",
+                pc, kind, access.addr, access.bytes
+            ));
+        }
+        if self.trace.ftrace {
+            let next = cpu.pc();
+            let indent = "  ".repeat(self.calls.len());
+            if call {
+                log(format_args!(
+                    "{:#010x}: {}call {} [{:#x}]",
+                    pc,
+                    indent,
+                    self.function(next),
+                    next
+                ));
+                self.calls.push(pc.wrapping_add(4));
+            } else if let Some(depth) = self.calls.iter().rposition(|ret| *ret == next) {
+                // returns skipping frames are those of tail calls
+                self.calls.truncate(depth);
+                let indent = "  ".repeat(depth);
+                log(format_args!(
+                    "{:#010x}: {}ret  {}",
+                    pc,
+                    indent,
+                    self.function(pc)
+                ));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace() {
+        let trace = Trace::parse("itrace, ftrace").unwrap();
+        assert!(trace.itrace && trace.ftrace && !trace.mtrace);
+        assert!(Trace::parse("xtrace").is_err());
+
+        let mut tracer = Tracer::new(Trace::default(), None);
+        tracer.functions = vec![
+            SymbolEntry {
+                name: "main".to_string(),
+                value: 0x100,
+                size: 0x20,
+                is_func: true,
+            },
+            SymbolEntry {
+                name: "_start".to_string(),
+                value: 0x200,
+                size: 0,
+                is_func: true,
+            },
+        ];
+        assert_eq!(tracer.function(0x110), "main");
+        assert_eq!(tracer.function(0x120), "0x120");
+        assert_eq!(tracer.function(0x280), "_start");
+        assert_eq!(tracer.function(0x80), "0x80");
+    }
+}
@@ -1,4 +1,29 @@
+use crate::error::RError;
+use colored::ColoredString;
+use std::fs::File;
+use std::io::Write;
 use std::num::ParseIntError;
+use std::sync::Mutex;
+
+/// file the log goes to instead of stdout
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Write the log, and traces, to the file at `path` from now on.
+pub fn set_log_file(path: &str) -> Result<(), RError> {
+    let file = File::create(path).map_err(|e| RError::IOError(format!("{}: {}", path, e)))?;
+    *LOG_FILE.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+/// Log `message` after `level`, uncolored when logging to a file.
+pub fn log(level: &ColoredString, message: std::fmt::Arguments) {
+    match LOG_FILE.lock().unwrap().as_mut() {
+        Some(file) => {
+            let _ = writeln!(file, "{}{}", &**level, message);
+        }
+        None => println!("{}{}", level, message),
+    }
+}
 
 #[macro_export]
 macro_rules! function {
@@ -16,9 +41,10 @@ macro_rules! function {
 macro_rules! info {
     ($($arg:tt)*) => ({
         use colored::Colorize;
-        print!("{}", "[INFO] ".blue().bold());
-        print!("[{}:{}] ", file!(), line!());
-        println!($($arg)*);
+        $crate::util::log(
+            &"[INFO] ".blue().bold(),
+            format_args!("[{}:{}] {}", file!(), line!(), format_args!($($arg)*)),
+        );
     })
 }
 
@@ -26,10 +52,11 @@ macro_rules! info {
 macro_rules! warn {
     ($($arg:tt)*) => ({
         use colored::Colorize;
-        print!("{}", "[WARN] ".yellow().bold());
-        print!("{} [{}:{}:{}] ",
-        $crate::function!(), file!(), line!(), column!());
-        println!($($arg)*);
+        $crate::util::log(
+            &"[WARN] ".yellow().bold(),
+            format_args!("{} [{}:{}:{}] {}",
+            $crate::function!(), file!(), line!(), column!(), format_args!($($arg)*)),
+        );
     })
 }
 
@@ -37,10 +64,11 @@ macro_rules! warn {
 macro_rules! error {
     ($($arg:tt)*) => ({
         use colored::Colorize;
-        print!("{}", "[ERROR] ".red().bold());
-        print!("{} [{}:{}:{}] ",
-        $crate::function!(), file!(), line!(), column!());
-        println!($($arg)*);
+        $crate::util::log(
+            &"[ERROR] ".red().bold(),
+            format_args!("{} [{}:{}:{}] {}",
+            $crate::function!(), file!(), line!(), column!(), format_args!($($arg)*)),
+        );
     })
 }
 
@@ -48,10 +76,11 @@ macro_rules! error {
 macro_rules! debug {
     ($($arg:tt)*) => ({
         use colored::Colorize;
-        print!("{}", "[DEBUG] ".bold());
-        print!("{} [{}:{}:{}] ",
-        $crate::function!(), file!(), line!(), column!());
-        println!($($arg)*);
+        $crate::util::log(
+            &"[DEBUG] ".bold(),
+            format_args!("{} [{}:{}:{}] {}",
+            $crate::function!(), file!(), line!(), column!(), format_args!($($arg)*)),
+        );
     })
 }
 
@@ -59,10 +88,11 @@ macro_rules! debug {
 macro_rules! fatal {
     ($($arg:tt)*) => ({
         use colored::Colorize;
-        print!("{}", "[FATAL]: ".red().bold());
-        print!("{} [{}:{}:{}] ",
-        $crate::function!(), file!(), line!(), column!());
-        println!($($arg)*);
+        $crate::util::log(
+            &"[FATAL]: ".red().bold(),
+            format_args!("{} [{}:{}:{}] {}",
+            $crate::function!(), file!(), line!(), column!(), format_args!($($arg)*)),
+        );
     })
 }
 
@@ -40,8 +40,12 @@ pub fn on_interrupt(event: Event, context: &mut Context) {
     }
 }
 
+/// Entered with the hart id in a0 and the device tree in a1.
 #[no_mangle]
-pub extern "C" fn _start() -> ! {
+pub extern "C" fn _start(_hartid: usize, dtb: usize) -> ! {
+    if let Some(fdt) = unsafe { fdt::Fdt::from_ptr(dtb) } {
+        io::init(&fdt);
+    }
     cte::init(on_interrupt);
     let fs = filesystem::FileSystem::new();
     unsafe {